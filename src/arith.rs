@@ -0,0 +1,523 @@
+//! arith.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Summary
+//! The arith module evaluates the integer expressions of `$(( ))`, `(( ))`
+//! and `let`. Expressions use C's operators and precedence, with `**` for
+//! powers:
+//!
+//! ```text
+//! ,                                  (lowest)
+//! = += -= *= /= %= <<= >>= &= ^= |=
+//! ?:
+//! ||
+//! &&
+//! |
+//! ^
+//! &
+//! == !=
+//! < <= > >=
+//! << >>
+//! + -
+//! * / %
+//! **
+//! - + ! ~ ++ -- (prefix), ++ -- (postfix)  (highest)
+//! ```
+//!
+//! Numbers can be decimal, octal (`017`), hexadecimal (`0x1f`) or in any
+//! base from 2 to 64 (`2#101`). A name refers to a shell variable: unset
+//! and empty variables count as 0, and a variable holding an expression is
+//! evaluated in turn. Arithmetic is done on 64 bit integers and wraps
+//! around on overflow.
+//!
+//! The parts of an expression that aren't evaluated, like the right side of
+//! `0 && x = 1`, are still parsed but don't assign anything
+
+use std::fmt;
+
+use vars::Variables;
+
+// How deeply variables can refer to other variables' expressions
+const MAX_DEPTH: usize = 32;
+
+// Operators, longest first so that `<<=` isn't read as `<` `<=`
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "++", "--", "+=", "-=", "*=", "/=", "%=",
+    "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~", "?", ":", "=", ",", "(", ")",
+];
+
+// Operators that assign to the variable on their left
+const ASSIGNMENT_OPS: &[&str] = &["=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|="];
+
+/// An error found while evaluating an arithmetic expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArithError {
+    /// Something unexpected, at the given token (empty at the end of the
+    /// expression)
+    Syntax(String),
+
+    /// A number that isn't valid in its base
+    InvalidNumber(String),
+
+    /// Division or remainder by zero
+    DivisionByZero,
+
+    /// A power with a negative exponent
+    NegativeExponent,
+
+    /// Variables that refer to each other's expressions too deeply
+    TooDeep,
+}
+
+impl fmt::Display for ArithError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArithError::Syntax(ref token) if token.is_empty() => write!(f, "syntax error: operand expected"),
+            ArithError::Syntax(ref token) => write!(f, "syntax error in expression (error token is \"{}\")", token),
+            ArithError::InvalidNumber(ref number) => write!(f, "{}: invalid number", number),
+            ArithError::DivisionByZero => write!(f, "division by 0"),
+            ArithError::NegativeExponent => write!(f, "exponent less than 0"),
+            ArithError::TooDeep => write!(f, "expression recursion level exceeded"),
+        }
+    }
+}
+
+/// Evaluates an expression, reading and assigning variables in `vars`. An
+/// empty expression is 0
+pub fn eval(expr: &str, vars: &mut Variables) -> Result<i64, ArithError> {
+    eval_at_depth(expr, vars, 0)
+}
+
+// Evaluates an expression that is `depth` variables deep
+fn eval_at_depth(expr: &str, vars: &mut Variables, depth: usize) -> Result<i64, ArithError> {
+    if depth > MAX_DEPTH {
+        return Err(ArithError::TooDeep);
+    }
+
+    let tokens = tokenize(expr)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut evaluator = Evaluator {
+        tokens,
+        pos: 0,
+        vars,
+        depth,
+        skip: false,
+    };
+    let value = evaluator.comma()?;
+
+    match evaluator.tokens.get(evaluator.pos) {
+        None => Ok(value),
+        Some(token) => Err(ArithError::Syntax(token.to_string())),
+    }
+}
+
+// A piece of an expression
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(ref name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+// Splits an expression into numbers, names and operators
+fn tokenize(expr: &str) -> Result<Vec<Token>, ArithError> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '#' || c == '@' || c == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Number(parse_number(&rest[..len])?));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            len
+        } else {
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    op.len()
+                }
+                None => return Err(ArithError::Syntax(rest.to_string())),
+            }
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+// Parses a number: `base#digits`, `0x` hexadecimal, `0` octal or decimal
+fn parse_number(text: &str) -> Result<i64, ArithError> {
+    let invalid = || ArithError::InvalidNumber(text.to_string());
+
+    let (base, digits) = match text.find('#') {
+        Some(hash) => match text[..hash].parse::<u32>() {
+            Ok(base) if (2..=64).contains(&base) => (base, &text[hash + 1..]),
+            _ => return Err(invalid()),
+        },
+        None if text.starts_with("0x") || text.starts_with("0X") => (16, &text[2..]),
+        None if text.starts_with('0') && text.len() > 1 => (8, &text[1..]),
+        None => (10, text),
+    };
+
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        // Up to base 36 letters are digits whatever their case, above that
+        // lowercase comes before uppercase, then `@` and `_`
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        value = value.wrapping_mul(i64::from(base)).wrapping_add(i64::from(digit));
+    }
+    Ok(value)
+}
+
+// Evaluates tokens by recursive descent, one function per precedence level
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    vars: &'a mut Variables,
+    depth: usize,
+
+    // Set while parsing a part of the expression that isn't evaluated
+    skip: bool,
+}
+
+impl<'a> Evaluator<'a> {
+    // Consumes the next token if it is the operator `op`
+    fn eat(&mut self, op: &str) -> bool {
+        if self.tokens.get(self.pos) == Some(&Token::Op(Self::intern(op))) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Consumes the next token if it's one of `ops`, returning it
+    fn eat_any(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(&Token::Op(op)) if ops.contains(&op) => {
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    // Returns the `&'static` version of an operator, for comparing tokens
+    fn intern(op: &str) -> &'static str {
+        OPERATORS.iter().find(|known| **known == op).cloned().unwrap_or("")
+    }
+
+    // Returns the error for the next token, which is somewhere it isn't
+    // allowed
+    fn unexpected(&self) -> ArithError {
+        ArithError::Syntax(self.tokens.get(self.pos).map(Token::to_string).unwrap_or_default())
+    }
+
+    // `expr , expr`, whose value is the last one
+    fn comma(&mut self) -> Result<i64, ArithError> {
+        let mut value = self.assignment()?;
+        while self.eat(",") {
+            value = self.assignment()?;
+        }
+        Ok(value)
+    }
+
+    // `name = expr` and the other assignment operators, which group right
+    // to left
+    fn assignment(&mut self) -> Result<i64, ArithError> {
+        if let (Some(Token::Name(name)), Some(&Token::Op(op))) = (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            if ASSIGNMENT_OPS.contains(&op) {
+                let name = name.clone();
+                self.pos += 2;
+                let right = self.assignment()?;
+
+                let value = if op == "=" {
+                    right
+                } else {
+                    let left = self.variable(&name)?;
+                    self.apply(&op[..op.len() - 1], left, right)?
+                };
+                self.assign(&name, value);
+                return Ok(value);
+            }
+        }
+        self.conditional()
+    }
+
+    // `cond ? expr : expr`, where only the chosen branch is evaluated
+    fn conditional(&mut self) -> Result<i64, ArithError> {
+        let condition = self.binary(0)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+
+        let skip = self.skip;
+        self.skip = skip || condition == 0;
+        let then = self.comma()?;
+        if !self.eat(":") {
+            return Err(self.unexpected());
+        }
+        self.skip = skip || condition != 0;
+        let otherwise = self.conditional()?;
+        self.skip = skip;
+
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    // The binary operators from `||` down to `*`, which group left to
+    // right. `level` indexes the precedence table, loosest first
+    fn binary(&mut self, level: usize) -> Result<i64, ArithError> {
+        const LEVELS: &[&[&str]] = &[
+            &["||"],
+            &["&&"],
+            &["|"],
+            &["^"],
+            &["&"],
+            &["==", "!="],
+            &["<", "<=", ">", ">="],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+
+        if level == LEVELS.len() {
+            return self.power();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.eat_any(LEVELS[level]) {
+            // The right side of `&&` and `||` is only evaluated if it can
+            // change the result
+            let skip = self.skip;
+            self.skip |= (op == "&&" && left == 0) || (op == "||" && left != 0);
+            let right = self.binary(level + 1)?;
+            self.skip = skip;
+
+            left = self.apply(op, left, right)?;
+        }
+        Ok(left)
+    }
+
+    // `base ** exponent`, which groups right to left
+    fn power(&mut self) -> Result<i64, ArithError> {
+        let base = self.unary()?;
+        if !self.eat("**") {
+            return Ok(base);
+        }
+        let exponent = self.power()?;
+        self.apply("**", base, exponent)
+    }
+
+    // The prefix operators
+    fn unary(&mut self) -> Result<i64, ArithError> {
+        match self.eat_any(&["-", "+", "!", "~", "++", "--"]) {
+            Some("-") => Ok(self.unary()?.wrapping_neg()),
+            Some("+") => self.unary(),
+            Some("!") => Ok((self.unary()? == 0) as i64),
+            Some("~") => Ok(!self.unary()?),
+            Some(op) => {
+                let name = match self.tokens.get(self.pos) {
+                    Some(Token::Name(name)) => name.clone(),
+                    _ => return Err(self.unexpected()),
+                };
+                self.pos += 1;
+                let value = self.variable(&name)?.wrapping_add(if op == "++" { 1 } else { -1 });
+                self.assign(&name, value);
+                Ok(value)
+            }
+            None => self.postfix(),
+        }
+    }
+
+    // Numbers, variables (with `++` or `--` after them) and parentheses
+    fn postfix(&mut self) -> Result<i64, ArithError> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(n)
+            }
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                let value = self.variable(&name)?;
+                if let Some(op) = self.eat_any(&["++", "--"]) {
+                    self.assign(&name, value.wrapping_add(if op == "++" { 1 } else { -1 }));
+                }
+                Ok(value)
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let value = self.comma()?;
+                if !self.eat(")") {
+                    return Err(self.unexpected());
+                }
+                Ok(value)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    // Returns the value of a variable, evaluating it if it holds an
+    // expression
+    fn variable(&mut self, name: &str) -> Result<i64, ArithError> {
+        let value = match self.vars.get(name) {
+            Some(value) => value.to_string_lossy().into_owned(),
+            None => return Ok(0),
+        };
+
+        match value.trim().parse() {
+            Ok(n) => Ok(n),
+            Err(_) if self.skip => Ok(0),
+            Err(_) => eval_at_depth(&value, self.vars, self.depth + 1),
+        }
+    }
+
+    // Sets a variable, unless this part of the expression is skipped
+    fn assign(&mut self, name: &str, value: i64) {
+        if !self.skip {
+            self.vars.set(name, value.to_string().into());
+        }
+    }
+
+    // Applies a binary operator
+    fn apply(&self, op: &str, left: i64, right: i64) -> Result<i64, ArithError> {
+        Ok(match op {
+            "||" => (left != 0 || right != 0) as i64,
+            "&&" => (left != 0 && right != 0) as i64,
+            "|" => left | right,
+            "^" => left ^ right,
+            "&" => left & right,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            "<" => (left < right) as i64,
+            "<=" => (left <= right) as i64,
+            ">" => (left > right) as i64,
+            ">=" => (left >= right) as i64,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => {
+                if self.skip {
+                    0
+                } else {
+                    return Err(ArithError::DivisionByZero);
+                }
+            }
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "**" if right < 0 => return Err(ArithError::NegativeExponent),
+            "**" => left.wrapping_pow(right.min(i64::from(u32::MAX)) as u32),
+            _ => return Err(ArithError::Syntax(op.to_string())),
+        })
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+
+    // Evaluates an expression with no variables
+    fn calc(expr: &str) -> Result<i64, ArithError> {
+        eval(expr, &mut Variables::default())
+    }
+
+    // Tests precedence and grouping
+    #[test]
+    fn test_precedence() {
+        assert_eq!(calc("1 + 2 * 3"), Ok(7));
+        assert_eq!(calc("(1 + 2) * 3"), Ok(9));
+        assert_eq!(calc("10 - 4 - 3"), Ok(3));
+        assert_eq!(calc("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(calc("-2 ** 2"), Ok(4));
+        assert_eq!(calc("1 + 2 == 3 && 4 > 3"), Ok(1));
+        assert_eq!(calc("1 | 6 & 3 ^ 1"), Ok(3));
+        assert_eq!(calc("1 << 4 >> 2"), Ok(4));
+        assert_eq!(calc("!0 + ~0 + -7 % 3"), Ok(-1));
+        assert_eq!(calc("1 ? 2 : 0 ? 3 : 4"), Ok(2));
+        assert_eq!(calc("1, 2, 3"), Ok(3));
+        assert_eq!(calc(""), Ok(0));
+    }
+
+    // Tests the ways numbers can be written
+    #[test]
+    fn test_numbers() {
+        assert_eq!(calc("0x1F + 017 + 2#101 + 36#z + 64#_"), Ok(31 + 15 + 5 + 35 + 63));
+        assert_eq!(calc("9223372036854775807 + 1"), Ok(i64::MIN));
+        assert_eq!(calc("08"), Err(ArithError::InvalidNumber("08".to_string())));
+        assert_eq!(calc("65#1"), Err(ArithError::InvalidNumber("65#1".to_string())));
+    }
+
+    // Tests assignments and increments on variables
+    #[test]
+    fn test_variables() {
+        let mut vars = Variables::default();
+        vars.set("ensh_arith_e", OsString::from("ensh_arith_x * 2"));
+        assert_eq!(eval("ensh_arith_x = 5, ensh_arith_x += 2", &mut vars), Ok(7));
+        assert_eq!(eval("ensh_arith_e + ensh_arith_unset", &mut vars), Ok(14));
+        assert_eq!(eval("ensh_arith_x++ + ++ensh_arith_x", &mut vars), Ok(16));
+        assert_eq!(eval("ensh_arith_a = ensh_arith_b = 3", &mut vars), Ok(3));
+        assert_eq!(vars.get("ensh_arith_a"), Some(OsString::from("3")));
+        assert_eq!(vars.get("ensh_arith_x"), Some(OsString::from("9")));
+
+        vars.set("ensh_arith_loop", OsString::from("ensh_arith_loop + 1"));
+        assert_eq!(eval("ensh_arith_loop", &mut vars), Err(ArithError::TooDeep));
+    }
+
+    // Tests that the parts of an expression that aren't evaluated don't
+    // assign or fail
+    #[test]
+    fn test_short_circuit() {
+        let mut vars = Variables::default();
+        assert_eq!(eval("0 && (ensh_arith_s = 1 / 0)", &mut vars), Ok(0));
+        assert_eq!(eval("1 || ensh_arith_s++", &mut vars), Ok(1));
+        assert_eq!(eval("0 ? ensh_arith_s = 1 : 2", &mut vars), Ok(2));
+        assert_eq!(vars.get("ensh_arith_s"), None);
+    }
+
+    // Tests the errors
+    #[test]
+    fn test_errors() {
+        assert_eq!(calc("1 / 0"), Err(ArithError::DivisionByZero));
+        assert_eq!(calc("2 ** -1"), Err(ArithError::NegativeExponent));
+        assert_eq!(calc("1 +"), Err(ArithError::Syntax(String::new())));
+        assert_eq!(calc("1 2"), Err(ArithError::Syntax("2".to_string())));
+        assert_eq!(calc("(1"), Err(ArithError::Syntax(String::new())));
+        assert_eq!(calc("1 $ 2"), Err(ArithError::Syntax("$ 2".to_string())));
+        assert_eq!(calc("++1"), Err(ArithError::Syntax("1".to_string())));
+    }
+}
@@ -1,87 +1,1393 @@
-/// # Command Dispatch module
-///
-/// ## Author
-/// Afnan Enayet
-///
-/// # Summary
-///
-/// The command dispatch module contains internal commands/utilities for the
-/// shell. This includes utilities like `cd`. These commands perform an action
-/// on a Shell object. It will determine whether a function is an internal shell
-/// function, or a binary that needs to be executed through another process.
-///
-
-use shell::Shell;
-use parser::norm_abs_path;
+//! # Command Dispatch module
+//!
+//! ## Author
+//! Afnan Enayet
+//!
+//! # Summary
+//!
+//! The command dispatch module contains internal commands/utilities for the
+//! shell. This includes utilities like `cd`, `pushd`, `j`, `reload` and
+//! `alias`. These
+//! commands perform an action on a Shell object. It will determine whether a command is a
+//! user-defined function, an internal shell function, or a binary that needs to be executed
+//! through another process.
+//!
+
+use shell::{Shell, Unwind};
+use parser::{norm_abs_path, condense_path, logical_path, tilde_home, ParseError};
+use parser::ast;
+use parser::expand::{expand_string, split_line};
+use parser::lexer::{tokenize, quote};
+use parser::alias::{expand_aliases, valid_alias_name};
+use parser::syntax::parse;
+use interface::{print_out, print_err};
+use consts::NOT_FOUND_HANDLER;
+use arith;
+use shell;
+use cond;
+use exec;
+use frecency;
+use suggest::suggestions;
+use sys::{self, Disposition};
+use vars::{split_subscript, valid_name, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path, PathBuf};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::process::{Command, Stdio};
-use std::str::from_utf8;
+use std::str;
+use std::time::{Duration, Instant};
+
+// The names of the builtins, for suggesting commands
+const BUILTINS: &[&str] = &[
+    "cd", "pwd", "pushd", "popd", "dirs", "j", "reload", "alias", "unalias", "return", "local", "declare", "break",
+    "continue", "true", "false", ":", "test", "[", "let", "set", "trap", "read", "exit",
+];
+
+// How deeply functions can call each other before the shell gives up,
+// which keeps runaway recursion from overflowing the stack
+const MAX_FUNCTION_DEPTH: usize = 100;
+
+// The status of a command that couldn't be found
+const NOT_FOUND_STATUS: i32 = 127;
 
-// Dispatches a command based on some sanitized input string (ex: "cd ~")
+// The status of a command that was found but couldn't be run
+const NOT_EXECUTABLE_STATUS: i32 = 126;
+
+// The status of `read` when it runs out of time, as if killed by `SIGALRM`
+const TIMEOUT_STATUS: i32 = 142;
+
+// Dispatches a command based on some sanitized input string (ex: "cd ~").
+// Returns whether the command succeeded
 pub fn dispatch(shell: &mut Shell, cmd: &str) -> bool {
-    // Tokenize command, splitting into words and spaces
-    let tok_cmd = cmd.split(" ").collect::<Vec<_>>();
+    match parse_input(shell, cmd) {
+        Ok(list) => exec::run_list(&list, shell) == 0,
+        Err(e) => {
+            print_err(&format!("ensh: {}", e));
+            shell.set_status(2);
+            false
+        }
+    }
+}
+
+/// Returns whether a command stops partway through, like an `if` without
+/// its `fi`, so the rest of it has to be read before it can run
+pub fn is_incomplete(shell: &Shell, cmd: &str) -> bool {
+    match parse_input(shell, cmd) {
+        Ok(_) => false,
+        Err(e) => e.is_incomplete(),
+    }
+}
+
+/// Tokenizes a command, expands any aliases and parses it
+pub fn parse_input(shell: &Shell, cmd: &str) -> Result<ast::List, ParseError> {
+    tokenize(cmd)
+        .and_then(|tokens| expand_aliases(tokens, shell.aliases()))
+        .and_then(parse)
+}
+
+/// Runs a command that has been expanded into its arguments. Functions come
+/// first, then builtins, then binaries in the shell's paths. Returns the
+/// command's exit status
+pub fn run(args: &[OsString], shell: &mut Shell) -> i32 {
+    if let Some(body) = args[0].to_str().and_then(|name| shell.function(name)) {
+        return call_function(&body, args, shell);
+    }
+
+    // Execute internal function if necessary. Builtins that only deal in
+    // text get their arguments as strings, the others keep them as they are
+    let success = match args[0].to_str() {
+        Some("cd") => cd(&args[1..], shell),
+        Some("pwd") => pwd(&args[1..], shell),
+        Some("pushd") => pushd(&args[1..], shell),
+        Some("popd") => popd(&text_args(&args[1..]), shell),
+        Some("dirs") => dirs(&text_args(&args[1..]), shell),
+        Some("j") => jump(&text_args(&args[1..]), shell),
+        Some("reload") => reload(shell),
+        Some("alias") => alias(&text_args(&args[1..]), shell),
+        Some("unalias") => unalias(&text_args(&args[1..]), shell),
+        Some("return") => return return_from(&text_args(&args[1..]), shell),
+        Some("exit") => return exit(&text_args(&args[1..]), shell),
+        Some("local") | Some("declare") => return declare(&args[0].to_string_lossy(), &literal_words(&args[1..]), shell),
+        Some("break") => leave_loop(&text_args(&args[1..]), false, shell),
+        Some("continue") => leave_loop(&text_args(&args[1..]), true, shell),
+        Some("true") | Some(":") => true,
+        Some("false") => false,
+        Some("test") | Some("[") => return cond::test(args, shell),
+        Some("let") => let_expr(&text_args(&args[1..]), shell),
+        Some("set") => return set(&args[1..], shell),
+        Some("trap") => return trap(&text_args(&args[1..]), shell),
+        Some("read") => return read(&text_args(&args[1..]), shell),
+        _ => return ex_bin(args, &[], shell),
+    };
+
+    if success {
+        0
+    } else {
+        1
+    }
+}
+
+/// Runs a command with variables that are only set for it, from the
+/// `name=value` words in front of it. A function or builtin sees them as
+/// shell variables, which get their old values back once it is done. A
+/// binary gets them in its environment instead
+pub fn run_with_vars(args: &[OsString], vars: &[(String, OsString)], shell: &mut Shell) -> i32 {
+    let name = args[0].to_str().unwrap_or_default();
+    if shell.function(name).is_none() && !BUILTINS.contains(&name) {
+        return ex_bin(args, vars, shell);
+    }
+
+    let mut saved = Vec::new();
+    for (name, value) in vars {
+        saved.push((name, shell.vars().value(name)));
+        shell.vars_mut().set(name, value.clone());
+    }
+
+    let status = run(args, shell);
 
-    // Execute internal function if necessary
-    match tok_cmd[0] {
-        "cd" => cd(tok_cmd[1], shell),
-        _ => ex_bin(cmd, shell),
+    // Put them back last first, so a name given twice ends up as it was
+    for (name, old) in saved.into_iter().rev() {
+        match old {
+            Some(value) => shell.vars_mut().set_value(name, value),
+            None => shell.vars_mut().unset(name),
+        }
     }
+    status
+}
+
+// Runs a function with the arguments after its name as its positional
+// parameters. Returns the status of the function
+fn call_function(body: &ast::Command, args: &[OsString], shell: &mut Shell) -> i32 {
+    if shell.vars().depth() >= MAX_FUNCTION_DEPTH {
+        print_err(&format!("{}: maximum function nesting level exceeded", args[0].to_string_lossy()));
+        return 1;
+    }
+
+    // The caller's loops can't be broken out of from inside the function
+    let loop_depth = shell.loop_depth();
+    shell.set_loop_depth(0);
+
+    shell.vars_mut().push_frame(args[1..].to_vec());
+    let status = exec::run_command(body, shell);
+    shell.vars_mut().pop_frame();
+    shell.set_loop_depth(loop_depth);
+
+    // A `return` only ends the function it was run in
+    if shell.unwinding() == Some(Unwind::Return) {
+        shell.set_unwinding(None);
+    }
+    status
+}
+
+// Ends the function that is running. Its status is the argument, or the
+// status of the last command if there is no argument
+fn return_from(args: &[String], shell: &mut Shell) -> i32 {
+    if !shell.vars().in_function() {
+        print_err("return: can only `return' from a function");
+        return 1;
+    }
+
+    let status = match args.len() {
+        0 => shell.status(),
+        1 => match args[0].parse::<i32>() {
+            // Statuses wrap around like they do for processes
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                print_err(&format!("return: {}: numeric argument required", args[0]));
+                2
+            }
+        },
+        _ => {
+            print_err("return: too many arguments");
+            return 1;
+        }
+    };
+
+    shell.set_unwinding(Some(Unwind::Return));
+    status
+}
+
+// Runs `exit [n]`, which ends the shell, or the subshell it is run in,
+// with status `n` (the status of the last command if there is none). The
+// commands that are running are cut short first, and the `EXIT` trap runs
+// on the way out
+fn exit(args: &[String], shell: &mut Shell) -> i32 {
+    let status = match args.len() {
+        0 => shell.status(),
+        1 => match args[0].parse::<i32>() {
+            // Statuses wrap around like they do for processes
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                print_err(&format!("exit: {}: numeric argument required", args[0]));
+                2
+            }
+        },
+        _ => {
+            print_err("exit: too many arguments");
+            return 1;
+        }
+    };
+
+    shell.set_unwinding(Some(Unwind::Exit(status)));
+    status
+}
+
+// Runs `break [n]` or `continue [n]`, which end the `n` innermost loops
+// (or go around the `n`th one again). `n` is capped at the number of loops
+// that are running. Returns whether a loop was running and `n` was valid
+fn leave_loop(args: &[String], next: bool, shell: &mut Shell) -> bool {
+    let name = if next { "continue" } else { "break" };
+
+    if shell.loop_depth() == 0 {
+        print_err(&format!("{}: only meaningful in a `for', `while', or `until' loop", name));
+        return false;
+    }
+
+    let count = match args.len() {
+        0 => 1,
+        1 => match args[0].parse::<usize>() {
+            Ok(n) if n > 0 => n.min(shell.loop_depth()),
+            _ => {
+                print_err(&format!("{}: {}: loop count out of range", name, args[0]));
+                return false;
+            }
+        },
+        _ => {
+            print_err(&format!("{}: too many arguments", name));
+            return false;
+        }
+    };
+
+    shell.set_unwinding(Some(if next { Unwind::Continue(count) } else { Unwind::Break(count) }));
+    true
+}
+
+// Runs `set`, which turns options on with `-e` or `-o errexit` and off
+// with `+e` or `+o errexit`. `-o` on its own lists the options and `+o`
+// prints the commands that would set them again. Any other arguments, or
+// the ones after `--`, become the positional parameters. Returns the status
+fn set(args: &[OsString], shell: &mut Shell) -> i32 {
+    let mut i = 0;
+    let mut positional = false;
+
+    while i < args.len() {
+        let arg = args[i].to_string_lossy().into_owned();
+        let on = arg.starts_with('-');
+        if arg == "--" {
+            i += 1;
+            positional = true;
+            break;
+        }
+        if arg.len() < 2 || !(on || arg.starts_with('+')) {
+            break;
+        }
+
+        for flag in arg[1..].chars() {
+            let name = if flag == 'o' {
+                i += 1;
+                match args.get(i) {
+                    Some(name) => name.to_string_lossy().into_owned(),
+                    None => {
+                        list_options(on, shell);
+                        continue;
+                    }
+                }
+            } else {
+                match shell::Options::NAMES.iter().find(|&&(_, letter)| letter == Some(flag)) {
+                    Some(&(name, _)) => name.to_string(),
+                    None => {
+                        print_err(&format!("set: {}{}: invalid option", &arg[..1], flag));
+                        return 2;
+                    }
+                }
+            };
+
+            match shell.options_mut().by_name(&name) {
+                Some(option) => *option = on,
+                None => {
+                    print_err(&format!("set: {}: invalid option name", name));
+                    return 2;
+                }
+            }
+        }
+        i += 1;
+    }
+
+    if positional || i < args.len() {
+        shell.vars_mut().set_args(args[i..].to_vec());
+    }
+    0
+}
+
+// Prints the options for `set -o`, or as the `set` commands that would
+// turn them back on and off for `set +o`
+fn list_options(readable: bool, shell: &Shell) {
+    let options = shell.options();
+
+    for &(name, _) in shell::Options::NAMES {
+        let on = options.is_set(name) == Some(true);
+        if readable {
+            print_out(&format!("{:<15}{}", name, if on { "on" } else { "off" }));
+        } else {
+            print_out(&format!("set {}o {}", if on { '-' } else { '+' }, name));
+        }
+    }
+}
+
+// Runs `trap`. `trap command signal...` runs the command whenever one of
+// the signals arrives, `trap '' signal...` ignores them and `trap - signal...`
+// (or just the signals) goes back to what they normally do. Besides real
+// signals there are `EXIT`, when the shell exits, `ERR`, after a command
+// fails, and `DEBUG`, before each simple command. With no arguments or
+// with `-p`, prints the traps as the commands that would set them again,
+// and `-l` lists the signals. Returns the status
+fn trap(args: &[String], shell: &mut Shell) -> i32 {
+    let args = match args.first().map(String::as_str) {
+        Some("-p") => return print_traps(&args[1..], shell),
+        Some("-l") => {
+            for &(name, number) in sys::SIGNALS {
+                print_out(&format!("{:2}) SIG{}", number, name));
+            }
+            return 0;
+        }
+        Some("--") => &args[1..],
+        _ => args,
+    };
+    if args.is_empty() {
+        return print_traps(&[], shell);
+    }
+
+    // Like in other shells, a first argument that is a number is a signal
+    let (command, specs) = if args.len() == 1 || args[0].parse::<u32>().is_ok() {
+        (None, args)
+    } else if args[0] == "-" {
+        (None, &args[1..])
+    } else {
+        (Some(args[0].clone()), &args[1..])
+    };
+
+    let mut status = 0;
+    for spec in specs {
+        let name = match trap_name(spec) {
+            Some(name) => name,
+            None => {
+                print_err(&format!("trap: {}: invalid signal specification", spec));
+                status = 1;
+                continue;
+            }
+        };
+
+        if let Some(&(_, signal)) = sys::SIGNALS.iter().find(|&&(signal, _)| signal == name) {
+            let disposition = match command {
+                None => Disposition::Default,
+                Some(ref command) if command.is_empty() => Disposition::Ignore,
+                Some(_) => Disposition::Catch,
+            };
+            if let Err(e) = sys::set_signal(signal, disposition) {
+                print_err(&format!("trap: {}: {}", spec, e));
+                status = 1;
+                continue;
+            }
+        }
+        shell.set_trap(name, command.clone());
+    }
+    status
+}
+
+// Prints the traps for the given signals, or all of them if there are
+// none, as `trap` commands. Returns the status
+fn print_traps(specs: &[String], shell: &Shell) -> i32 {
+    let mut status = 0;
+    let mut names = Vec::new();
+
+    for spec in specs {
+        match trap_name(spec) {
+            Some(name) => names.push(name),
+            None => {
+                print_err(&format!("trap: {}: invalid signal specification", spec));
+                status = 1;
+            }
+        }
+    }
+
+    for (name, command) in shell.traps() {
+        if specs.is_empty() || names.contains(&name.as_str()) {
+            print_out(&format!("trap -- {} {}", quote(command), name));
+        }
+    }
+    status
+}
+
+// Returns the name a trap is kept under for a signal given to `trap`,
+// which can be a name, with or without `SIG` and in any case, or a
+// number. `0` is `EXIT`
+fn trap_name(spec: &str) -> Option<&'static str> {
+    if let Ok(number) = spec.parse::<i32>() {
+        if number == 0 {
+            return Some("EXIT");
+        }
+        return sys::SIGNALS.iter().find(|&&(_, signal)| signal == number).map(|&(name, _)| name);
+    }
+
+    let upper = spec.to_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    ["EXIT", "ERR", "DEBUG"]
+        .iter()
+        .cloned()
+        .chain(sys::SIGNALS.iter().map(|&(name, _)| name))
+        .find(|&known| known == name)
+}
+
+/// Runs the command set with `trap` for a signal, if there is one. `$?` is
+/// the same afterwards, and no traps go off while one is running
+pub fn run_trap(name: &str, shell: &mut Shell) {
+    let command = match shell.trap(name) {
+        Some(command) if !command.is_empty() && !shell.in_trap() => command.to_string(),
+        _ => return,
+    };
+
+    let status = shell.status();
+    let unwinding = shell.unwinding();
+    shell.set_unwinding(None);
+    shell.set_in_trap(true);
+
+    dispatch(shell, &command);
+
+    shell.set_in_trap(false);
+    let unwinding = unwinding.or_else(|| shell.unwinding());
+    shell.set_unwinding(unwinding);
+    shell.set_status(status);
+}
+
+/// Runs the traps for the signals that have arrived since this was last
+/// called. While a trap is running they are left for later
+pub fn run_pending_traps(shell: &mut Shell) {
+    if shell.in_trap() {
+        return;
+    }
+
+    for signal in sys::take_signals() {
+        if let Some(&(name, _)) = sys::SIGNALS.iter().find(|&&(_, number)| number == signal) {
+            run_trap(name, shell);
+        }
+    }
+}
+
+// The options given to `read`
+struct ReadOptions {
+    prompt: Option<String>,
+    raw: bool,
+    timeout: Option<Duration>,
+    delimiter: u8,
+    count: Option<usize>,
+    silent: bool,
+    array: Option<String>,
+}
+
+// Runs `read`, which reads a line from standard input and splits it on
+// `$IFS` into the named variables, the last of which gets the rest of the
+// line. With no names the whole line goes in `$REPLY`. Backslashes escape
+// the next character (and join lines) unless `-r` is given. The options
+// are:
+// * `-p prompt`: prints the prompt first, if the input is a terminal
+// * `-t seconds`: gives up after that long
+// * `-d delim`: reads up to the first character of `delim` instead of a
+//   line break
+// * `-n count`: stops after that many characters
+// * `-s`: doesn't echo what is typed on a terminal
+// * `-a name`: puts the fields in an indexed array
+// Returns nonzero if the input ended before the delimiter or it timed out
+fn read(args: &[String], shell: &mut Shell) -> i32 {
+    let (options, names) = match read_options(args) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            print_err(&format!("read: {}", message));
+            return 2;
+        }
+    };
+    for name in options.array.iter().chain(names) {
+        if !valid_name(name) {
+            print_err(&format!("read: `{}': not a valid identifier", name));
+            return 1;
+        }
+    }
+
+    let terminal = sys::isatty(0);
+    if let Some(ref prompt) = options.prompt {
+        if terminal {
+            eprint!("{}", prompt);
+            let _ = io::stderr().flush();
+        }
+    }
+
+    // `-t 0` only checks whether there is input
+    if options.timeout == Some(Duration::from_secs(0)) {
+        return if sys::wait_readable(0, Duration::from_secs(0)).unwrap_or(false) { 0 } else { 1 };
+    }
+
+    let silent = options.silent && terminal && sys::set_echo(0, false).is_ok();
+    let result = read_input(&options);
+    if silent {
+        let _ = sys::set_echo(0, true);
+        eprintln!();
+    }
+
+    let (line, status) = match result {
+        Ok(read) => read,
+        Err(e) => {
+            print_err(&format!("read: {}", e));
+            return 1;
+        }
+    };
+
+    if let Some(ref array) = options.array {
+        let fields = split_line(&line, usize::MAX, shell);
+        let elements = fields.into_iter().enumerate().collect();
+        shell.vars_mut().set_value(array, Value::Indexed(elements));
+    } else if names.is_empty() {
+        let line = line.into_iter().map(|(b, _)| b).collect();
+        shell.vars_mut().set("REPLY", OsString::from_vec(line));
+    } else {
+        let mut fields = split_line(&line, names.len(), shell).into_iter();
+        for name in names {
+            shell.vars_mut().set(name, fields.next().unwrap_or_default());
+        }
+    }
+    status
+}
+
+// Reads the options given to `read`, returning them with the names after
+// them, or a message saying what is wrong
+fn read_options(args: &[String]) -> Result<(ReadOptions, &[String]), String> {
+    let mut options = ReadOptions {
+        prompt: None,
+        raw: false,
+        timeout: None,
+        delimiter: b'\n',
+        count: None,
+        silent: false,
+        array: None,
+    };
+    let mut i = 0;
+
+    while i < args.len() && args[i].len() > 1 && args[i].starts_with('-') {
+        if args[i] == "--" {
+            i += 1;
+            break;
+        }
+
+        for (j, flag) in args[i].char_indices().skip(1) {
+            match flag {
+                'r' => options.raw = true,
+                's' => options.silent = true,
+                'p' | 't' | 'd' | 'n' | 'a' => {
+                    // The value is the rest of the argument, or the next one
+                    let rest = &args[i][j + flag.len_utf8()..];
+                    let value = if !rest.is_empty() {
+                        rest.to_string()
+                    } else {
+                        i += 1;
+                        args.get(i).cloned().ok_or_else(|| format!("-{}: option requires an argument", flag))?
+                    };
+
+                    match flag {
+                        'p' => options.prompt = Some(value),
+                        't' => match value.parse::<f64>() {
+                            Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => {
+                                options.timeout = Some(Duration::from_secs_f64(seconds))
+                            }
+                            _ => return Err(format!("{}: invalid timeout specification", value)),
+                        },
+                        'd' => options.delimiter = value.bytes().next().unwrap_or(0),
+                        'n' => match value.parse::<usize>() {
+                            Ok(count) => options.count = Some(count),
+                            Err(_) => return Err(format!("{}: invalid number", value)),
+                        },
+                        _ => options.array = Some(value),
+                    }
+                    break;
+                }
+                _ => return Err(format!("-{}: invalid option", flag)),
+            }
+        }
+        i += 1;
+    }
+    Ok((options, &args[i..]))
+}
+
+// Reads the input for `read` up to the delimiter, one byte at a time so
+// that the rest is left for the commands after it. Each byte is marked if
+// it was escaped with a backslash. Returns the bytes with the status
+fn read_input(options: &ReadOptions) -> io::Result<(Vec<(u8, bool)>, i32)> {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut line = Vec::new();
+    let mut chars = 0;
+    let mut escaped = false;
+
+    // How many more bytes the last character takes, which are read even
+    // once there are enough characters
+    let mut continuing = 0;
+
+    let next = || -> io::Result<Option<u8>> {
+        if let Some(deadline) = deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if !sys::wait_readable(0, left)? {
+                return Err(io::Error::from(io::ErrorKind::TimedOut));
+            }
+        }
+        sys::read_byte(0)
+    };
+
+    loop {
+        if continuing == 0 && options.count.is_some_and(|count| chars >= count) {
+            return Ok((line, 0));
+        }
+
+        let b = match next() {
+            Ok(Some(b)) => b,
+            Ok(None) => return Ok((line, 1)),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => return Ok((line, TIMEOUT_STATUS)),
+            Err(e) => return Err(e),
+        };
+
+        let quoted = escaped;
+        if escaped {
+            escaped = false;
+            // A backslash before a line break joins the lines
+            if b == b'\n' {
+                continue;
+            }
+        } else if b == options.delimiter {
+            return Ok((line, 0));
+        } else if b == b'\\' && !options.raw {
+            escaped = true;
+            continue;
+        }
+        line.push((b, quoted));
+
+        if continuing > 0 && b & 0xc0 == 0x80 {
+            continuing -= 1;
+        } else {
+            chars += 1;
+            continuing = match b {
+                0xc0..=0xdf => 1,
+                0xe0..=0xef => 2,
+                0xf0..=0xf7 => 3,
+                _ => 0,
+            };
+        }
+    }
+}
+
+/// Runs `declare` or `local` (which `builtin` is). Its words haven't been
+/// expanded yet, so `name=value` words are expanded like assignments and
+/// `name=(a b c)` assigns an array. `-a` makes the names indexed arrays and
+/// `-A` associative arrays. Inside a function the variables are local to
+/// it, and a bare name starts out unset. Returns the status
+pub fn declare(builtin: &str, words: &[String], shell: &mut Shell) -> i32 {
+    let local = shell.vars().in_function();
+    if builtin == "local" && !local {
+        print_err("local: can only be used in a function");
+        return 1;
+    }
+
+    let mut kind = None;
+    let mut options = true;
+    let mut status = 0;
+
+    for word in words {
+        let (name, value) = match exec::split_assignment(word) {
+            Some((name, value)) => (name.to_string(), Some(value)),
+            None => match expand_string(word, shell) {
+                Ok(name) => (name.to_string_lossy().into_owned(), None),
+                Err(e) => {
+                    print_err(&format!("ensh: {}", e));
+                    return 1;
+                }
+            },
+        };
+
+        if options && value.is_none() && name.len() > 1 && name.starts_with('-') {
+            for flag in name[1..].chars() {
+                match flag {
+                    'a' | 'A' => kind = Some(flag),
+                    _ => {
+                        print_err(&format!("{}: -{}: invalid option", builtin, flag));
+                        return 2;
+                    }
+                }
+            }
+            continue;
+        }
+        options = false;
+
+        let (var, subscript) = match split_subscript(&name) {
+            Some((var, subscript)) => (var, Some(subscript)),
+            None => (name.as_str(), None),
+        };
+        if !valid_name(var) {
+            print_err(&format!("{}: `{}': not a valid identifier", builtin, name));
+            status = 1;
+            continue;
+        }
+
+        // The value is expanded before the variable is declared, so that
+        // `local x=$x` sees the caller's `x`
+        let assoc = kind == Some('A') || (!local && shell.vars().is_assoc(var));
+        let assigned = match value {
+            Some(value) if subscript.is_none() => match exec::expand_value(value, assoc, shell) {
+                Ok((value, _)) => Some(value),
+                Err(e) => {
+                    print_err(&format!("ensh: {}", e));
+                    status = 1;
+                    continue;
+                }
+            },
+            _ => None,
+        };
+
+        if let Err(message) = declare_var(var, kind, local, shell) {
+            print_err(&format!("{}: {}", builtin, message));
+            status = 1;
+            continue;
+        }
+
+        match (assigned, value) {
+            (Some(Value::Scalar(value)), _) => shell.vars_mut().set(var, value),
+            (Some(array), _) => shell.vars_mut().set_value(var, array),
+            (None, Some(value)) => {
+                if let Err(e) = exec::assign_word(&name, value, shell) {
+                    print_err(&format!("ensh: {}", e));
+                    status = 1;
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    status
+}
+
+// Declares a variable, as an array if `kind` is `a` (indexed) or `A`
+// (associative). A local variable starts out unset, or as an empty array.
+// Any other variable keeps its value, and a string becomes the element 0
+// of an indexed array
+fn declare_var(name: &str, kind: Option<char>, local: bool, shell: &mut Shell) -> Result<(), String> {
+    let empty = match kind {
+        Some('A') => Value::Assoc(BTreeMap::new()),
+        Some(_) => Value::Indexed(BTreeMap::new()),
+        None => {
+            if local {
+                shell.vars_mut().declare_local(name, None);
+            }
+            return Ok(());
+        }
+    };
+
+    if local {
+        shell.vars_mut().declare_local(name, Some(empty));
+        return Ok(());
+    }
+
+    let value = match (shell.vars().value(name), empty) {
+        (Some(Value::Indexed(_)), Value::Assoc(_)) => {
+            return Err(format!("{}: cannot convert indexed to associative array", name));
+        }
+        (Some(Value::Assoc(_)), Value::Indexed(_)) => {
+            return Err(format!("{}: cannot convert associative to indexed array", name));
+        }
+        (Some(Value::Scalar(s)), Value::Indexed(_)) => Value::Indexed(vec![(0, s)].into_iter().collect()),
+        (Some(Value::Scalar(s)), Value::Assoc(_)) => Value::Assoc(vec![("0".to_string(), s)].into_iter().collect()),
+        (Some(_), _) => return Ok(()),
+        (None, empty) => empty,
+    };
+    shell.vars_mut().set_value(name, value);
+    Ok(())
+}
+
+// Evaluates each argument as an arithmetic expression. Returns whether the
+// last one was evaluated and isn't 0
+fn let_expr(args: &[String], shell: &mut Shell) -> bool {
+    if args.is_empty() {
+        print_err("let: expression expected");
+        return false;
+    }
+
+    let mut value = 0;
+    for arg in args {
+        match arith::eval(arg, shell.vars_mut()) {
+            Ok(n) => value = n,
+            Err(e) => {
+                print_err(&format!("let: {}: {}", arg, e));
+                return false;
+            }
+        }
+    }
+    value != 0
+}
+
+// Converts arguments to strings for the builtins that only deal in text,
+// replacing any bytes that aren't valid UTF-8
+fn text_args(args: &[OsString]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string_lossy().into_owned()).collect()
+}
+
+// Quotes arguments that have already been expanded, so `declare` can
+// expand them again without changing them. The value of an assignment is
+// quoted on its own, to keep it an assignment
+fn literal_words(args: &[OsString]) -> Vec<String> {
+    text_args(args)
+        .iter()
+        .map(|arg| match exec::split_assignment(arg) {
+            Some((name, value)) => format!("{}={}", name, quote(value)),
+            None => quote(arg),
+        })
+        .collect()
 }
 
 // Executes a binary/program that is present in the shell's path, prints output
-// to stdout/stderr as necessary
-// Returns whether the operation was successful
-fn ex_bin(cmd: &str, shell: &mut Shell) -> bool {
+// to stdout/stderr as necessary. `vars` are added to its environment
+// Returns the exit status of the program
+fn ex_bin(args: &[OsString], vars: &[(String, OsString)], shell: &mut Shell) -> i32 {
     // if binary is found in one of the shell's include directories,
     // execute binary with argument, spawn process, etc
-    // otherwise return false
-
-    // Tokenize command, splitting by space
-    let tok_cmd = cmd.split(" ").collect::<Vec<_>>();
-
-    // look to see if binary exists. If it does, then execute command. Otherwise
-    // return false. Let process take over stdio/stderr/stdout
-    if shell.find_bin(tok_cmd[0]) {
-        let process = Command::new(tok_cmd[0])
-            .args(&tok_cmd[1..])
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .expect("Failed to execute process");
-
-        // Getting output from process. Waiting ensures that the process
-        // exits and doesn't become a zombie (Rust doesn't allow us to
-        // drop a Command)
-        let output = process.wait_with_output().expect(
-            "Failed to wait on child process",
-        );
-
-        // Printing any final output
-        let output_str = from_utf8(&output.stdout.as_slice()).unwrap();
-        true
+    // otherwise report it
+
+    // look to see if binary exists. If it does, then execute command.
+    // Let process take over stdio/stderr/stdout
+    match shell.find_bin(&args[0]) {
+        Some(bin) => spawn(&bin, args, vars),
+        None => command_not_found(args, vars, shell),
+    }
+}
+
+// Runs the program at `bin` with inherited stdio and waits for it. The
+// program sees `args[0]` as its name, and `vars` on top of the shell's
+// environment. Returns its exit status, which for a program killed by a
+// signal is 128 plus the signal number
+fn spawn(bin: &Path, args: &[OsString], vars: &[(String, OsString)]) -> i32 {
+    let process = Command::new(bin)
+        .arg0(&args[0])
+        .args(&args[1..])
+        .envs(vars.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn();
+
+    // Waiting ensures that the process exits and doesn't become a
+    // zombie (Rust doesn't allow us to drop a Command). The output has
+    // already gone to the inherited stdout/stderr
+    match process.and_then(|mut process| process.wait()) {
+        Ok(status) => status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0)),
+        Err(e) => {
+            print_err(&format!("ensh: {}: {}", args[0].to_string_lossy(), e));
+            NOT_EXECUTABLE_STATUS
+        }
+    }
+}
+
+// Handles a command that isn't a builtin and isn't in the shell's paths.
+// If there is a `command_not_found_handler` function or command it is run
+// with the command line as its arguments and its status is used. Otherwise
+// the command is reported along with any similarly named builtins, aliases,
+// functions and executables. A handler that is a binary gets `vars` in its
+// environment. Returns the status of the command
+fn command_not_found(args: &[OsString], vars: &[(String, OsString)], shell: &mut Shell) -> i32 {
+    let mut handler_args = vec![OsString::from(NOT_FOUND_HANDLER)];
+    handler_args.extend_from_slice(args);
+
+    if let Some(body) = shell.function(NOT_FOUND_HANDLER) {
+        return call_function(&body, &handler_args, shell);
+    }
+    if let Some(handler) = shell.find_bin(OsStr::new(NOT_FOUND_HANDLER)) {
+        return spawn(&handler, &handler_args, vars);
+    }
+
+    let name = args[0].to_string_lossy();
+    print_err(&format!("ensh: command not found: {}", name));
+
+    let mut candidates: Vec<String> = BUILTINS.iter().map(|builtin| builtin.to_string()).collect();
+    candidates.extend(shell.aliases().keys().cloned());
+    candidates.extend(shell.function_names());
+    candidates.extend(shell.executables());
+
+    let close = suggestions(&name, &candidates);
+    if !close.is_empty() {
+        print_err(&format!("ensh: did you mean: {}?", close.join(", ")));
+    }
+    NOT_FOUND_STATUS
+}
+
+// Changes the working directory of a Shell object. With no argument the
+// directory is changed to the home directory and `-` goes back to the
+// previous directory. Relative names are looked up in `$CDPATH`. `-L` (the
+// default) keeps symlinks in the new working directory, `-P` resolves them.
+// Will return a boolean indicating whether the operation was successful
+fn cd(args: &[OsString], shell: &mut Shell) -> bool {
+    let (physical, args) = match parse_link_options("cd", args) {
+        Some(parsed) => parsed,
+        None => return false,
+    };
+
+    let target = match args.len() {
+        0 => match tilde_home("") {
+            Some(home) => home,
+            None => {
+                print_err("cd: HOME not set");
+                return false;
+            }
+        },
+        1 => PathBuf::from(&args[0]),
+        _ => {
+            print_err("cd: too many arguments");
+            return false;
+        }
+    };
+
+    // Going back to the previous directory prints where we ended up, so
+    // does finding the directory through CDPATH
+    let (path, print_dir) = if target.as_os_str() == "-" {
+        match shell.previous_dir() {
+            Some(dir) => (dir.to_path_buf(), true),
+            None => {
+                print_err("cd: OLDPWD not set");
+                return false;
+            }
+        }
     } else {
-        false
+        match env::var_os("CDPATH").and_then(|cdpath| search_cdpath(&target, &cdpath)) {
+            Some(dir) => (dir, true),
+            None => (target, false),
+        }
+    };
+
+    if !change_dir(&path, physical, shell) {
+        print_err(&format!("cd: {}: No such directory", path.display()));
+        return false;
     }
+
+    if print_dir {
+        print_out(&shell.get_cwd());
+    }
+    true
+}
+
+// Looks a relative directory name up in the directories listed in
+// `cdpath` (the value of `$CDPATH`). An empty entry means the working
+// directory, which isn't counted as a CDPATH match. Names that start with
+// `/`, `.` or `..` are never looked up
+fn search_cdpath(dir: &Path, cdpath: &OsStr) -> Option<PathBuf> {
+    match dir.components().next() {
+        Some(Component::Normal(_)) => {}
+        _ => return None,
+    }
+
+    env::split_paths(cdpath)
+        .filter(|entry| !entry.as_os_str().is_empty())
+        .map(|entry| entry.join(dir))
+        .find(|candidate| candidate.is_dir())
 }
 
 // Changes the working directory of a Shell object to the path referenced by
-// the argument. Will return a boolean indicating whether the operation was
+// the argument. A relative path is resolved against the logical working
+// directory, unless `physical` is set, in which case every symlink is
+// resolved. Will return a boolean indicating whether the operation was
 // successful
-fn cd(path: &str, shell: &mut Shell) -> bool {
-    let abs_path = norm_abs_path(path);
+fn change_dir(path: &Path, physical: bool, shell: &mut Shell) -> bool {
+    if !physical {
+        // The logical path is only used if it actually leads somewhere,
+        // otherwise fall back to the physical path like other shells do
+        let logical = logical_path(shell.working_dir(), path);
+        if logical.is_dir() && shell.change_working_dir(&logical) {
+            return true;
+        }
+    }
+
+    // Relative paths are relative to the shell's working directory
+    let abs_path = norm_abs_path(&shell.working_dir().join(path));
 
     // If path has an issue then return false, don't try
-    if abs_path.is_err() {
-        false
+    match abs_path {
+        Ok(abs_path) => shell.change_working_dir(&abs_path),
+        Err(_) => false,
+    }
+}
+
+// Prints the working directory. `-L` (the default) prints the logical path,
+// `-P` prints it with symlinks resolved
+fn pwd(args: &[OsString], shell: &mut Shell) -> bool {
+    let (physical, args) = match parse_link_options("pwd", args) {
+        Some(parsed) => parsed,
+        None => return false,
+    };
+
+    if !args.is_empty() {
+        print_err("pwd: too many arguments");
+        return false;
+    }
+
+    if physical {
+        print_out(&shell.physical_dir().to_string_lossy());
     } else {
-        shell.change_working_dir(abs_path.unwrap().as_str())
+        print_out(&shell.get_cwd());
+    }
+    true
+}
+
+// Parses the leading `-L`/`-P` options shared by `cd` and `pwd`, the last
+// one given wins. Returns whether symlinks should be resolved along with
+// the remaining arguments, or `None` (after reporting it) on a bad option
+fn parse_link_options<'a>(builtin: &str, args: &'a [OsString]) -> Option<(bool, &'a [OsString])> {
+    let mut physical = false;
+    let mut rest = args;
+
+    while let Some(arg) = rest.first() {
+        // A lone `-` is an argument (`cd -`), not an option
+        let arg = arg.as_bytes();
+        if !arg.starts_with(b"-") || arg == b"-" {
+            break;
+        }
+
+        rest = &rest[1..];
+        if arg == b"--" {
+            break;
+        }
+
+        for flag in String::from_utf8_lossy(&arg[1..]).chars() {
+            match flag {
+                'L' => physical = false,
+                'P' => physical = true,
+                _ => {
+                    print_err(&format!("{}: -{}: invalid option", builtin, flag));
+                    return None;
+                }
+            }
+        }
+    }
+    Some((physical, rest))
+}
+
+// Pushes the working directory onto the directory stack and changes to
+// the given directory. With no argument the top two directories are
+// swapped, and `+N`/`-N` rotates the stack so that the Nth directory from
+// the top/bottom ends up on top. Prints the new stack on success
+fn pushd(args: &[OsString], shell: &mut Shell) -> bool {
+    let mut stack = full_dir_stack(shell);
+
+    match args.len() {
+        0 => {
+            if stack.len() < 2 {
+                print_err("pushd: no other directory");
+                return false;
+            }
+            stack.swap(0, 1);
+        }
+        1 => match parse_stack_index(&args[0].to_string_lossy(), stack.len()) {
+            Some(Some(index)) => stack.rotate_left(index),
+            Some(None) => {
+                print_err(&format!("pushd: {}: directory stack index out of range", args[0].to_string_lossy()));
+                return false;
+            }
+            None => {
+                if !change_dir(Path::new(&args[0]), false, shell) {
+                    print_err(&format!("pushd: {}: No such directory", args[0].to_string_lossy()));
+                    return false;
+                }
+                stack.insert(0, shell.working_dir().to_path_buf());
+                shell.set_dir_stack(stack.split_off(1));
+                print_dir_stack(shell, false, false);
+                return true;
+            }
+        },
+        _ => {
+            print_err("pushd: too many arguments");
+            return false;
+        }
     }
+
+    switch_dir_stack(stack, "pushd", shell)
+}
+
+// Removes the top directory from the directory stack and changes to the
+// new top. `+N`/`-N` removes the Nth directory from the top/bottom
+// instead, which only changes directory if it was the top one. Prints the
+// new stack on success
+fn popd(args: &[String], shell: &mut Shell) -> bool {
+    let mut stack = full_dir_stack(shell);
+
+    if stack.len() < 2 {
+        print_err("popd: directory stack empty");
+        return false;
+    }
+
+    let index = match args.len() {
+        0 => 0,
+        1 => match parse_stack_index(&args[0], stack.len()) {
+            Some(Some(index)) => index,
+            Some(None) => {
+                print_err(&format!("popd: {}: directory stack index out of range", args[0]));
+                return false;
+            }
+            None => {
+                print_err(&format!("popd: {}: invalid argument", args[0]));
+                return false;
+            }
+        },
+        _ => {
+            print_err("popd: too many arguments");
+            return false;
+        }
+    };
+
+    stack.remove(index);
+    if index == 0 {
+        return switch_dir_stack(stack, "popd", shell);
+    }
+
+    shell.set_dir_stack(stack.split_off(1));
+    print_dir_stack(shell, false, false);
+    true
+}
+
+// Prints the directory stack, starting with the working directory. `-c`
+// clears the stack, `-l` prints full paths instead of using `~`, `-v`
+// prints one numbered entry per line and `-p` prints one entry per line.
+// `+N`/`-N` prints only the Nth entry from the top/bottom
+fn dirs(args: &[String], shell: &mut Shell) -> bool {
+    let (mut long, mut verbose, mut per_line) = (false, false, false);
+    let mut index = None;
+
+    for arg in args {
+        if let Some(parsed) = parse_stack_index(arg, full_dir_stack(shell).len()) {
+            match parsed {
+                Some(n) => index = Some(n),
+                None => {
+                    print_err(&format!("dirs: {}: directory stack index out of range", arg));
+                    return false;
+                }
+            }
+            continue;
+        }
+
+        if !arg.starts_with('-') || arg.len() < 2 {
+            print_err(&format!("dirs: {}: invalid argument", arg));
+            return false;
+        }
+
+        for flag in arg[1..].chars() {
+            match flag {
+                'c' => shell.set_dir_stack(Vec::new()),
+                'l' => long = true,
+                'v' => verbose = true,
+                'p' => per_line = true,
+                _ => {
+                    print_err(&format!("dirs: -{}: invalid option", flag));
+                    return false;
+                }
+            }
+        }
+    }
+
+    match index {
+        Some(n) => print_out(&format_stack_entry(&full_dir_stack(shell)[n], long)),
+        None => print_dir_stack(shell, long, verbose || per_line),
+    }
+    true
+}
+
+// Jumps to the most frecent (frequently and recently visited) directory
+// whose path contains all of the fragments, in order. `-l` lists the
+// matching directories with their scores instead, best first, which can be
+// used to complete a jump. Directories that no longer exist are forgotten
+fn jump(args: &[String], shell: &mut Shell) -> bool {
+    let (list, fragments) = match args.first() {
+        Some(flag) if flag == "-l" => (true, &args[1..]),
+        _ => (false, args),
+    };
+
+    if fragments.is_empty() && !list {
+        print_err("j: usage: j [-l] fragment [fragment ...]");
+        return false;
+    }
+
+    let now = frecency::now();
+    let candidates: Vec<frecency::Entry> = shell
+        .frecency()
+        .matches(fragments, now)
+        .into_iter()
+        .filter(|entry| entry.path != shell.working_dir())
+        .cloned()
+        .collect();
+
+    if list {
+        for entry in &candidates {
+            print_out(&format!("{:>10.1}  {}", entry.score(now), entry.path.display()));
+        }
+        return true;
+    }
+
+    for entry in candidates {
+        if entry.path.is_dir() {
+            return change_dir(&entry.path, false, shell);
+        }
+        shell.forget_dir(&entry.path);
+    }
+
+    print_err(&format!("j: no directory matching {}", fragments.join(" ")));
+    false
+}
+
+// Returns the directory stack including the working directory on top
+fn full_dir_stack(shell: &Shell) -> Vec<PathBuf> {
+    let mut stack = vec![shell.working_dir().to_path_buf()];
+    stack.extend_from_slice(shell.dir_stack());
+    stack
+}
+
+// Changes to the directory at the top of `stack` and makes the rest the
+// shell's directory stack. The stack is left alone if the directory can't
+// be changed to
+fn switch_dir_stack(mut stack: Vec<PathBuf>, builtin: &str, shell: &mut Shell) -> bool {
+    let top = stack[0].clone();
+
+    if !change_dir(&top, false, shell) {
+        print_err(&format!("{}: {}: No such directory", builtin, top.display()));
+        return false;
+    }
+
+    shell.set_dir_stack(stack.split_off(1));
+    print_dir_stack(shell, false, false);
+    true
+}
+
+// Parses a `+N` or `-N` directory stack index, counting from the top or the
+// bottom of a stack with `len` entries. Returns `None` if the argument isn't
+// an index, and `Some(None)` if it is out of range
+fn parse_stack_index(arg: &str, len: usize) -> Option<Option<usize>> {
+    if arg.len() < 2 || !(arg.starts_with('+') || arg.starts_with('-')) {
+        return None;
+    }
+
+    let n: usize = arg[1..].parse().ok()?;
+    if n >= len {
+        Some(None)
+    } else if arg.starts_with('+') {
+        Some(Some(n))
+    } else {
+        Some(Some(len - 1 - n))
+    }
+}
+
+// Prints the directory stack on one line, or one entry per line (numbered
+// if `numbered` is set)
+fn print_dir_stack(shell: &Shell, long: bool, numbered: bool) {
+    let entries: Vec<String> = full_dir_stack(shell).iter().map(|dir| format_stack_entry(dir, long)).collect();
+
+    if numbered {
+        for (i, entry) in entries.iter().enumerate() {
+            print_out(&format!("{:2}  {}", i, entry));
+        }
+    } else {
+        print_out(&entries.join(" "));
+    }
+}
+
+// Formats a directory stack entry, shortening the home directory to `~`
+// unless `long` is set
+fn format_stack_entry(dir: &Path, long: bool) -> String {
+    if long {
+        dir.to_string_lossy().into_owned()
+    } else {
+        condense_path(dir).unwrap_or_else(|_| dir.to_string_lossy().into_owned())
+    }
+}
+
+// Re-reads the shell's config file, replacing its paths, prompt and
+// environment settings. Returns whether the config was reloaded
+fn reload(shell: &mut Shell) -> bool {
+    match shell.reload_config() {
+        Ok(()) => true,
+        Err(e) => {
+            print_err(&format!("reload: {}", e));
+            false
+        }
+    }
+}
+
+// Defines the aliases given as `name=value`, and prints the aliases given
+// as just a name. With no arguments every alias is printed. Returns whether
+// every argument was valid
+fn alias(args: &[String], shell: &mut Shell) -> bool {
+    if args.is_empty() {
+        for (name, value) in shell.aliases() {
+            print_out(&format_alias(name, value));
+        }
+        return true;
+    }
+
+    let mut success = true;
+    for arg in args {
+        match arg.find('=') {
+            Some(eq) => {
+                let (name, value) = (&arg[..eq], &arg[eq + 1..]);
+
+                if valid_alias_name(name) {
+                    shell.set_alias(name, value);
+                } else {
+                    print_err(&format!("alias: `{}': invalid alias name", name));
+                    success = false;
+                }
+            }
+            None => match shell.aliases().get(arg) {
+                Some(value) => print_out(&format_alias(arg, value)),
+                None => {
+                    print_err(&format!("alias: {}: not found", arg));
+                    success = false;
+                }
+            },
+        }
+    }
+    success
+}
+
+// Removes the named aliases, or all of them with `-a`. Returns whether
+// every alias existed
+fn unalias(args: &[String], shell: &mut Shell) -> bool {
+    if args.is_empty() {
+        print_err("unalias: usage: unalias [-a] name [name ...]");
+        return false;
+    }
+
+    if args[0] == "-a" {
+        shell.clear_aliases();
+        return true;
+    }
+
+    let mut success = true;
+    for name in args {
+        if !shell.remove_alias(name) {
+            print_err(&format!("unalias: {}: not found", name));
+            success = false;
+        }
+    }
+    success
+}
+
+// Formats an alias the way it would be defined
+fn format_alias(name: &str, value: &str) -> String {
+    format!("alias {}={}", name, quote(value))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::os::unix::fs::{symlink, PermissionsExt};
 
     // Helper function for test functions that returns an initialized shell
     fn initialize_shell() -> Shell {
@@ -92,7 +1398,7 @@ mod tests {
     #[test]
     fn test_cwd_valid_path() {
         let mut shell = initialize_shell();
-        assert!(cd("/", &mut shell));
+        assert!(change_dir(Path::new("/"), false, &mut shell));
     }
 
     // Test that the shell's working directory can't be changed to an invalid
@@ -100,6 +1406,491 @@ mod tests {
     #[test]
     fn test_cwd_invalid_path() {
         let mut shell = initialize_shell();
-        assert!(!cd("nonexistent", &mut shell));
+        assert!(!change_dir(Path::new("nonexistent"), false, &mut shell));
+    }
+
+    // Tests that a bare `cd` goes to the home directory
+    #[test]
+    fn test_cd_home() {
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, "cd"));
+        let home = norm_abs_path(&tilde_home("").unwrap()).unwrap();
+        assert_eq!(shell.working_dir(), home);
+        assert!(!dispatch(&mut shell, "cd / /"));
+    }
+
+    // Tests that `cd -` swaps between the last two directories and that
+    // PWD and OLDPWD are exported
+    #[test]
+    fn test_cd_previous() {
+        let mut shell = initialize_shell();
+        assert!(!dispatch(&mut shell, "cd -"));
+
+        let tmp = norm_abs_path(&env::temp_dir()).unwrap();
+        assert!(dispatch(&mut shell, "cd /"));
+        assert!(dispatch(&mut shell, &format!("cd {}", tmp.display())));
+        assert!(dispatch(&mut shell, "cd -"));
+        assert_eq!(shell.get_cwd(), "/");
+        assert!(dispatch(&mut shell, "cd -"));
+        assert_eq!(shell.working_dir(), tmp);
+    }
+
+    // Tests that symlinks are kept in the working directory with `-L` and
+    // resolved with `-P`
+    #[test]
+    fn test_cd_logical_physical() {
+        let base = norm_abs_path(&env::temp_dir()).unwrap().join("ensh_logical");
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("a/real")).unwrap();
+        symlink(base.join("a/real"), base.join("link")).unwrap();
+
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, &format!("cd {}/link", base.display())));
+        assert_eq!(shell.working_dir(), base.join("link"));
+        assert_eq!(shell.physical_dir(), base.join("a/real"));
+        assert!(dispatch(&mut shell, "pwd -P"));
+
+        // `..` is taken lexically from the logical path
+        assert!(dispatch(&mut shell, "cd .."));
+        assert_eq!(shell.working_dir(), base);
+
+        assert!(dispatch(&mut shell, "cd -P link/.."));
+        assert_eq!(shell.working_dir(), base.join("a"));
+
+        assert!(dispatch(&mut shell, "cd -L ../link"));
+        assert_eq!(shell.working_dir(), base.join("link"));
+        assert!(!dispatch(&mut shell, "cd -X /"));
+        assert!(!dispatch(&mut shell, "pwd extra"));
+    }
+
+    // Tests that directories whose names aren't valid UTF-8 can be entered
+    // and used with the directory stack
+    #[test]
+    fn test_cd_non_utf8() {
+        let base = norm_abs_path(&env::temp_dir()).unwrap().join("ensh_non_utf8");
+        let target = base.join(OsStr::from_bytes(b"dir_\xff\xfe"));
+        fs::create_dir_all(&target).unwrap();
+
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, &format!("cd {}", base.display())));
+        assert!(dispatch(&mut shell, "cd dir_*"));
+        assert_eq!(shell.working_dir(), target);
+        assert!(dispatch(&mut shell, "pwd"));
+        assert!(dispatch(&mut shell, "pushd .."));
+        assert!(dispatch(&mut shell, "dirs -v"));
+        assert!(dispatch(&mut shell, "popd"));
+        assert_eq!(shell.working_dir(), target);
+    }
+
+    // Tests pushing, rotating and popping the directory stack
+    #[test]
+    fn test_pushd_popd() {
+        let base = norm_abs_path(&env::temp_dir()).unwrap().join("ensh_dirstack");
+        for dir in &["a", "b", "c"] {
+            fs::create_dir_all(base.join(dir)).unwrap();
+        }
+
+        let mut shell = initialize_shell();
+        assert!(!dispatch(&mut shell, "popd"));
+        assert!(!dispatch(&mut shell, "pushd"));
+        assert!(dispatch(&mut shell, &format!("cd {}/a", base.display())));
+        assert!(dispatch(&mut shell, "pushd ../b"));
+        assert!(dispatch(&mut shell, "pushd ../c"));
+        assert_eq!(full_dir_stack(&shell), vec![base.join("c"), base.join("b"), base.join("a")]);
+
+        // Swap the top two, then rotate
+        assert!(dispatch(&mut shell, "pushd"));
+        assert_eq!(shell.working_dir(), base.join("b"));
+        assert!(dispatch(&mut shell, "pushd +2"));
+        assert_eq!(full_dir_stack(&shell), vec![base.join("a"), base.join("b"), base.join("c")]);
+        assert!(dispatch(&mut shell, "pushd -0"));
+        assert_eq!(full_dir_stack(&shell), vec![base.join("c"), base.join("a"), base.join("b")]);
+        assert!(!dispatch(&mut shell, "pushd +3"));
+        assert!(!dispatch(&mut shell, "pushd nonexistent"));
+
+        // Removing an entry other than the top doesn't change directory
+        assert!(dispatch(&mut shell, "popd +1"));
+        assert_eq!(full_dir_stack(&shell), vec![base.join("c"), base.join("b")]);
+        assert!(dispatch(&mut shell, "popd"));
+        assert_eq!(shell.working_dir(), base.join("b"));
+        assert!(shell.dir_stack().is_empty());
+    }
+
+    // Tests printing and clearing the directory stack
+    #[test]
+    fn test_dirs() {
+        let mut shell = initialize_shell();
+        shell.set_dir_stack(vec![PathBuf::from("/")]);
+        assert!(dispatch(&mut shell, "dirs"));
+        assert!(dispatch(&mut shell, "dirs -v -l"));
+        assert!(dispatch(&mut shell, "dirs +1"));
+        assert!(!dispatch(&mut shell, "dirs +2"));
+        assert!(!dispatch(&mut shell, "dirs -x"));
+        assert!(dispatch(&mut shell, "dirs -c"));
+        assert!(shell.dir_stack().is_empty());
+    }
+
+    // Tests jumping to a visited directory by a fragment of its path
+    #[test]
+    fn test_jump() {
+        let base = norm_abs_path(&env::temp_dir()).unwrap().join("ensh_jump");
+        let target = base.join("deeply/nested/jumptarget");
+        fs::create_dir_all(&target).unwrap();
+
+        let mut shell = initialize_shell();
+        assert!(!dispatch(&mut shell, "j"));
+        assert!(!dispatch(&mut shell, "j jumptarget"));
+
+        assert!(dispatch(&mut shell, &format!("cd {}", target.display())));
+        assert!(dispatch(&mut shell, "cd /"));
+        assert!(dispatch(&mut shell, "j -l jump"));
+        assert!(dispatch(&mut shell, "j NESTED jump"));
+        assert_eq!(shell.working_dir(), target);
+
+        // The working directory itself is never a jump target
+        assert!(!dispatch(&mut shell, "j jumptarget"));
+    }
+
+    // Tests parsing of directory stack indexes
+    #[test]
+    fn test_parse_stack_index() {
+        assert_eq!(parse_stack_index("+0", 3), Some(Some(0)));
+        assert_eq!(parse_stack_index("-0", 3), Some(Some(2)));
+        assert_eq!(parse_stack_index("+3", 3), Some(None));
+        assert_eq!(parse_stack_index("dir", 3), None);
+        assert_eq!(parse_stack_index("-", 3), None);
+        assert_eq!(parse_stack_index("-v", 3), None);
+    }
+
+    // Tests that relative names are found through CDPATH
+    #[test]
+    fn test_search_cdpath() {
+        let tmp = env::temp_dir();
+        let base = tmp.join("ensh_cdpath");
+        fs::create_dir_all(base.join("project")).unwrap();
+        let cdpath = format!("::/nonexistent:{}", base.display());
+
+        let cdpath = OsStr::new(&cdpath);
+
+        assert_eq!(search_cdpath(Path::new("project"), cdpath), Some(base.join("project")));
+        assert_eq!(search_cdpath(Path::new("missing"), cdpath), None);
+    }
+
+    // Tests which names are never looked up in CDPATH
+    #[test]
+    fn test_search_cdpath_skips() {
+        let root = OsStr::new("/");
+        assert_eq!(search_cdpath(Path::new("/usr"), root), None);
+        assert_eq!(search_cdpath(Path::new("./usr"), root), None);
+        assert_eq!(search_cdpath(Path::new("../usr"), root), None);
+        assert!(search_cdpath(Path::new("usr"), root).is_some());
+    }
+
+    // Tests that reloading fails cleanly when the shell has no config file
+    #[test]
+    fn test_reload_without_config() {
+        let mut shell = initialize_shell();
+        assert!(!dispatch(&mut shell, "reload"));
+    }
+
+    // Tests that aliases can be defined, queried and removed
+    #[test]
+    fn test_alias_unalias() {
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, "alias ll='ls -la' la=\"ls -a\""));
+        assert_eq!(shell.aliases().get("ll").unwrap(), "ls -la");
+        assert_eq!(shell.aliases().get("la").unwrap(), "ls -a");
+        assert!(dispatch(&mut shell, "alias ll"));
+        assert!(dispatch(&mut shell, "alias"));
+        assert!(!dispatch(&mut shell, "alias nope"));
+        assert!(!dispatch(&mut shell, "alias 'a b=c'"));
+
+        assert!(dispatch(&mut shell, "unalias ll"));
+        assert!(!shell.aliases().contains_key("ll"));
+        assert!(!dispatch(&mut shell, "unalias ll"));
+        assert!(dispatch(&mut shell, "unalias -a"));
+        assert!(shell.aliases().is_empty());
+    }
+
+    // Tests that an alias is expanded before the command is dispatched
+    #[test]
+    fn test_alias_dispatch() {
+        let mut shell = initialize_shell();
+        shell.set_alias("goto", "cd");
+        assert!(dispatch(&mut shell, "goto /"));
+        assert!(!dispatch(&mut shell, "goto nonexistent"));
+    }
+
+    // Tests that a command that can't be found fails, and that a handler in
+    // the shell's paths gets the command line instead
+    #[test]
+    fn test_command_not_found() {
+        let mut shell = initialize_shell();
+        shell.set_alias("gitlog", "git log");
+        assert!(!dispatch(&mut shell, "gtilog"));
+
+        let bin = env::temp_dir().join("ensh_not_found");
+        fs::create_dir_all(&bin).unwrap();
+        let handler = bin.join(NOT_FOUND_HANDLER);
+        fs::write(&handler, "#!/bin/sh\n[ \"$1\" = gti ]\n").unwrap();
+        fs::set_permissions(&handler, fs::Permissions::from_mode(0o755)).unwrap();
+
+        shell.set_paths(vec![bin]);
+        assert!(dispatch(&mut shell, "gti status"));
+        assert!(!dispatch(&mut shell, "other"));
+    }
+
+    // Tests that input that can't be tokenized is rejected
+    #[test]
+    fn test_dispatch_unterminated_quote() {
+        let mut shell = initialize_shell();
+        assert!(!dispatch(&mut shell, "alias x='oops"));
+        assert!(shell.aliases().is_empty());
+    }
+
+    // Tests that functions get their own positional parameters
+    #[test]
+    fn test_function_call() {
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, "f() { n=$#; first=$1; all=\"$*\"; }"));
+        assert!(dispatch(&mut shell, "f 'a b' c; f2=$1"));
+        assert_eq!(shell.vars().get("n"), Some(OsString::from("2")));
+        assert_eq!(shell.vars().get("first"), Some(OsString::from("a b")));
+        assert_eq!(shell.vars().get("all"), Some(OsString::from("a b c")));
+        assert_eq!(shell.vars().get("f2"), Some(OsString::new()));
+    }
+
+    // Tests that locals are dropped when their function returns
+    #[test]
+    fn test_function_local() {
+        let mut shell = initialize_shell();
+        let line = "x=outer; function f { local x=inner y; inside=$x; y=set; }; f";
+        assert!(dispatch(&mut shell, line));
+        assert_eq!(shell.vars().get("inside"), Some(OsString::from("inner")));
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("outer")));
+        assert_eq!(shell.vars().get("y"), None);
+        assert!(!dispatch(&mut shell, "local z"));
+    }
+
+    // Tests assigning arrays and their elements, with and without `declare`
+    #[test]
+    fn test_declare_arrays() {
+        let mut shell = initialize_shell();
+        let values = |shell: &Shell, name: &str| shell.vars().value(name).map(|value| value.values());
+        let strings = |items: &[&str]| Some(items.iter().map(OsString::from).collect::<Vec<_>>());
+
+        assert!(dispatch(&mut shell, "v='x y'; arr=(\"$v\" $v [5]=z); arr[1+1]=Y; arr[-1]=${arr[-1]}w"));
+        assert_eq!(values(&shell, "arr"), strings(&["x y", "x", "Y", "zw"]));
+        assert!(dispatch(&mut shell, "declare -A map=([a]=1 [$v]=2); map[c]=$v; declare -a arr"));
+        assert_eq!(values(&shell, "map"), strings(&["1", "x y", "2"]));
+        assert_eq!(shell.vars().get("arr"), Some(OsString::from("x y")));
+
+        assert!(!dispatch(&mut shell, "declare -A arr"));
+        assert!(!dispatch(&mut shell, "declare 1x=2"));
+        assert!(dispatch(&mut shell, "declare s=$v; declare -a s"));
+        assert_eq!(values(&shell, "s"), strings(&["x y"]));
+    }
+
+    // Tests that arrays declared in a function are local to it, and that
+    // `local x=$x` sees the outer variable
+    #[test]
+    fn test_declare_local() {
+        let mut shell = initialize_shell();
+        let line = "x=1; f() { local x=$x$x; declare -a a=(1 2); inside=${a[1]}$x; }; f";
+        assert!(dispatch(&mut shell, line));
+        assert_eq!(shell.vars().get("inside"), Some(OsString::from("211")));
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("1")));
+        assert_eq!(shell.vars().value("a"), None);
+    }
+
+    // Tests that `return` ends only its own function with the given status
+    #[test]
+    fn test_function_return() {
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, "g() { return 3; after=1; }; f() { g; s=$?; return; }"));
+        assert!(dispatch(&mut shell, "f"));
+        assert_eq!(shell.vars().get("s"), Some(OsString::from("3")));
+        assert_eq!(shell.vars().get("after"), None);
+        assert!(!dispatch(&mut shell, "g"));
+        assert_eq!(shell.status(), 3);
+        assert!(!dispatch(&mut shell, "return"));
+    }
+
+    // Tests that functions take precedence over builtins and binaries, and
+    // that runaway recursion is stopped
+    #[test]
+    fn test_function_precedence() {
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, "cd() { called=cd; }; cd /"));
+        assert_eq!(shell.vars().get("called"), Some(OsString::from("cd")));
+        assert!(dispatch(&mut shell, "sh() { called=sh; }; sh -c 'exit 1'"));
+        assert_eq!(shell.vars().get("called"), Some(OsString::from("sh")));
+        assert!(!dispatch(&mut shell, "loop() { loop; }; loop"));
+    }
+
+    // Tests that `break` and `continue` only work on loops in the same
+    // function
+    #[test]
+    fn test_break_continue() {
+        let mut shell = initialize_shell();
+        assert!(!dispatch(&mut shell, "break"));
+        assert!(!dispatch(&mut shell, "for x in a; do continue 0; done"));
+        assert!(dispatch(&mut shell, "f() { break; }; for x in a b; do f; last=$x; done"));
+        assert_eq!(shell.vars().get("last"), Some(OsString::from("b")));
+        assert!(dispatch(&mut shell, "for x in a b; do for y in c; do break 5; done; broken=$x; done"));
+        assert_eq!(shell.vars().get("broken"), None);
+    }
+
+    // Tests that commands cut off at the end of a line are told apart from
+    // ones with mistakes in them
+    #[test]
+    fn test_is_incomplete() {
+        let mut shell = initialize_shell();
+        assert!(is_incomplete(&shell, "if true; then"));
+        assert!(is_incomplete(&shell, "for x in a b; do\necho $x"));
+        assert!(is_incomplete(&shell, "echo 'a"));
+        assert!(!is_incomplete(&shell, "echo a; fi"));
+        assert!(!is_incomplete(&shell, "while true; do break; done"));
+
+        shell.set_alias("loop", "while");
+        assert!(is_incomplete(&shell, "loop true"));
+        assert!(is_incomplete(&shell, "(( 1 +"));
+    }
+
+    // Tests that `set` changes options and the positional parameters
+    #[test]
+    fn test_set() {
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, "set -eu -o pipefail +e"));
+        assert!(!shell.options().errexit);
+        assert!(shell.options().nounset && shell.options().pipefail);
+        assert!(dispatch(&mut shell, "set +o nounset -C a 'b c'"));
+        assert!(!shell.options().nounset && shell.options().noclobber);
+        assert_eq!(shell.vars().args(), &[OsString::from("a"), OsString::from("b c")][..]);
+        assert!(dispatch(&mut shell, "set -- -x"));
+        assert_eq!(shell.vars().args(), &[OsString::from("-x")][..]);
+        assert!(!shell.options().xtrace);
+        assert!(dispatch(&mut shell, "set --"));
+        assert!(shell.vars().args().is_empty());
+
+        assert!(!dispatch(&mut shell, "set -q"));
+        assert_eq!(shell.status(), 2);
+        assert!(!dispatch(&mut shell, "set -o nonsense"));
+    }
+
+    // Tests that `set -n` only checks commands, including `set +n`, unless
+    // the shell is interactive
+    #[test]
+    fn test_set_noexec() {
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, "set -n; x=1"));
+        assert!(dispatch(&mut shell, "x=2; set +n"));
+        assert!(!dispatch(&mut shell, "if true; then"));
+        assert!(shell.options().noexec);
+        assert_eq!(shell.vars().get("x"), None);
+
+        shell.set_interactive(true);
+        assert!(dispatch(&mut shell, "x=3; set +n"));
+        assert!(!shell.options().noexec);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("3")));
+    }
+
+    // Tests setting and resetting traps, and that a trapped signal runs its
+    // command. The signal is sent in a child, so the other tests' threads
+    // don't take it
+    #[test]
+    fn test_trap() {
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, "trap 'x=$?' exit Err"));
+        assert_eq!(shell.trap("EXIT"), Some("x=$?"));
+        assert_eq!(shell.trap("ERR"), Some("x=$?"));
+        assert!(!dispatch(&mut shell, "false"));
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("1")));
+        assert_eq!(shell.status(), 1);
+
+        assert!(dispatch(&mut shell, "trap - ERR; trap 0"));
+        assert!(shell.traps().is_empty());
+        assert!(!dispatch(&mut shell, "trap 'x=1' NOPE"));
+        assert!(!dispatch(&mut shell, "trap 'x=1' SIGKILL"));
+        assert!(shell.traps().is_empty());
+
+        match sys::fork().unwrap() {
+            sys::Fork::Child => {
+                let mut ok = dispatch(&mut shell, "trap 'y=$((y + 1))' SIGUSR1 && trap '' usr2");
+                unsafe { libc::raise(libc::SIGUSR1) };
+                unsafe { libc::raise(libc::SIGUSR2) };
+                ok &= dispatch(&mut shell, ":") && shell.vars().get("y") == Some(OsString::from("1"));
+                sys::exit_child(if ok { 0 } else { 1 });
+            }
+            sys::Fork::Parent(pid) => assert_eq!(sys::wait(pid).unwrap(), 0),
+        }
+    }
+
+    // Tests reading lines into variables. The input is redirected inside a
+    // command substitution, so only the forked shell's is changed
+    #[test]
+    fn test_read() {
+        let mut shell = initialize_shell();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+        let mut read = |line: &str| {
+            assert!(dispatch(&mut shell, &format!("x=$({})", line)));
+            shell.vars().get("x").unwrap().into_string().unwrap()
+        };
+
+        assert_eq!(read("read a b <<< '  one two  three  '; printf '%s|%s' \"$a\" \"$b\""), "one|two  three");
+        assert_eq!(read("read a b c <<< one; printf '%s|%s|%s' \"$a\" \"$b\" \"$c\""), "one||");
+        assert_eq!(read("read <<< '  a b  '; printf '[%s]' \"$REPLY\""), "[  a b  ]");
+        assert_eq!(read("read a b <<< 'x\\ y z\\\nw'; printf '%s|%s' \"$a\" \"$b\""), "x y|zw");
+        assert_eq!(read("read -r a <<< 'x\\ y'; printf %s \"$a\""), "x\\ y");
+        assert_eq!(read("IFS=: read a b c <<< 'p::q:r'; printf '%s|%s|%s' \"$a\" \"$b\" \"$c\""), "p||q:r");
+        assert_eq!(read("read -a arr <<< ' a b  c '; printf '%s,' \"${arr[@]}\" ${#arr[@]}"), "a,b,c,3,");
+        assert_eq!(read("read -d , a b <<< 'x y,z'; printf '%s|%s|%s' $? \"$a\" \"$b\""), "0|x|y");
+        assert_eq!(read("read -rn3 a <<< 'héllo'; printf %s \"$a\""), "hél");
+
+        assert_eq!(read("read a < /dev/null; printf %s $?"), "1");
+        assert_eq!(read("printf abc | { read a; printf '%s %s' $? \"$a\"; }"), "1 abc");
+        assert_eq!(read("sleep 0.5 | { read -t 0.1 a; printf %s $?; }"), "142");
+        assert_eq!(read("read -t 0 < /dev/null; printf %s $?"), "0");
+        assert_eq!(read("read -q a; printf %s $?"), "2");
+        assert_eq!(read("read 1a; printf %s $?"), "1");
+    }
+
+    // Tests that `read` takes only its line from input that isn't a
+    // terminal, leaving the rest for the shell's own input. This runs in a
+    // child, since it replaces standard input
+    #[test]
+    fn test_read_shared_input() {
+        use std::io::Write;
+        use std::os::unix::io::AsRawFd;
+
+        let mut shell = initialize_shell();
+        let (reader, mut writer) = sys::pipe().unwrap();
+        writer.write_all(b"hello  world\necho next\nlast").unwrap();
+        drop(writer);
+
+        match sys::fork().unwrap() {
+            sys::Fork::Child => {
+                let mut ok = sys::dup2(reader.as_raw_fd(), 0).is_ok() && dispatch(&mut shell, "read a b");
+                ok &= shell.vars().get("a") == Some(OsString::from("hello"));
+                ok &= shell.vars().get("b") == Some(OsString::from("world"));
+                ok &= ::interface::read_line() == Some("echo next".to_string());
+                ok &= !dispatch(&mut shell, "read c") && shell.vars().get("c") == Some(OsString::from("last"));
+                ok &= ::interface::read_line().is_none();
+                sys::exit_child(if ok { 0 } else { 1 });
+            }
+            sys::Fork::Parent(pid) => assert_eq!(sys::wait(pid).unwrap(), 0),
+        }
+    }
+
+    // Tests that `let` evaluates each argument and fails if the last one
+    // is 0
+    #[test]
+    fn test_let() {
+        let mut shell = initialize_shell();
+        assert!(dispatch(&mut shell, "let 'a = 2' b=a**3"));
+        assert_eq!(shell.vars().get("b"), Some(OsString::from("8")));
+        assert!(!dispatch(&mut shell, "let b-8"));
+        assert!(!dispatch(&mut shell, "let 1/0"));
+        assert!(!dispatch(&mut shell, "let"));
     }
 }
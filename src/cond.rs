@@ -0,0 +1,482 @@
+//! cond.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Summary
+//! The cond module evaluates conditional expressions, for the `test` and `[`
+//! builtins and for the `[[ ]]` command. Both share the same file, string
+//! and integer tests. `test` gets its operands after they've been expanded
+//! like any other command's arguments, so it has to work out which of them
+//! are operators as it goes. `[[ ]]` is parsed along with the rest of the
+//! input, so its words are only expanded once it's known what they are,
+//! which lets `==` take a glob pattern and `=~` a regular expression.
+//!
+//! Relative paths are resolved against the shell's working directory
+
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::PathBuf;
+
+use libc;
+
+use shell::Shell;
+use interface::print_err;
+use parser::ast::CondExpr;
+use parser::expand::{expand_pattern, expand_regex, expand_string};
+use parser::glob::Pattern;
+use sys;
+
+// Operators that take a single operand
+const UNARY_OPS: &[&str] = &[
+    "-e", "-f", "-d", "-L", "-h", "-s", "-p", "-S", "-b", "-c", "-r", "-w", "-x", "-g", "-u", "-k", "-z", "-n", "-t",
+    "-v",
+];
+
+// Operators that compare two operands
+const BINARY_OPS: &[&str] = &[
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+// The status of a test that couldn't be evaluated
+const ERROR_STATUS: i32 = 2;
+
+/// Returns whether `op` is an operator that takes one operand, like `-f`
+pub fn is_unary_op(op: &str) -> bool {
+    UNARY_OPS.contains(&op)
+}
+
+/// Returns whether `op` is an operator that compares two operands, like
+/// `-eq`
+pub fn is_binary_op(op: &str) -> bool {
+    BINARY_OPS.contains(&op)
+}
+
+/// Runs `test` or `[`, given the whole command line. `[` has to end with a
+/// `]`. Returns 0 if the expression is true, 1 if it's false and 2 if it
+/// couldn't be evaluated
+pub fn test(args: &[OsString], shell: &Shell) -> i32 {
+    let name = args[0].to_string_lossy();
+    let mut operands = &args[1..];
+
+    if name == "[" {
+        match operands.split_last() {
+            Some((last, rest)) if last == "]" => operands = rest,
+            _ => {
+                print_err("[: missing `]'");
+                return ERROR_STATUS;
+            }
+        }
+    }
+
+    match eval_args(operands, shell) {
+        Ok(result) => status(result),
+        Err(e) => {
+            print_err(&format!("{}: {}", name, e));
+            ERROR_STATUS
+        }
+    }
+}
+
+/// Evaluates the expression of a `[[ ]]` command, expanding its words.
+/// Returns an error if a word can't be expanded or an operand isn't valid
+pub fn eval_cond(expr: &CondExpr, shell: &mut Shell) -> Result<bool, String> {
+    match *expr {
+        CondExpr::And(ref left, ref right) => Ok(eval_cond(left, shell)? && eval_cond(right, shell)?),
+        CondExpr::Or(ref left, ref right) => Ok(eval_cond(left, shell)? || eval_cond(right, shell)?),
+        CondExpr::Not(ref expr) => eval_cond(expr, shell).map(|result| !result),
+        CondExpr::Unary(ref op, ref operand) => {
+            let operand = expand(operand, shell)?;
+            unary(op, &operand, shell)
+        }
+        CondExpr::Binary(ref left, ref op, ref right) => {
+            let left = expand(left, shell)?;
+            match op.as_str() {
+                // The right side is a pattern, unless it's quoted
+                "=" | "==" | "!=" => {
+                    let pattern = expand_pattern(right, shell).map_err(|e| e.to_string())?;
+                    Ok(Pattern::new(pattern).matches(&left) == (op != "!="))
+                }
+                "=~" => {
+                    let regex = expand_regex(right, shell).map_err(|e| e.to_string())?;
+                    sys::regex_match(&regex, &left)
+                        .ok_or_else(|| format!("{}: invalid regular expression", regex.to_string_lossy()))
+                }
+                op => {
+                    let right = expand(right, shell)?;
+                    binary(&left, op, &right, shell)
+                }
+            }
+        }
+        CondExpr::Word(ref word) => Ok(!expand(word, shell)?.is_empty()),
+    }
+}
+
+// Expands a word of a `[[ ]]` command
+fn expand(word: &str, shell: &mut Shell) -> Result<OsString, String> {
+    expand_string(word, shell).map_err(|e| e.to_string())
+}
+
+/// Returns the exit status for the result of a test
+pub fn status(result: bool) -> i32 {
+    if result {
+        0
+    } else {
+        1
+    }
+}
+
+// Evaluates the operands of `test`. Short expressions are evaluated the way
+// POSIX lays out, by how many operands there are, so that `test ! = x` and
+// `test -n` do the expected thing. Longer ones are parsed with `-a`, `-o`,
+// `!` and parentheses
+fn eval_args(args: &[OsString], shell: &Shell) -> Result<bool, String> {
+    match args.len() {
+        0 => return Ok(false),
+        1 => return Ok(!args[0].is_empty()),
+        2 => {
+            if args[0] == "!" {
+                return Ok(args[1].is_empty());
+            }
+            return match args[0].to_str() {
+                Some(op) if is_unary_op(op) => unary(op, &args[1], shell),
+                _ => Err(format!("{}: unary operator expected", args[0].to_string_lossy())),
+            };
+        }
+        3 => {
+            if let Some(op) = args[1].to_str().filter(|op| is_binary_op(op)) {
+                return binary(&args[0], op, &args[2], shell);
+            }
+            if args[0] == "!" {
+                return eval_args(&args[1..], shell).map(|result| !result);
+            }
+            if args[0] == "(" && args[2] == ")" {
+                return Ok(!args[1].is_empty());
+            }
+        }
+        4 => {
+            if args[0] == "!" {
+                return eval_args(&args[1..], shell).map(|result| !result);
+            }
+            if args[0] == "(" && args[3] == ")" {
+                return eval_args(&args[1..3], shell);
+            }
+        }
+        _ => {}
+    }
+
+    let mut parser = TestParser { args, pos: 0, shell };
+    let result = parser.or()?;
+    match args.get(parser.pos) {
+        None => Ok(result),
+        Some(_) => Err("too many arguments".to_string()),
+    }
+}
+
+// The operands of a long `test` expression and how far it's been parsed
+struct TestParser<'a> {
+    args: &'a [OsString],
+    pos: usize,
+    shell: &'a Shell,
+}
+
+impl<'a> TestParser<'a> {
+    // Returns whether the operand `offset` ahead is `s`
+    fn is(&self, offset: usize, s: &str) -> bool {
+        self.args.get(self.pos + offset).is_some_and(|arg| arg == s)
+    }
+
+    // Parses expressions joined by `-o`
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+
+        while self.is(0, "-o") {
+            self.pos += 1;
+            let right = self.and()?;
+            result = result || right;
+        }
+        Ok(result)
+    }
+
+    // Parses expressions joined by `-a`, which binds tighter than `-o`
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+
+        while self.is(0, "-a") {
+            self.pos += 1;
+            let right = self.not()?;
+            result = result && right;
+        }
+        Ok(result)
+    }
+
+    // Parses an expression that may be negated with `!`
+    fn not(&mut self) -> Result<bool, String> {
+        if self.is(0, "!") {
+            self.pos += 1;
+            return self.not().map(|result| !result);
+        }
+        self.primary()
+    }
+
+    // Parses a parenthesized expression, a test with an operator or a
+    // single string
+    fn primary(&mut self) -> Result<bool, String> {
+        let args = self.args;
+        let arg = match args.get(self.pos) {
+            Some(arg) => arg,
+            None => return Err("argument expected".to_string()),
+        };
+
+        if arg == "(" {
+            self.pos += 1;
+            let result = self.or()?;
+            if !self.is(0, ")") {
+                return Err("missing `)'".to_string());
+            }
+            self.pos += 1;
+            return Ok(result);
+        }
+
+        if self.pos + 2 < args.len() {
+            if let Some(op) = args[self.pos + 1].to_str().filter(|op| is_binary_op(op)) {
+                self.pos += 3;
+                return binary(arg, op, &args[self.pos - 1], self.shell);
+            }
+        }
+
+        if let Some(op) = arg.to_str().filter(|op| is_unary_op(op)) {
+            if self.pos + 1 < args.len() {
+                self.pos += 2;
+                return unary(op, &args[self.pos - 1], self.shell);
+            }
+        }
+
+        self.pos += 1;
+        Ok(!arg.is_empty())
+    }
+}
+
+// Applies an operator that takes one operand
+fn unary(op: &str, operand: &OsStr, shell: &Shell) -> Result<bool, String> {
+    match op {
+        "-z" => return Ok(operand.is_empty()),
+        "-n" => return Ok(!operand.is_empty()),
+        "-t" => return integer(operand).map(|fd| libc::c_int::try_from(fd).map(sys::isatty).unwrap_or(false)),
+        "-v" => return Ok(operand.to_str().and_then(|name| shell.vars().get(name)).is_some()),
+        _ => {}
+    }
+
+    // The rest are file tests. An empty operand names no file, rather than
+    // the working directory
+    if operand.is_empty() {
+        return Ok(false);
+    }
+    let path = resolve(operand, shell);
+
+    if op == "-L" || op == "-h" {
+        return Ok(fs::symlink_metadata(&path).map(|meta| meta.file_type().is_symlink()).unwrap_or(false));
+    }
+
+    let meta = match fs::metadata(&path) {
+        Ok(meta) => meta,
+        Err(_) => return Ok(false),
+    };
+    let file_type = meta.file_type();
+    let mode = meta.permissions().mode();
+
+    Ok(match op {
+        "-e" => true,
+        "-f" => file_type.is_file(),
+        "-d" => file_type.is_dir(),
+        "-s" => meta.len() > 0,
+        "-p" => file_type.is_fifo(),
+        "-S" => file_type.is_socket(),
+        "-b" => file_type.is_block_device(),
+        "-c" => file_type.is_char_device(),
+        "-r" => sys::access(&path, libc::R_OK),
+        "-w" => sys::access(&path, libc::W_OK),
+        "-x" => sys::access(&path, libc::X_OK),
+        "-g" => mode & 0o2000 != 0,
+        "-u" => mode & 0o4000 != 0,
+        "-k" => mode & 0o1000 != 0,
+        _ => return Err(format!("{}: unary operator expected", op)),
+    })
+}
+
+// Applies an operator that compares two operands
+fn binary(left: &OsStr, op: &str, right: &OsStr, shell: &Shell) -> Result<bool, String> {
+    match op {
+        "=" | "==" => Ok(left == right),
+        "!=" => Ok(left != right),
+        "<" => Ok(left < right),
+        ">" => Ok(left > right),
+        "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+            let (left, right) = (integer(left)?, integer(right)?);
+            Ok(match op {
+                "-eq" => left == right,
+                "-ne" => left != right,
+                "-lt" => left < right,
+                "-le" => left <= right,
+                "-gt" => left > right,
+                _ => left >= right,
+            })
+        }
+        "-nt" | "-ot" => {
+            let modified = |path: &OsStr| fs::metadata(resolve(path, shell)).and_then(|meta| meta.modified()).ok();
+            let (left, right) = (modified(left), modified(right));
+
+            // A file that exists is newer than one that doesn't
+            Ok(match (left, right) {
+                (Some(left), Some(right)) => (op == "-nt" && left > right) || (op == "-ot" && left < right),
+                (Some(_), None) => op == "-nt",
+                (None, Some(_)) => op == "-ot",
+                (None, None) => false,
+            })
+        }
+        "-ef" => {
+            let meta = |path: &OsStr| fs::metadata(resolve(path, shell)).ok();
+            Ok(match (meta(left), meta(right)) {
+                (Some(left), Some(right)) => left.dev() == right.dev() && left.ino() == right.ino(),
+                _ => false,
+            })
+        }
+        _ => Err(format!("{}: binary operator expected", op)),
+    }
+}
+
+// Parses an operand of an integer comparison, which may have blanks
+// around it
+fn integer(operand: &OsStr) -> Result<i64, String> {
+    operand
+        .to_str()
+        .and_then(|s| s.trim().parse().ok())
+        .ok_or_else(|| format!("{}: integer expression expected", operand.to_string_lossy()))
+}
+
+// Resolves a path against the shell's working directory
+fn resolve(path: &OsStr, shell: &Shell) -> PathBuf {
+    shell.working_dir().join(path)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::thread;
+    use std::time::Duration;
+    use parser::lexer::tokenize;
+    use parser::syntax::parse;
+    use parser::ast::Command;
+
+    // Runs `test` with the given operands
+    fn run_test(args: &[&str], shell: &Shell) -> i32 {
+        let mut line = vec![OsString::from("test")];
+        line.extend(args.iter().map(OsString::from));
+        test(&line, shell)
+    }
+
+    // Evaluates a `[[ ]]` command
+    fn run_cond(line: &str, shell: &mut Shell) -> Result<bool, String> {
+        match parse(tokenize(line).unwrap()).unwrap().remove(0) {
+            Command::Cond(expr) => eval_cond(&expr, shell),
+            command => panic!("not a conditional: {:?}", command),
+        }
+    }
+
+    // Tests string and integer comparisons
+    #[test]
+    fn test_compare() {
+        let shell = Shell::default();
+        assert_eq!(run_test(&["abc", "=", "abc"], &shell), 0);
+        assert_eq!(run_test(&["abc", "!=", "abc"], &shell), 1);
+        assert_eq!(run_test(&["a", "<", "b"], &shell), 0);
+        assert_eq!(run_test(&[" 10 ", "-gt", "9"], &shell), 0);
+        assert_eq!(run_test(&["-3", "-ge", "-2"], &shell), 1);
+        assert_eq!(run_test(&["x", "-eq", "1"], &shell), 2);
+    }
+
+    // Tests the special cases for few operands
+    #[test]
+    fn test_operand_count() {
+        let shell = Shell::default();
+        assert_eq!(run_test(&[], &shell), 1);
+        assert_eq!(run_test(&[""], &shell), 1);
+        assert_eq!(run_test(&["-n"], &shell), 0);
+        assert_eq!(run_test(&["!", ""], &shell), 0);
+        assert_eq!(run_test(&["-z", ""], &shell), 0);
+        assert_eq!(run_test(&["!", "=", "x"], &shell), 1);
+        assert_eq!(run_test(&["(", "x", ")"], &shell), 0);
+        assert_eq!(run_test(&["!", "a", "=", "a"], &shell), 1);
+        assert_eq!(run_test(&["x", "y"], &shell), 2);
+    }
+
+    // Tests `!`, `-a`, `-o` and parentheses in longer expressions
+    #[test]
+    fn test_logic() {
+        let shell = Shell::default();
+        assert_eq!(run_test(&["a", "-o", "", "-a", ""], &shell), 0);
+        assert_eq!(run_test(&["(", "a", "-o", "", ")", "-a", ""], &shell), 1);
+        assert_eq!(run_test(&["!", "-z", "a", "-a", "1", "-lt", "2"], &shell), 0);
+        assert_eq!(run_test(&["(", "a", "-a", "b"], &shell), 2);
+        assert_eq!(run_test(&["a", "b", "c", "d", "e"], &shell), 2);
+    }
+
+    // Tests that `[` needs its closing bracket
+    #[test]
+    fn test_bracket() {
+        let shell = Shell::default();
+        let line = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(test(&line(&["[", "a", "=", "a", "]"]), &shell), 0);
+        assert_eq!(test(&line(&["[", "]"]), &shell), 1);
+        assert_eq!(test(&line(&["[", "a"]), &shell), 2);
+    }
+
+    // Tests the file tests, relative to the shell's working directory
+    #[test]
+    fn test_files() {
+        let dir = env::temp_dir().join("ensh_cond_files");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("old"), "x").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        fs::write(dir.join("new"), "").unwrap();
+        let _ = fs::remove_file(dir.join("link"));
+        ::std::os::unix::fs::symlink("old", dir.join("link")).unwrap();
+
+        let mut shell = Shell::default();
+        shell.change_working_dir(&dir);
+        assert_eq!(run_test(&["-f", "old"], &shell), 0);
+        assert_eq!(run_test(&["-d", "old"], &shell), 1);
+        assert_eq!(run_test(&["-d", "/"], &shell), 0);
+        assert_eq!(run_test(&["-s", "old"], &shell), 0);
+        assert_eq!(run_test(&["-s", "new"], &shell), 1);
+        assert_eq!(run_test(&["-L", "link"], &shell), 0);
+        assert_eq!(run_test(&["-L", "old"], &shell), 1);
+        assert_eq!(run_test(&["-x", "old"], &shell), 1);
+        assert_eq!(run_test(&["-e", ""], &shell), 1);
+        assert_eq!(run_test(&["new", "-nt", "old"], &shell), 0);
+        assert_eq!(run_test(&["old", "-nt", "new"], &shell), 1);
+        assert_eq!(run_test(&["old", "-nt", "missing"], &shell), 0);
+        assert_eq!(run_test(&["old", "-ot", "new"], &shell), 0);
+        assert_eq!(run_test(&["link", "-ef", "old"], &shell), 0);
+    }
+
+    // Tests `[[ ]]`, where `==` matches patterns and `=~` regular
+    // expressions, and words aren't split
+    #[test]
+    fn test_cond() {
+        let mut shell = Shell::default();
+        shell.vars_mut().set("ensh_cond_v", OsString::from("foo bar"));
+        assert_eq!(run_cond("[[ $ensh_cond_v == foo* ]]", &mut shell), Ok(true));
+        assert_eq!(run_cond("[[ $ensh_cond_v == 'foo*' ]]", &mut shell), Ok(false));
+        assert_eq!(run_cond("[[ $ensh_cond_v != *z ]]", &mut shell), Ok(true));
+        assert_eq!(run_cond("[[ $ensh_cond_v =~ ^fo+\\ (bar|baz)$ ]]", &mut shell), Ok(true));
+        assert_eq!(run_cond("[[ a.c =~ 'a.c' && ! abc =~ 'a.c' ]]", &mut shell), Ok(true));
+        assert_eq!(run_cond("[[ -n $ensh_cond_unset || ( 2 -lt 10 && b > a ) ]]", &mut shell), Ok(true));
+        assert_eq!(run_cond("[[ $ensh_cond_unset ]]", &mut shell), Ok(false));
+        assert!(run_cond("[[ a =~ ( ]]", &mut shell).is_err());
+    }
+}
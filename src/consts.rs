@@ -1,17 +1,17 @@
-/// consts.rs
-///
-/// # Author
-/// Afnan Enayet
-///
-/// # Summary
-/// The `consts.rs` file contains global/static constants to be used
-/// throughout the entire program. This file shall not contain any functions
-/// or anything that is not a static or constant declaration of a variable
-///
+//! consts.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Summary
+//! The `consts.rs` file contains global/static constants to be used
+//! throughout the entire program. This file shall not contain any functions
+//! or anything that is not a static or constant declaration of a variable
+//!
 
 // Default paths to be used for the program when no configuration file has been
 // specified (used to find binaries)
-pub const DEFAULT_PATHS: &'static [&'static str] = &[
+pub const DEFAULT_PATHS: &[&str] = &[
     "/usr/bin",
     "/usr/local/bin",
     "/usr/local/sbin",
@@ -20,7 +20,27 @@ pub const DEFAULT_PATHS: &'static [&'static str] = &[
 ];
 
 // The shell prompt that precedes each input prompt
-pub const SHELL_PROMPT: &'static str = ">";
+pub const SHELL_PROMPT: &str = ">";
+
+// The prompt shown while reading the rest of a command that was cut off at
+// the end of a line, like an `if` without its `fi`
+pub const CONTINUATION_PROMPT: &str = ">";
 
 // Default file path for config file
-pub const DEF_CONFIG_FNAME: &'static str = ".ensh_config";
+pub const DEF_CONFIG_FNAME: &str = ".ensh_config";
+
+// Environment variable that can point the shell at a config file
+pub const CONFIG_ENV_VAR: &str = "ENSH_CONFIG";
+
+// Directory and file name of the config file under `$XDG_CONFIG_HOME`
+pub const XDG_CONFIG_DIR: &str = "ensh";
+pub const XDG_CONFIG_FNAME: &str = "config";
+
+// Directory under `$XDG_DATA_HOME` that holds the shell's data files, and
+// the file in it that stores the directories visited for `j`
+pub const DATA_DIR_NAME: &str = "ensh";
+pub const FRECENCY_FNAME: &str = "dirs";
+
+// The command that is run, with the command line, when a command can't be
+// found
+pub const NOT_FOUND_HANDLER: &str = "command_not_found_handler";
@@ -0,0 +1,1025 @@
+//! exec.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Summary
+//! The exec module runs the syntax tree built by the parser. Compound
+//! commands are walked here; simple commands have their words expanded and
+//! are handed to `cmd_dispatch`, which decides whether they name a
+//! function, a builtin or a binary. Every command gives an exit status,
+//! where zero means success
+
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use shell::{Shell, Unwind};
+use parser::ast::{Case, Command, For, If, List, Loop};
+use parser::expand::{
+    expand_arithmetic, expand_array, expand_assignment, expand_key, expand_pattern, expand_string, expand_words,
+    ExpandError,
+};
+use parser::glob::Pattern;
+use parser::lexer::quote;
+use interface::print_err;
+use vars::{split_subscript, valid_name, Value};
+use cmd_dispatch;
+use cond;
+use redirect;
+use sys::{self, Fork};
+
+// The pipes of process substitutions are moved at least this high, out of
+// the way of the ones scripts use
+const SUBSTITUTION_FD_MIN: RawFd = 60;
+
+// What `set -x` puts before each command it prints when `$PS4` isn't set
+const DEFAULT_PS4: &str = "+ ";
+
+/// Runs the commands of a list in order, recording the status of each one
+/// as `$?`. Stops early if the shell is unwinding (because of `return`,
+/// `break`, `continue` or `set -e`), and runs nothing after `set -n`
+/// unless the shell is interactive.
+/// Returns the status of the last command that ran
+pub fn run_list(list: &List, shell: &mut Shell) -> i32 {
+    let mut status = 0;
+
+    for command in list {
+        if shell.options().noexec && !shell.interactive() {
+            break;
+        }
+        status = run_command(command, shell);
+        shell.set_status(status);
+        cmd_dispatch::run_pending_traps(shell);
+
+        if shell.unwinding().is_some() {
+            break;
+        }
+    }
+    status
+}
+
+/// Runs a single command and returns its status. Process substitutions
+/// started for it are finished once it is done
+pub fn run_command(command: &Command, shell: &mut Shell) -> i32 {
+    let substitutions = shell.substitution_count();
+
+    let status = match *command {
+        Command::Simple(ref words) => run_simple(words, shell),
+        Command::Group(ref list) => run_list(list, shell),
+        Command::Subshell(ref list) => run_subshell(list, shell),
+        Command::If(ref command) => run_if(command, shell),
+        Command::Loop(ref command) => run_loop(command, shell),
+        Command::For(ref command) => run_for(command, shell),
+        Command::Case(ref command) => run_case(command, shell),
+        Command::Cond(ref expr) => match cond::eval_cond(expr, shell) {
+            Ok(result) => cond::status(result),
+            Err(e) => {
+                print_err(&format!("ensh: {}", e));
+                2
+            }
+        },
+        Command::Arith(ref expr) => match expand_arithmetic(expr, shell) {
+            Ok(value) => cond::status(value != 0),
+            Err(e) => expansion_failed(&e, shell),
+        },
+        Command::Redirected(ref command, ref redirects) => match redirect::apply(redirects, shell) {
+            Ok(_applied) => run_command(command, shell),
+            Err(message) => {
+                print_err(&format!("ensh: {}", message));
+                1
+            }
+        },
+        Command::Pipeline(ref commands) => run_pipeline(commands, shell),
+        Command::Not(ref command) => run_not(command, shell),
+        Command::And(ref first, ref second) => run_and_or(first, second, true, shell),
+        Command::Or(ref first, ref second) => run_and_or(first, second, false, shell),
+        Command::FunctionDef(ref def) => {
+            shell.define_function(&def.name, Rc::clone(&def.body));
+            0
+        }
+    };
+
+    shell.finish_substitutions(substitutions);
+
+    // With `set -e` a failure ends the shell, unless it is part of a
+    // condition. For `&&` and `||` only the last command counts, which
+    // was already checked when it ran, and a negated pipeline is tested
+    // like a condition
+    let tested = matches!(*command, Command::And(..) | Command::Or(..) | Command::Not(_));
+    if status != 0 && !tested && shell.condition_depth() == 0 && shell.unwinding().is_none() {
+        // The `ERR` trap goes off once, for the command that failed rather
+        // than for each compound command around it
+        let failed = matches!(*command, Command::Simple(_) | Command::Pipeline(_) | Command::Cond(_) | Command::Arith(_));
+        if failed {
+            shell.set_status(status);
+            cmd_dispatch::run_trap("ERR", shell);
+        }
+        if shell.options().errexit {
+            shell.set_unwinding(Some(Unwind::Exit(status)));
+        }
+    }
+    status
+}
+
+// Runs a simple command. A command made up only of `name=value` words sets
+// those variables, anything else is expanded and run. `name=value` words
+// in front of a command only set the variables for that command
+fn run_simple(words: &[String], shell: &mut Shell) -> i32 {
+    cmd_dispatch::run_trap("DEBUG", shell);
+
+    // The assignments given to `declare` and `local` are expanded like
+    // other assignments, not like arguments
+    if let Some(builtin) = words.first().filter(|word| *word == "declare" || *word == "local") {
+        if shell.function(builtin).is_none() {
+            return cmd_dispatch::declare(builtin, &words[1..], shell);
+        }
+    }
+
+    let prefix = words.iter().take_while(|word| split_assignment(word).is_some()).count();
+    if prefix == words.len() {
+        return assign(words, shell);
+    }
+
+    let args = match expand_words(&words[prefix..], shell) {
+        Ok(args) => args,
+        Err(e) => return expansion_failed(&e, shell),
+    };
+
+    // With no command left after expansion, the assignments are kept
+    if args.is_empty() {
+        return assign(&words[..prefix], shell);
+    }
+
+    let vars = match expand_prefix(&words[..prefix], shell) {
+        Ok(vars) => vars,
+        Err(e) => return expansion_failed(&e, shell),
+    };
+    if shell.options().xtrace {
+        let mut traced: Vec<OsString> = vars.iter().map(|(name, value)| assignment_text(name, value)).collect();
+        traced.extend_from_slice(&args);
+        trace(&traced, shell);
+    }
+
+    if vars.is_empty() {
+        cmd_dispatch::run(&args, shell)
+    } else {
+        cmd_dispatch::run_with_vars(&args, &vars, shell)
+    }
+}
+
+// Expands the `name=value` words in front of a command into the names and
+// values of the variables they set for it. A value is expanded like the
+// value of any other assignment, and an array element can't be set this way
+fn expand_prefix(words: &[String], shell: &mut Shell) -> Result<Vec<(String, OsString)>, ExpandError> {
+    let mut vars = Vec::new();
+
+    for word in words {
+        if let Some((name, value)) = split_assignment(word) {
+            if !valid_name(name) {
+                return Err(ExpandError::Parameter(format!("{}: cannot set an element for one command", name)));
+            }
+            let (value, _status) = expand_assignment(value, shell)?;
+            vars.push((name.to_string(), value));
+        }
+    }
+    Ok(vars)
+}
+
+// Returns `name=value` as one word, for printing with `set -x`
+fn assignment_text(name: &str, value: &OsString) -> OsString {
+    let mut text = OsString::from(format!("{}=", name));
+    text.push(value);
+    text
+}
+
+// Prints a command that is about to run to standard error for `set -x`,
+// with the expansion of `$PS4` in front. Words are quoted if they need to
+// be to read back the same
+fn trace(args: &[OsString], shell: &mut Shell) {
+    let prefix = match shell.vars().get("PS4") {
+        Some(ps4) => expand_string(&ps4.to_string_lossy(), shell).unwrap_or(ps4),
+        None => OsString::from(DEFAULT_PS4),
+    };
+
+    let words: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if !arg.is_empty() && arg.chars().all(|c| c.is_alphanumeric() || "-_./=:,+%@^".contains(c)) {
+                arg.into_owned()
+            } else {
+                quote(&arg)
+            }
+        })
+        .collect();
+    print_err(&format!("{}{}", prefix.to_string_lossy(), words.join(" ")));
+}
+
+// Sets the variables from a list of assignment words. The values are
+// expanded, but not split into fields or globbed. The status is that of the
+// last command substitution in the values, or zero if there were none
+fn assign(words: &[String], shell: &mut Shell) -> i32 {
+    let mut status = 0;
+
+    for word in words {
+        if let Some((name, value)) = split_assignment(word) {
+            match assign_word(name, value, shell) {
+                Ok(Some(substituted)) => status = substituted,
+                Ok(None) => {}
+                Err(e) => return expansion_failed(&e, shell),
+            }
+
+            if shell.options().xtrace {
+                let traced = match shell.vars().value(name) {
+                    Some(Value::Scalar(value)) => OsString::from(format!("{}={}", name, value.to_string_lossy())),
+                    _ => OsString::from(word.as_str()),
+                };
+                trace(&[traced], shell);
+            }
+        }
+    }
+    status
+}
+
+/// Expands the value of an assignment and assigns it. The name can be an
+/// element of an array, `name[subscript]`, and the value can be a whole
+/// array, `(a b c)`. Returns the status of the last command substitution
+/// in the value, if there was one
+pub fn assign_word(name: &str, value: &str, shell: &mut Shell) -> Result<Option<i32>, ExpandError> {
+    if let Some((array, subscript)) = split_subscript(name) {
+        let key = expand_key(array, subscript, shell)?;
+        let (value, status) = expand_assignment(value, shell)?;
+        if !shell.vars_mut().set_element(array, key, value) {
+            return Err(ExpandError::Parameter(format!("{}: bad array subscript", name)));
+        }
+        return Ok(status);
+    }
+
+    let assoc = shell.vars().is_assoc(name);
+    match expand_value(value, assoc, shell)? {
+        (Value::Scalar(value), status) => {
+            shell.vars_mut().set(name, value);
+            Ok(status)
+        }
+        (array, status) => {
+            shell.vars_mut().set_value(name, array);
+            Ok(status)
+        }
+    }
+}
+
+/// Expands the value of an assignment, which is an array if it is in
+/// parentheses. `assoc` is whether the array is associative. Also returns
+/// the status of the last command substitution, if there was one
+pub fn expand_value(value: &str, assoc: bool, shell: &mut Shell) -> Result<(Value, Option<i32>), ExpandError> {
+    if value.len() > 1 && value.starts_with('(') && value.ends_with(')') {
+        let array = expand_array(&value[1..value.len() - 1], assoc, shell)?;
+        return Ok((array, None));
+    }
+
+    let (value, status) = expand_assignment(value, shell)?;
+    Ok((Value::Scalar(value), status))
+}
+
+// Runs `first && second` (if `and` is set) or `first || second`. The
+// second command only runs if the first one succeeded or failed
+// respectively. The status is that of the last command that ran
+fn run_and_or(first: &Command, second: &Command, and: bool, shell: &mut Shell) -> i32 {
+    let status = as_condition(shell, |shell| run_command(first, shell));
+    if shell.unwinding().is_some() || (status == 0) != and {
+        return status;
+    }
+
+    shell.set_status(status);
+    run_command(second, shell)
+}
+
+// Runs a negated pipeline as a condition, so `set -e` leaves it alone.
+// The status is 1 if the pipeline succeeded and 0 if it failed
+fn run_not(command: &Command, shell: &mut Shell) -> i32 {
+    let status = as_condition(shell, |shell| run_command(command, shell));
+    if shell.unwinding().is_some() {
+        return status;
+    }
+    cond::status(status != 0)
+}
+
+// Runs the body of the first branch whose condition succeeds. The status is
+// that of the body, or zero if no branch ran
+fn run_if(command: &If, shell: &mut Shell) -> i32 {
+    for (condition, body) in &command.branches {
+        let status = as_condition(shell, |shell| run_list(condition, shell));
+        if shell.unwinding().is_some() {
+            return status;
+        }
+        if status == 0 {
+            return run_list(body, shell);
+        }
+    }
+
+    match command.otherwise {
+        Some(ref body) => run_list(body, shell),
+        None => 0,
+    }
+}
+
+// Runs a `while` or `until` loop. The status is that of the last time the
+// body ran, or zero if it never did
+fn run_loop(command: &Loop, shell: &mut Shell) -> i32 {
+    let mut status = 0;
+    shell.set_loop_depth(shell.loop_depth() + 1);
+
+    loop {
+        let condition = as_condition(shell, |shell| run_list(&command.condition, shell));
+        if !keep_looping(shell) || (condition == 0) == command.until {
+            break;
+        }
+
+        status = run_list(&command.body, shell);
+        if !keep_looping(shell) {
+            break;
+        }
+    }
+
+    shell.set_loop_depth(shell.loop_depth() - 1);
+    status
+}
+
+// Runs a `for` loop, setting the variable to each of the expanded words in
+// turn. The status is that of the last time the body ran, or zero if it
+// never did
+fn run_for(command: &For, shell: &mut Shell) -> i32 {
+    let words = match command.words {
+        Some(ref words) => match expand_words(words, shell) {
+            Ok(words) => words,
+            Err(e) => return expansion_failed(&e, shell),
+        },
+        None => shell.vars().args().to_vec(),
+    };
+
+    let mut status = 0;
+    shell.set_loop_depth(shell.loop_depth() + 1);
+
+    for word in words {
+        shell.vars_mut().set(&command.name, word);
+        status = run_list(&command.body, shell);
+        if !keep_looping(shell) {
+            break;
+        }
+    }
+
+    shell.set_loop_depth(shell.loop_depth() - 1);
+    status
+}
+
+// Runs the body of the first `case` item with a pattern that matches the
+// word. The status is that of the body, or zero if nothing matched
+fn run_case(command: &Case, shell: &mut Shell) -> i32 {
+    let word = match expand_string(&command.word, shell) {
+        Ok(word) => word,
+        Err(e) => return expansion_failed(&e, shell),
+    };
+
+    for item in &command.items {
+        for pattern in &item.patterns {
+            match expand_pattern(pattern, shell) {
+                Ok(pattern) if Pattern::new(&pattern).matches(&word) => return run_list(&item.body, shell),
+                Ok(_) => {}
+                Err(e) => return expansion_failed(&e, shell),
+            }
+        }
+    }
+    0
+}
+
+// Runs the commands of a pipeline at the same time, each in a forked copy
+// of the shell, with the output of each one going to the input of the
+// next. The status is that of the last command, or with `set -o pipefail`
+// that of the last one to fail
+fn run_pipeline(commands: &[Command], shell: &mut Shell) -> i32 {
+    let _ = io::stdout().flush();
+
+    let mut pids = Vec::new();
+    let mut input: Option<File> = None;
+    let mut failure = None;
+
+    for (i, command) in commands.iter().enumerate() {
+        let (reader, writer) = if i + 1 < commands.len() {
+            match sys::pipe() {
+                Ok((reader, writer)) => (Some(reader), Some(writer)),
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+        } else {
+            (None, None)
+        };
+
+        match sys::fork() {
+            Ok(Fork::Child) => {
+                drop(reader);
+                if let Some(input) = input {
+                    let _ = sys::dup2(input.as_raw_fd(), 0);
+                }
+                if let Some(writer) = writer {
+                    let _ = sys::dup2(writer.as_raw_fd(), 1);
+                }
+                run_child(shell, |shell| run_command(command, shell))
+            }
+            Ok(Fork::Parent(pid)) => pids.push(pid),
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+        input = reader;
+    }
+    drop(input);
+
+    let mut status = 0;
+    let mut last = 0;
+    for pid in pids {
+        last = sys::wait(pid).unwrap_or(1);
+        if last != 0 {
+            status = last;
+        }
+    }
+
+    if let Some(e) = failure {
+        print_err(&format!("ensh: {}", e));
+        return 1;
+    }
+    if shell.options().pipefail {
+        status
+    } else {
+        last
+    }
+}
+
+// Runs a list in a forked copy of the shell, so that changes it makes to
+// the shell don't last past it
+fn run_subshell(list: &List, shell: &mut Shell) -> i32 {
+    // Anything still buffered would otherwise be written by both processes
+    let _ = io::stdout().flush();
+
+    let status = sys::fork().and_then(|fork| match fork {
+        Fork::Child => run_child(shell, |shell| run_list(list, shell)),
+        Fork::Parent(pid) => sys::wait(pid),
+    });
+
+    status.unwrap_or_else(|e| {
+        print_err(&format!("ensh: {}", e));
+        1
+    })
+}
+
+/// Runs a list in a forked copy of the shell and collects what it writes to
+/// standard output. Changes the list makes to the shell, like `cd` or
+/// setting variables, only happen in the copy. Returns the output and the
+/// status of the list
+pub fn capture(list: &List, shell: &mut Shell) -> io::Result<(Vec<u8>, i32)> {
+    // Anything still buffered would otherwise be written by both processes
+    io::stdout().flush()?;
+    let (mut reader, writer) = sys::pipe()?;
+
+    match sys::fork()? {
+        Fork::Child => {
+            drop(reader);
+            run_child(shell, |shell| match sys::dup2(writer.as_raw_fd(), 1) {
+                Ok(()) => {
+                    drop(writer);
+                    run_list(list, shell)
+                }
+                Err(e) => {
+                    print_err(&format!("ensh: {}", e));
+                    1
+                }
+            })
+        }
+        Fork::Parent(pid) => {
+            drop(writer);
+            let mut output = Vec::new();
+            let read = reader.read_to_end(&mut output);
+            let status = sys::wait(pid)?;
+            read?;
+            Ok((output, status))
+        }
+    }
+}
+
+/// Runs a list in a forked copy of the shell connected to a pipe, for a
+/// process substitution. `<(list)` (`input` is false) reads what the list
+/// writes, `>(list)` (`input` is true) gives it something to read. Returns
+/// the `/dev/fd` path of the shell's end of the pipe, which stays open
+/// until the command the substitution is part of is done
+pub fn substitute_process(list: &List, input: bool, shell: &mut Shell) -> io::Result<PathBuf> {
+    io::stdout().flush()?;
+    let (reader, writer) = sys::pipe()?;
+    let (ours, theirs, fd) = if input { (writer, reader, 0) } else { (reader, writer, 1) };
+
+    match sys::fork()? {
+        Fork::Child => {
+            drop(ours);
+            shell.forget_substitutions();
+            run_child(shell, |shell| match sys::dup2(theirs.as_raw_fd(), fd) {
+                Ok(()) => {
+                    drop(theirs);
+                    run_list(list, shell)
+                }
+                Err(e) => {
+                    print_err(&format!("ensh: {}", e));
+                    1
+                }
+            })
+        }
+        Fork::Parent(pid) => {
+            drop(theirs);
+
+            // The command has to inherit the pipe to open it by its path
+            let inherited = sys::dup_inheritable(ours.as_raw_fd(), SUBSTITUTION_FD_MIN);
+            drop(ours);
+            let inherited = match inherited {
+                Ok(fd) => unsafe { File::from_raw_fd(fd) },
+                Err(e) => {
+                    let _ = sys::wait(pid);
+                    return Err(e);
+                }
+            };
+
+            let path = PathBuf::from(format!("/dev/fd/{}", inherited.as_raw_fd()));
+            shell.add_substitution(pid, inherited);
+            Ok(path)
+        }
+    }
+}
+
+// Runs commands in a forked copy of the shell, then ends the copy with
+// their status, or the one given to `exit`. The shell's `EXIT` trap is for
+// when the shell itself exits, but one set inside the copy runs when it
+// does
+fn run_child<F: FnOnce(&mut Shell) -> i32>(shell: &mut Shell, run: F) -> ! {
+    shell.set_trap("EXIT", None);
+    let mut status = run(shell);
+    if let Some(Unwind::Exit(code)) = shell.unwinding() {
+        status = code;
+    }
+
+    shell.set_unwinding(None);
+    shell.set_status(status);
+    cmd_dispatch::run_trap("EXIT", shell);
+    let _ = io::stdout().flush();
+    sys::exit_child(status)
+}
+
+// Deals with a `break` or `continue` that reached the loop that is running.
+// Returns whether the loop should go around again
+fn keep_looping(shell: &mut Shell) -> bool {
+    match shell.unwinding() {
+        None => true,
+        Some(Unwind::Break(n)) => {
+            shell.set_unwinding(if n > 1 { Some(Unwind::Break(n - 1)) } else { None });
+            false
+        }
+        Some(Unwind::Continue(n)) if n > 1 => {
+            shell.set_unwinding(Some(Unwind::Continue(n - 1)));
+            false
+        }
+        Some(Unwind::Continue(_)) => {
+            shell.set_unwinding(None);
+            true
+        }
+        Some(Unwind::Return) | Some(Unwind::Exit(_)) => false,
+    }
+}
+
+// Runs something whose status is tested, like the condition of an `if`,
+// where a failure doesn't end the shell under `set -e`
+fn as_condition<F: FnOnce(&mut Shell) -> i32>(shell: &mut Shell, run: F) -> i32 {
+    shell.set_condition_depth(shell.condition_depth() + 1);
+    let status = run(shell);
+    shell.set_condition_depth(shell.condition_depth() - 1);
+    status
+}
+
+// Reports a word that couldn't be expanded, returning the status of the
+// command it was part of. A parameter error, like an unbound variable with
+// `set -u` or `${name?message}`, also ends a shell that isn't interactive
+fn expansion_failed(e: &ExpandError, shell: &mut Shell) -> i32 {
+    print_err(&format!("ensh: {}", e));
+
+    if let ExpandError::Parameter(_) = *e {
+        if !shell.interactive() {
+            shell.set_unwinding(Some(Unwind::Exit(1)));
+        }
+    }
+    1
+}
+
+/// Splits a `name=value` word into the name and the (unexpanded) value.
+/// The name can be an element of an array, `name[subscript]`. Returns
+/// `None` if the word isn't an assignment
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let name_len = word.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(word.len());
+    let eq = match word[name_len..].strip_prefix('[') {
+        Some(subscript) => name_len + subscript.find(']')? + 2,
+        None => name_len,
+    };
+
+    if valid_name(&word[..name_len]) && word[eq..].starts_with('=') {
+        Some((&word[..eq], &word[eq + 1..]))
+    } else {
+        None
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+    use parser::lexer::tokenize;
+    use parser::syntax::parse;
+
+    // Parses and runs a line, returning its status
+    fn run(line: &str, shell: &mut Shell) -> i32 {
+        run_list(&parse(tokenize(line).unwrap()).unwrap(), shell)
+    }
+
+    // Tests which words are assignments
+    #[test]
+    fn test_split_assignment() {
+        assert_eq!(split_assignment("a=b=c"), Some(("a", "b=c")));
+        assert_eq!(split_assignment("_x="), Some(("_x", "")));
+        assert_eq!(split_assignment("=x"), None);
+        assert_eq!(split_assignment("'a'=x"), None);
+        assert_eq!(split_assignment("ls"), None);
+    }
+
+    // Tests that assignments set variables without splitting the value
+    #[test]
+    fn test_assign() {
+        let mut shell = Shell::default();
+        assert_eq!(run("ensh_exec_a='x  y' ensh_exec_b=$ensh_exec_a!", &mut shell), 0);
+        assert_eq!(shell.vars().get("ensh_exec_b"), Some(OsString::from("x  y!")));
+    }
+
+    // Tests that assignments in front of a command only last for it: a
+    // builtin or function sees them as variables, a binary in its
+    // environment
+    #[test]
+    fn test_prefix_assign() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+
+        assert_eq!(run("IFS=: read a b <<< 'x:y:z'", &mut shell), 0);
+        assert_eq!(shell.vars().get("a"), Some(OsString::from("x")));
+        assert_eq!(shell.vars().get("b"), Some(OsString::from("y:z")));
+        assert_eq!(shell.vars().get("IFS"), None);
+
+        assert_eq!(run("ensh_exec_foo=old; x=$(ensh_exec_foo=bar env)", &mut shell), 0);
+        assert!(shell.vars().get("x").unwrap().to_str().unwrap().lines().any(|line| line == "ensh_exec_foo=bar"));
+        assert_eq!(run("x=$(env)", &mut shell), 0);
+        assert!(!shell.vars().get("x").unwrap().to_str().unwrap().contains("ensh_exec_foo"));
+        assert_eq!(shell.vars().get("ensh_exec_foo"), Some(OsString::from("old")));
+
+        assert_eq!(run("f() { y=$ensh_exec_foo; }; ensh_exec_foo=1 ensh_exec_foo=2 f", &mut shell), 0);
+        assert_eq!(shell.vars().get("y"), Some(OsString::from("2")));
+        assert_eq!(shell.vars().get("ensh_exec_foo"), Some(OsString::from("old")));
+
+        assert_eq!(run("ensh_exec_kept=1 $ensh_exec_empty", &mut shell), 0);
+        assert_eq!(shell.vars().get("ensh_exec_kept"), Some(OsString::from("1")));
+    }
+
+    // Tests that each command's status is recorded and the last one is
+    // returned
+    #[test]
+    fn test_run_list_status() {
+        let mut shell = Shell::default();
+        assert_eq!(run("ensh_exec_missing_command; ensh_exec_s=$?", &mut shell), 0);
+        assert_eq!(shell.vars().get("ensh_exec_s"), Some(OsString::from("127")));
+        assert_eq!(run("{ ensh_exec_s=1; ensh_exec_missing_command; }", &mut shell), 127);
+        assert_eq!(shell.status(), 127);
+    }
+
+    // Tests that only the first branch whose condition succeeds runs
+    #[test]
+    fn test_run_if() {
+        let mut shell = Shell::default();
+        let line = "if ensh_exec_missing; then r=1; elif r=2; then r=$r-3; else r=4; fi";
+        assert_eq!(run(line, &mut shell), 0);
+        assert_eq!(shell.vars().get("r"), Some(OsString::from("2-3")));
+        assert_eq!(run("if ensh_exec_missing; then r=1; fi", &mut shell), 0);
+    }
+
+    // Tests `for` loops over words and over the positional parameters
+    #[test]
+    fn test_run_for() {
+        let mut shell = Shell::default();
+        assert_eq!(run("s=; for x in a 'b c' d; do s=$s-$x; done", &mut shell), 0);
+        assert_eq!(shell.vars().get("s"), Some(OsString::from("-a-b c-d")));
+
+        shell.vars_mut().push_frame(vec![OsString::from("1"), OsString::from("2")]);
+        assert_eq!(run("s=; for x; do s=$s$x; done", &mut shell), 0);
+        assert_eq!(shell.vars().get("s"), Some(OsString::from("12")));
+    }
+
+    // Tests `while` and `until` loops with `break` and `continue`
+    #[test]
+    fn test_run_loop() {
+        let mut shell = Shell::default();
+        let line = "s=; while true; do for x in a b c; do
+            case $x in b) continue;; c) break 2;; esac; s=$s$x
+        done; done";
+        assert_eq!(run(line, &mut shell), 0);
+        assert_eq!(shell.vars().get("s"), Some(OsString::from("a")));
+        assert_eq!(shell.loop_depth(), 0);
+
+        assert_eq!(run("n=; until case $n in xxx) ;; *) false;; esac; do n=x$n; done", &mut shell), 0);
+        assert_eq!(shell.vars().get("n"), Some(OsString::from("xxx")));
+    }
+
+    // Tests that the first matching `case` pattern wins and that quoted
+    // parts of patterns only match literally
+    #[test]
+    fn test_run_case() {
+        let mut shell = Shell::default();
+        let line = "for w in abc '*' x; do case $w in '*') r=$r-star;; a*|x) r=$r-$w;; esac; done";
+        assert_eq!(run(line, &mut shell), 0);
+        assert_eq!(shell.vars().get("r"), Some(OsString::from("-abc-star-x")));
+    }
+
+    // Tests that `&&` and `||` only run the second command when they should
+    #[test]
+    fn test_run_and_or() {
+        let mut shell = Shell::default();
+        assert_eq!(run("false && a=1 || b=$?", &mut shell), 0);
+        assert_eq!(shell.vars().get("a"), None);
+        assert_eq!(shell.vars().get("b"), Some(OsString::from("1")));
+        assert_eq!(run("true || c=1", &mut shell), 0);
+        assert_eq!(shell.vars().get("c"), None);
+        assert_eq!(run("[[ x == y ]] && true", &mut shell), 1);
+    }
+
+    // Tests that `!` inverts the status of a pipeline, and that a negated
+    // failure doesn't end the shell under `set -e`
+    #[test]
+    fn test_run_not() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+
+        assert_eq!(run("if ! false; then r=neg; else r=pos; fi", &mut shell), 0);
+        assert_eq!(shell.vars().get("r"), Some(OsString::from("neg")));
+        assert_eq!(run("! true; s=$?", &mut shell), 0);
+        assert_eq!(shell.vars().get("s"), Some(OsString::from("1")));
+        assert_eq!(run("! sh -c 'exit 3' | false", &mut shell), 0);
+        assert_eq!(run("! ! true", &mut shell), 0);
+
+        shell.options_mut().errexit = true;
+        assert_eq!(run("! true; x=1; ! false && false || x=2", &mut shell), 0);
+        assert_eq!(shell.unwinding(), None);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("2")));
+    }
+
+    // Tests that a command substitution runs in a copy of the shell, and
+    // that its status is the status of an assignment
+    #[test]
+    fn test_substitution_status() {
+        let mut shell = Shell::default();
+        assert_eq!(run("x=$(ensh_exec_inner=1; false)", &mut shell), 1);
+        assert_eq!(shell.vars().get("x"), Some(OsString::new()));
+        assert_eq!(shell.vars().get("ensh_exec_inner"), None);
+        assert_eq!(run("x=$(false) y=$(true)", &mut shell), 0);
+    }
+
+    // Tests that `(( ))` succeeds when its expression isn't 0, and that
+    // `$(( ))` expands parameters and substitutions before evaluating
+    #[test]
+    fn test_run_arith() {
+        let mut shell = Shell::default();
+        assert_eq!(run("i=0; while (( i < 5 )); do ((i++)); done", &mut shell), 0);
+        assert_eq!(shell.vars().get("i"), Some(OsString::from("5")));
+        assert_eq!(run("(( i - 5 ))", &mut shell), 1);
+        assert_eq!(run("(( 1 / 0 ))", &mut shell), 1);
+
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+
+        assert_eq!(run("n=$(( $i * \"$(echo 2)\" + i )) m=\"$((0x10))\"", &mut shell), 0);
+        assert_eq!(shell.vars().get("n"), Some(OsString::from("15")));
+        assert_eq!(shell.vars().get("m"), Some(OsString::from("16")));
+    }
+
+    // Tests redirecting output to files and reading it back, and that the
+    // shell's own file descriptors are back to normal afterwards
+    #[test]
+    fn test_run_redirect() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+        let dir = std::env::temp_dir().join(format!("ensh-redirect-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        shell.vars_mut().set("dir", dir.clone().into_os_string());
+
+        assert_eq!(run("printf a >$dir/out; printf b >>$dir/out; x=$(cat <$dir/out)", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("ab")));
+        assert_eq!(run("cat $dir/missing 2>$dir/err", &mut shell), 1);
+        assert!(std::fs::metadata(dir.join("err")).unwrap().len() > 0);
+        assert_eq!(run("cat <$dir/missing", &mut shell), 1);
+        assert_eq!(run("cat <$dir/*", &mut shell), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Tests that here-documents expand unless their delimiter is quoted, and
+    // that here-strings get a line break on the end
+    #[test]
+    fn test_run_here_doc() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+        shell.vars_mut().set("v", OsString::from("value"));
+
+        assert_eq!(run("x=$(cat <<EOF\n$v \\$v $((1 + 1))\n'a'\nEOF\n)", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("value $v 2\n'a'")));
+        assert_eq!(run("x=$(cat <<-\"EOF\"\n\t$v\n\tEOF\n)", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("$v")));
+        assert_eq!(run("x=$(wc -l <<<\"$v\")", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("1")));
+    }
+
+    // Tests that a subshell's changes stay in the subshell, while a group's
+    // happen in the shell itself, and that both take redirections
+    #[test]
+    fn test_run_subshell() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+
+        assert_eq!(run("x=1; (x=2; sh -c 'exit 3')", &mut shell), 3);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("1")));
+        assert_eq!(run("{ x=2; false; }", &mut shell), 1);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("2")));
+
+        assert_eq!(run("x=$({ printf a; printf b >&2; } 2>&1; (printf c; printf d) >/dev/null)", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("ab")));
+        assert_eq!(run("x=$( (cat; cat) <<EOF\n1\nEOF\n)", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("1")));
+    }
+
+    // Tests that process substitutions give commands a path to read from
+    // or write to, and that their processes are done once the command is
+    #[test]
+    fn test_run_process_substitution() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+        let file = std::env::temp_dir().join(format!("ensh-process-{}", std::process::id()));
+        shell.vars_mut().set("file", file.clone().into_os_string());
+
+        assert_eq!(run("x=$(cat <(printf a) < <(printf b) -)", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("ab")));
+        assert_eq!(run("diff <(printf 'a\\n') <(printf 'b\\n') >/dev/null", &mut shell), 1);
+
+        assert_eq!(run("printf abc > >(tr a-z A-Z >$file)", &mut shell), 0);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "ABC");
+        assert_eq!(shell.substitution_count(), 0);
+
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    // Tests that pipelines connect their commands, and that their status
+    // is that of the last command unless `pipefail` is set
+    #[test]
+    fn test_run_pipeline() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+
+        assert_eq!(run("x=$(printf 'b\\na\\n' | sort | tr a-z A-Z)", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("A\nB")));
+        assert_eq!(run("x=1; x=2 | x=3", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("1")));
+
+        assert_eq!(run("false | true", &mut shell), 0);
+        assert_eq!(run("true | sh -c 'exit 3'", &mut shell), 3);
+        shell.options_mut().pipefail = true;
+        assert_eq!(run("sh -c 'exit 2' | false | true", &mut shell), 1);
+        assert_eq!(run("true | true", &mut shell), 0);
+    }
+
+    // Tests that with `set -e` a failing command stops the shell, except
+    // where its status is being tested
+    #[test]
+    fn test_errexit() {
+        let mut shell = Shell::default();
+        shell.options_mut().errexit = true;
+
+        assert_eq!(run("if false; then x=1; fi; false || true; false && x=2; while false; do :; done", &mut shell), 0);
+        assert_eq!(shell.unwinding(), None);
+        assert_eq!(run("x=1; false; x=2", &mut shell), 1);
+        assert_eq!(shell.unwinding(), Some(Unwind::Exit(1)));
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("1")));
+
+        shell.set_unwinding(None);
+        assert_eq!(run("true && false; x=3", &mut shell), 1);
+        assert_eq!(shell.unwinding(), Some(Unwind::Exit(1)));
+    }
+
+    // Tests that with `set -u` an unbound variable ends a shell that isn't
+    // interactive, like `${name?message}` does, but only fails the command
+    // in one that is
+    #[test]
+    fn test_nounset_exit() {
+        let mut shell = Shell::default();
+        shell.options_mut().nounset = true;
+
+        assert_eq!(run("x=1; echo $ensh_exec_nope; x=2", &mut shell), 1);
+        assert_eq!(shell.unwinding(), Some(Unwind::Exit(1)));
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("1")));
+
+        shell.set_unwinding(None);
+        assert_eq!(run("x=${ensh_exec_nope?gone}; x=3", &mut shell), 1);
+        assert_eq!(shell.unwinding(), Some(Unwind::Exit(1)));
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("1")));
+
+        shell.set_unwinding(None);
+        shell.set_interactive(true);
+        assert_eq!(run("echo $ensh_exec_nope; x=4", &mut shell), 0);
+        assert_eq!(shell.unwinding(), None);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("4")));
+    }
+
+    // Tests that `set -C` keeps `>` from overwriting files, but not `>|`
+    #[test]
+    fn test_noclobber() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+        let file = std::env::temp_dir().join(format!("ensh-noclobber-{}", std::process::id()));
+        shell.vars_mut().set("file", file.clone().into_os_string());
+        shell.options_mut().noclobber = true;
+
+        assert_eq!(run("printf a > $file", &mut shell), 0);
+        assert_eq!(run("printf b > $file", &mut shell), 1);
+        assert_eq!(run("printf c >> $file; printf d > /dev/null", &mut shell), 0);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "ac");
+        assert_eq!(run("printf e >| $file", &mut shell), 0);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "e");
+
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    // Tests that the `ERR` trap runs once for each command that fails
+    // outside of a condition, and `DEBUG` before each simple command
+    #[test]
+    fn test_err_debug_traps() {
+        let mut shell = Shell::default();
+
+        shell.set_trap("ERR", Some("n=$((n + 1))".to_string()));
+        assert_eq!(run("false; true; { false; }; if false; then :; fi; false || true; ensh_missing", &mut shell), 127);
+        assert_eq!(shell.vars().get("n"), Some(OsString::from("3")));
+        assert_eq!(shell.status(), 127);
+        shell.set_trap("ERR", None);
+
+        shell.set_trap("DEBUG", Some("d=$((d + 1))".to_string()));
+        assert_eq!(run("a=1; if true; then b=2; fi", &mut shell), 0);
+        assert_eq!(shell.vars().get("d"), Some(OsString::from("3")));
+    }
+
+    // Tests that an `EXIT` trap set in a subshell runs when it exits, and
+    // that the shell's own one doesn't
+    #[test]
+    fn test_subshell_exit_trap() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+        shell.set_trap("EXIT", Some("printf outer".to_string()));
+
+        assert_eq!(run("x=$( (trap 'printf done' EXIT; printf 'a ') )", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("a done")));
+        assert_eq!(run("x=$( (printf a) )", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("a")));
+    }
+
+    // Tests that `exit` ends the shell or subshell with its status, cutting
+    // short functions and loops, and that the `EXIT` trap runs on the way
+    #[test]
+    fn test_exit() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+
+        assert_eq!(run("x=$( (trap 'printf bye' EXIT; exit 2; printf no) )", &mut shell), 2);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("bye")));
+        assert_eq!(run("(exit 3)", &mut shell), 3);
+        assert_eq!(run("false; (exit)", &mut shell), 1);
+        assert_eq!(run("(exit 256)", &mut shell), 0);
+        assert_eq!(run("(exit two)", &mut shell), 2);
+        assert_eq!(shell.unwinding(), None);
+
+        assert_eq!(run("f() { while true; do exit 4; done; x=loop; }; f; x=after", &mut shell), 4);
+        assert_eq!(shell.unwinding(), Some(Unwind::Exit(4)));
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("bye")));
+
+        shell.set_unwinding(None);
+        assert_eq!(run("exit 1 2; x=kept", &mut shell), 0);
+        assert_eq!(shell.vars().get("x"), Some(OsString::from("kept")));
+    }
+}
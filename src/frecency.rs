@@ -0,0 +1,302 @@
+//! frecency.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Summary
+//! The frecency module keeps track of the directories the shell visits so
+//! that the `j` builtin can jump to one from a fragment of its path. Each
+//! directory has a rank that goes up with every visit, and a score that
+//! weighs the rank by how recently the directory was visited (the same
+//! scheme `z` uses). The database is kept in a plain text file in the ensh
+//! data directory, one `rank|last visit|path` entry per line. Paths are
+//! written as raw bytes so that names that aren't valid UTF-8 survive
+
+use std::cmp::Ordering;
+use std::env;
+use std::env::home_dir;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use consts::{DATA_DIR_NAME, FRECENCY_FNAME};
+
+// Once the ranks add up to more than this, every rank is scaled down so
+// that old favourites can be overtaken
+const MAX_TOTAL_RANK: f64 = 9000.0;
+
+/// A directory that has been visited
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The directory
+    pub path: PathBuf,
+
+    /// Goes up by one with every visit
+    pub rank: f64,
+
+    /// When the directory was last visited, in seconds since the epoch
+    pub last_visit: u64,
+}
+
+impl Entry {
+    /// Returns the score of the entry at time `now`, which is its rank
+    /// weighted by how long ago it was last visited
+    pub fn score(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_visit);
+
+        if age < 60 * 60 {
+            self.rank * 4.0
+        } else if age < 60 * 60 * 24 {
+            self.rank * 2.0
+        } else if age < 60 * 60 * 24 * 7 {
+            self.rank / 2.0
+        } else {
+            self.rank / 4.0
+        }
+    }
+}
+
+/// The database of visited directories
+#[derive(Debug, Default)]
+pub struct FrecencyDb {
+    /// The visited directories
+    entries: Vec<Entry>,
+
+    /// The file the database is saved to. A database without a file is only
+    /// kept in memory
+    file: Option<PathBuf>,
+}
+
+impl FrecencyDb {
+    /// Loads the database from a file. A missing file gives an empty
+    /// database, which will be saved to that file
+    pub fn load(file: PathBuf) -> Result<FrecencyDb, io::Error> {
+        let mut entries = Vec::new();
+
+        match File::open(&file) {
+            Ok(f) => {
+                for line in BufReader::new(f).split(b'\n') {
+                    // Skip entries that are damaged rather than losing the
+                    // whole database
+                    if let Some(entry) = parse_entry(&line?) {
+                        entries.push(entry);
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(FrecencyDb {
+            entries,
+            file: Some(file),
+        })
+    }
+
+    /// Writes the database to its file, creating the data directory if
+    /// needed. Does nothing for a database that is only kept in memory
+    pub fn save(&self) -> Result<(), io::Error> {
+        let path = match self.file {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Write to a temporary file first so a failed write can't leave a
+        // truncated database behind. The name is this process's own, so
+        // shells saving at the same time don't write into the same file
+        let tmp_path = path.with_extension(format!("tmp.{}", process::id()));
+        let mut file = File::create(&tmp_path)?;
+        for entry in &self.entries {
+            write!(file, "{}|{}|", entry.rank, entry.last_visit)?;
+            file.write_all(entry.path.as_os_str().as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Records a visit to a directory at time `now`
+    pub fn visit(&mut self, dir: &Path, now: u64) {
+        match self.entries.iter_mut().find(|entry| entry.path == dir) {
+            Some(entry) => {
+                entry.rank += 1.0;
+                entry.last_visit = now;
+            }
+            None => self.entries.push(Entry {
+                path: dir.to_path_buf(),
+                rank: 1.0,
+                last_visit: now,
+            }),
+        }
+
+        let total: f64 = self.entries.iter().map(|entry| entry.rank).sum();
+        if total > MAX_TOTAL_RANK {
+            for entry in &mut self.entries {
+                entry.rank *= 0.99;
+            }
+            self.entries.retain(|entry| entry.rank >= 1.0);
+        }
+    }
+
+    /// Returns the entries matching all of the fragments, best score first.
+    /// The fragments have to appear in the path in order, ignoring case
+    pub fn matches(&self, fragments: &[String], now: u64) -> Vec<&Entry> {
+        let fragments: Vec<String> = fragments.iter().map(|f| f.to_lowercase()).collect();
+        let mut matches: Vec<&Entry> = self
+            .entries
+            .iter()
+            .filter(|entry| matches_in_order(&entry.path.to_string_lossy().to_lowercase(), &fragments))
+            .collect();
+
+        matches.sort_by(|a, b| b.score(now).partial_cmp(&a.score(now)).unwrap_or(Ordering::Equal));
+        matches
+    }
+
+    /// Removes a directory from the database, for directories that no
+    /// longer exist
+    pub fn remove(&mut self, dir: &Path) {
+        self.entries.retain(|entry| entry.path != dir);
+    }
+}
+
+// Parses a `rank|last visit|path` line
+fn parse_entry(line: &[u8]) -> Option<Entry> {
+    let mut fields = line.splitn(3, |&b| b == b'|');
+    let rank = str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+    let last_visit = str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+    let path = fields.next()?;
+
+    if path.is_empty() {
+        return None;
+    }
+
+    Some(Entry {
+        path: PathBuf::from(OsStr::from_bytes(path)),
+        rank,
+        last_visit,
+    })
+}
+
+// Returns whether every fragment appears in `haystack`, each one after the
+// one before it
+fn matches_in_order(haystack: &str, fragments: &[String]) -> bool {
+    let mut rest = haystack;
+
+    for fragment in fragments {
+        match rest.find(fragment.as_str()) {
+            Some(pos) => rest = &rest[pos + fragment.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Returns where the database is stored: `$XDG_DATA_HOME/ensh/dirs`, or
+/// `~/.local/share/ensh/dirs` if that isn't set
+pub fn default_db_path() -> Option<PathBuf> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(ref dir) if Path::new(dir).is_absolute() => PathBuf::from(dir),
+        _ => home_dir()?.join(".local").join("share"),
+    };
+    Some(data_home.join(DATA_DIR_NAME).join(FRECENCY_FNAME))
+}
+
+/// Returns the current time in seconds since the epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = HOUR * 24;
+
+    // Convenience function for the fragment lists
+    fn fragments(words: &[&str]) -> Vec<String> {
+        words.iter().map(|s| s.to_string()).collect()
+    }
+
+    // Tests that recent visits count for more than old ones
+    #[test]
+    fn test_score_decay() {
+        let entry = Entry {
+            path: PathBuf::from("/a"),
+            rank: 4.0,
+            last_visit: 10 * DAY,
+        };
+        assert_eq!(entry.score(10 * DAY), 16.0);
+        assert_eq!(entry.score(10 * DAY + 2 * HOUR), 8.0);
+        assert_eq!(entry.score(12 * DAY), 2.0);
+        assert_eq!(entry.score(30 * DAY), 1.0);
+    }
+
+    // Tests that the most frecent match comes first
+    #[test]
+    fn test_matches_ranking() {
+        let mut db = FrecencyDb::default();
+        let now = 100 * DAY;
+        db.visit(Path::new("/src/project"), now - 20 * DAY);
+        db.visit(Path::new("/src/project"), now - 20 * DAY);
+        db.visit(Path::new("/src/project"), now - 20 * DAY);
+        db.visit(Path::new("/build/project"), now);
+        db.visit(Path::new("/home/docs"), now);
+
+        let found: Vec<&Path> = db.matches(&fragments(&["proj"]), now).iter().map(|e| e.path.as_path()).collect();
+        assert_eq!(found, vec![Path::new("/build/project"), Path::new("/src/project")]);
+
+        let found = db.matches(&fragments(&["SRC", "ject"]), now);
+        assert_eq!(found.len(), 1);
+        assert!(db.matches(&fragments(&["ject", "src"]), now).is_empty());
+    }
+
+    // Tests that ranks are scaled down once they add up past the limit
+    #[test]
+    fn test_visit_aging() {
+        let mut db = FrecencyDb::default();
+        db.entries.push(Entry {
+            path: PathBuf::from("/busy"),
+            rank: MAX_TOTAL_RANK,
+            last_visit: 0,
+        });
+        db.visit(Path::new("/new"), 1);
+
+        assert_eq!(db.entries.len(), 1);
+        assert!(db.entries[0].rank < MAX_TOTAL_RANK);
+    }
+
+    // Tests that the database survives a round trip through its file
+    #[test]
+    fn test_save_load() {
+        let file = env::temp_dir().join("ensh_frecency").join("dirs");
+        let _ = fs::remove_file(&file);
+
+        let mut db = FrecencyDb::load(file.clone()).unwrap();
+        assert!(db.entries.is_empty());
+        db.visit(Path::new("/a|b"), 5);
+        db.visit(Path::new("/c"), 6);
+        db.visit(Path::new(OsStr::from_bytes(b"/d\xff")), 7);
+        db.save().unwrap();
+
+        let loaded = FrecencyDb::load(file.clone()).unwrap();
+        assert_eq!(loaded.entries, db.entries);
+        assert!(!file.with_extension(format!("tmp.{}", process::id())).exists());
+
+        fs::write(&file, "garbage\n1|2|/ok\n").unwrap();
+        assert_eq!(FrecencyDb::load(file).unwrap().entries.len(), 1);
+    }
+}
@@ -1,11 +1,13 @@
-/// interface.rs    Afnan Enayet
-///
-/// The interface module handles most of the input/output for the shell
-/// In general, it takes input from STDIN and displays output to STDOUT and
-/// STDERR and abstracts away some of the details like formatting and shell
-/// prompts
+//! interface.rs    Afnan Enayet
+//!
+//! The interface module handles most of the input/output for the shell
+//! In general, it takes input from STDIN and displays output to STDOUT and
+//! STDERR and abstracts away some of the details like formatting and shell
+//! prompts
 
-use std::io::{self, Write, BufRead};
+use std::io::{self, Write};
+
+use sys;
 
 // Prints some given output to stdout
 pub fn print_out(output: &str) {
@@ -19,23 +21,50 @@ pub fn print_err(output: &str) {
 }
 
 // Retrieves a line (delimited by the '\n' character) from stdin, will also
-// print the shell prompt that prepends the input space. Note that this
-// operation can fail, leaving a None value
-pub fn get_input(prompt: &str, working_dir: &str) -> String {
-    print_shell_prompt(prompt, working_dir);
+// print the shell prompt that prepends the input space. `stack_depth` is
+// the depth of the directory stack, if it should be shown. Input that isn't
+// valid UTF-8 has the bad bytes replaced rather than being rejected.
+// Returns `None` at the end of input
+pub fn get_input(prompt: &str, working_dir: &str, stack_depth: Option<usize>) -> Option<String> {
+    print_shell_prompt(prompt, working_dir, stack_depth);
+    read_line().map(|line| line.trim().to_string())
+}
+
+// Reads another line of a command that was cut off, after printing the
+// continuation prompt. Only the line break is removed, since the line may
+// be inside of quotes. Returns `None` at the end of input
+pub fn get_continuation(prompt: &str) -> Option<String> {
+    print!("{} ", prompt);
+    let r = io::stdout().flush();
+    r.expect("failed to flush stdout");
 
-    // Read line from stdin
-    let mut input = String::new();
-    let stdin = io::stdin();
-    stdin.lock().read_line(&mut input).expect(
-        "could not read from stdin",
-        );
-    input.trim().to_string() // strip the newline
+    read_line().map(|line| line.trim_end_matches('\r').to_string())
+}
+
+// Reads a line from stdin, without its line break. The bytes are read one
+// at a time rather than through a buffer, so whatever comes after the line
+// is still there for `read` and the programs the shell runs. Returns `None`
+// at the end of input
+pub fn read_line() -> Option<String> {
+    let mut line = Vec::new();
+
+    loop {
+        match sys::read_byte(0).expect("could not read from stdin") {
+            Some(b'\n') => break,
+            Some(b) => line.push(b),
+            None if line.is_empty() => return None,
+            None => break,
+        }
+    }
+    Some(String::from_utf8_lossy(&line).into_owned())
 }
 
 // Prints shell prompt to STDOUT
-pub fn print_shell_prompt(prompt: &str, wd_str: &str) {
-    println!("({})", wd_str);
+pub fn print_shell_prompt(prompt: &str, wd_str: &str, stack_depth: Option<usize>) {
+    match stack_depth {
+        Some(depth) => println!("({}) [{}]", wd_str, depth),
+        None => println!("({})", wd_str),
+    }
     print!("{} ", prompt);
     let r = io::stdout().flush();
     r.expect("failed to flush stdout");
@@ -61,6 +90,7 @@ mod tests {
     // Tests if function can print shell prompt to STDOUT
     #[test]
     fn test_print_sh_prompt() {
-        print_shell_prompt(">", "test");
+        print_shell_prompt(">", "test", None);
+        print_shell_prompt(">", "test", Some(2));
     }
 }
@@ -1,40 +1,52 @@
-/// lib.rs
-///
-/// # Author
-/// Afnan Enayet
-///
-/// # Synopsis
-/// The lib.rs file contains the main logic for the program. It assumes the
-/// comand line parameters that are passed in are valid (they should be verified
-/// in `main.rs`).
-/// This also contains the arguments struct and its implementation. This allows
-/// for the required arguments to be changed without much refactoring.
-///
+//! lib.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Synopsis
+//! The lib.rs file contains the main logic for the program. It assumes the
+//! comand line parameters that are passed in are valid (they should be verified
+//! in `main.rs`).
+//! This also contains the arguments struct and its implementation. This allows
+//! for the required arguments to be changed without much refactoring.
+//!
+
+extern crate libc;
 
 mod consts;
 mod interface;
 mod parser;
 mod shell;
 mod cmd_dispatch;
-
-use shell::Shell;
+mod sys;
+mod frecency;
+mod suggest;
+mod vars;
+mod exec;
+mod cond;
+mod arith;
+mod redirect;
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use shell::{Shell, Unwind};
 use consts::*;
-use parser::expand_path;
-use interface::print_out;
+use interface::{print_out, print_err};
 
 // Program wide constants
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// A structure that holds the arguments being passed in from the command
 /// line
 pub struct Args {
-    config_file_path: Option<String>,
+    config_file_path: Option<OsString>,
 }
 
 impl Args {
     /// Creates a new `Args` struct with all of the command line arguments
     /// represented as rust objects in the struct
-    pub fn new(args: Option<String>) -> Args {
+    pub fn new(args: Option<OsString>) -> Args {
         // Return struct with extracted arguments
         Args { config_file_path: args }
     }
@@ -44,43 +56,43 @@ impl Args {
 /// Initializes shell, dispatches shell loop, then calls exit function when
 /// appropriate
 pub fn run(args: Args) {
-    // Convert from Option<String> to Option<&str>
-    let config_fp: Option<&str> = match args.config_file_path.as_ref() {
-        Some(s) => Some(s),
-        None => None,
-    };
+    let config_fp: Option<&Path> = args.config_file_path.as_ref().map(Path::new);
 
     // Initialize shell and load config
     let mut shell = init_shell(config_fp);
 
+    // Load the directories visited in earlier sessions, for `j`. This is
+    // left out of `init_shell` so that tests don't touch the real database
+    if let Some(db_path) = frecency::default_db_path() {
+        if let Err(e) = shell.load_frecency(db_path) {
+            print_err(&format!("ensh: could not load directory history: {}", e));
+        }
+    }
+
     // Run everything that needs to run while the shell is operating
     while shell_loop(&mut shell) {}
-    shell_exit(0);
+    let status = shell.status();
+    shell_exit(&mut shell, status);
 }
 
 /// Initialize shell using config file provided from arguments (if any)
-/// If no config file was given, search for default config file path. If
-/// default does not exist, create a default config file with default
-/// paths
-fn init_shell(config_fp: Option<&str>) -> Shell {
+/// If no config file was given, search the default config locations. If
+/// none of them exist, create a default config file with default paths
+fn init_shell(config_fp: Option<&Path>) -> Shell {
     let initial_prompt = format!("Enayet Shell | v{}\n", VERSION);
     print_out(initial_prompt.as_str());
 
-    let normalized_fp = match config_fp {
-        Some(s) => expand_path(s),
-        None => expand_path("~/.ensh_config"),
-    };
-
     // Initialize shell and load config options from file
     let mut shell = Shell::default();
+    shell.set_interactive(sys::isatty(0));
     let mut def_path_vec: Vec<String> = Vec::new();
 
     for path in DEFAULT_PATHS {
         def_path_vec.push(path.to_string());
     }
 
-    // Load PATH(S) into shell
-    shell.load_paths(Some(normalized_fp.as_str()), &def_path_vec);
+    // Load PATH(S) and other settings into shell
+    shell.load_config(config_fp, &def_path_vec);
 
     // Set working directory to home or "/" if it fails
     if !cmd_dispatch::dispatch(&mut shell, "cd ~") {
@@ -93,27 +105,65 @@ fn init_shell(config_fp: Option<&str>) -> Shell {
 /// displays output to shell as necessary. Returns if shell should
 /// be terminated or continue for another loop iteration
 fn shell_loop(shell: &mut Shell) -> bool {
-    let exit_code = "exit".to_string();
-    let working_dir = shell.get_cwd().to_owned();
-
-    // Get command from user
-    let input = interface::get_input(SHELL_PROMPT, &working_dir[..]);
-
-    // Exit if necessary
-    if input != exit_code {
-        if !cmd_dispatch::dispatch(shell, &input[..]) {
-            println!(":(\n");
-        } else {
-            println!("");
+    // Pick up any changes made to the config file since the last prompt
+    if shell.config_changed() {
+        if let Err(e) = shell.reload_config() {
+            print_err(&format!("ensh: could not reload config: {}", e));
         }
-        true
+    }
+
+    let working_dir = shell.get_cwd().into_owned();
+    let stack_depth = if shell.show_dir_stack() {
+        Some(shell.dir_stack().len())
     } else {
-        false
+        None
+    };
+
+    // Get command from user, reading more lines while it is incomplete
+    // The shell ends with its input
+    let mut input = match interface::get_input(shell.prompt(), &working_dir[..], stack_depth) {
+        Some(input) => input,
+        None => return false,
+    };
+    while cmd_dispatch::is_incomplete(shell, &input) {
+        match interface::get_continuation(CONTINUATION_PROMPT) {
+            Some(line) => {
+                input.push('\n');
+                input.push_str(&line);
+            }
+            None => break,
+        }
+    }
+
+    // Signals that arrived while waiting for input set off their traps now
+    cmd_dispatch::run_pending_traps(shell);
+
+    let success = cmd_dispatch::dispatch(shell, &input[..]);
+
+    // `exit`, or a command that failed under `set -e`, ends the shell
+    // straight away, without the failure marker
+    if let Some(Unwind::Exit(status)) = shell.unwinding() {
+        shell_exit(shell, status);
     }
+
+    if !success {
+        println!(":(\n");
+    } else {
+        println!();
+    }
+    true
 }
 
-// Cleans up and exits the shell with the specified exit code
-fn shell_exit(exit_status: i32) {
+// Cleans up and exits the shell with the specified exit code, after
+// running the `EXIT` trap if there is one and saving the directories that
+// were visited
+fn shell_exit(shell: &mut Shell, exit_status: i32) {
+    shell.set_unwinding(None);
+    cmd_dispatch::run_trap("EXIT", shell);
+    if let Err(e) = shell.save_frecency() {
+        print_err(&format!("ensh: could not save directory history: {}", e));
+    }
+    let _ = std::io::Write::flush(&mut std::io::stdout());
     std::process::exit(exit_status);
 }
 
@@ -136,13 +186,12 @@ mod tests {
         // Create filepath/PathBuf for temporary config file
         let mut temp_pb = temp_dir();
         temp_pb.push("lib_test");
-        let temp_pb = temp_pb.as_path();
-        let config_fp: &str = temp_pb.to_str().unwrap();
+        let config_fp = temp_pb.as_path();
 
         // Create test config file
-        super::parser::config::create_default_config(config_fp, &config_vec);
+        super::parser::config::create_default_config(config_fp, &config_vec).unwrap();
 
         // Initialize shell with string pointing to temp file just created
-        let shell = init_shell(Some(config_fp));
+        let _shell = init_shell(Some(config_fp));
     }
 }
@@ -0,0 +1,165 @@
+//! alias.rs    Afnan Enayet
+//!
+//! Alias expansion. An alias replaces the first word of a command with its
+//! value, and the first word of the value is checked again so aliases can
+//! build on each other. An alias is never expanded inside its own
+//! expansion, which keeps `alias ls='ls -F'` (or a loop of aliases) from
+//! expanding forever. If a value ends with a blank, the word after the
+//! alias is checked for an alias too, the same as in bash
+
+use std::collections::BTreeMap;
+
+use super::ParseError;
+use super::lexer::{tokenize, is_quoted, Token};
+
+/// Expands aliases in the tokens from the lexer. Only a word in command
+/// position (at the start of a command) is looked up
+pub fn expand_aliases(tokens: Vec<Token>, aliases: &BTreeMap<String, String>) -> Result<Vec<Token>, ParseError> {
+    let mut expanding = Vec::new();
+    expand_tokens(tokens, aliases, &mut expanding).map(|(tokens, _)| tokens)
+}
+
+// Expands the aliases in `tokens`. `expanding` holds the aliases currently
+// being expanded, which may not be expanded again. Returns the tokens along
+// with whether the word after them should be checked for an alias (because
+// the last expansion ended with a blank)
+fn expand_tokens(
+    tokens: Vec<Token>,
+    aliases: &BTreeMap<String, String>,
+    expanding: &mut Vec<String>,
+) -> Result<(Vec<Token>, bool), ParseError> {
+    let mut result = Vec::new();
+    let mut check = true;
+
+    for token in tokens {
+        let word = match token {
+            Token::Word(word) => word,
+            Token::Op(op) => {
+                // Every operator other than a redirection starts a new
+                // command
+                result.push(token);
+                check = !op.is_redirect();
+                continue;
+            }
+            Token::Arith(_) | Token::IoNumber(_) | Token::HereDoc(_) => {
+                result.push(token);
+                check = false;
+                continue;
+            }
+        };
+
+        if check {
+            if let Some(value) = lookup(&word, aliases, expanding) {
+                expanding.push(word);
+                let (expanded, inner_check) = expand_tokens(tokenize(value)?, aliases, expanding)?;
+                expanding.pop();
+
+                result.extend(expanded);
+                check = inner_check || value.ends_with(char::is_whitespace);
+                continue;
+            }
+        }
+
+        // A command can start right after a reserved word like `{`, but
+        // only if the reserved word was itself where a command goes
+        check = check && starts_command(&word);
+        result.push(Token::Word(word));
+    }
+    Ok((result, check))
+}
+
+// Reserved words that are followed by a command
+const COMMAND_PREFIXES: &[&str] = &["{", "!", "if", "then", "elif", "else", "while", "until", "do"];
+
+// Returns whether the word after `word` is in command position
+fn starts_command(word: &str) -> bool {
+    COMMAND_PREFIXES.contains(&word)
+}
+
+// Returns the value of the alias named by `word`, unless the word is quoted
+// (quoting a command is the way to skip its alias) or the alias is already
+// being expanded
+fn lookup<'a>(word: &str, aliases: &'a BTreeMap<String, String>, expanding: &[String]) -> Option<&'a String> {
+    if is_quoted(word) || expanding.iter().any(|name| name == word) {
+        None
+    } else {
+        aliases.get(word)
+    }
+}
+
+/// Returns whether a name can be used for an alias
+pub fn valid_alias_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || "'\"\\$`=|&;<>()/".contains(c))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds an alias table from name/value pairs
+    fn table(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    // Splits and expands a command, joining the result for easy comparison
+    fn expand(cmd: &str, aliases: &BTreeMap<String, String>) -> String {
+        let tokens = expand_aliases(tokenize(cmd).unwrap(), aliases).unwrap();
+        tokens.iter().map(|token| token.to_string()).collect::<Vec<_>>().join(" ")
+    }
+
+    // Tests that only the first word is expanded
+    #[test]
+    fn test_expand_first_word() {
+        let aliases = table(&[("ll", "ls -la")]);
+        assert_eq!(expand("ll /tmp", &aliases), "ls -la /tmp");
+        assert_eq!(expand("echo ll", &aliases), "echo ll");
+        assert_eq!(expand("'ll'", &aliases), "'ll'");
+    }
+
+    // Tests that every command in a list is checked, including the ones in
+    // an alias's value
+    #[test]
+    fn test_expand_command_position() {
+        let aliases = table(&[("ll", "ls -la"), ("both", "ll; ll")]);
+        assert_eq!(expand("ll; ll ll", &aliases), "ls -la ; ls -la ll");
+        assert_eq!(expand("both", &aliases), "ls -la ; ls -la");
+        assert_eq!(expand("{ ll; }", &aliases), "{ ls -la ; }");
+        assert_eq!(expand("if ll; then ll; fi", &aliases), "if ls -la ; then ls -la ; fi");
+        assert_eq!(expand("echo then ll", &aliases), "echo then ll");
+        assert_eq!(expand("! ll | ll; echo ! ll", &aliases), "! ls -la | ls -la ; echo ! ll");
+        assert_eq!(expand("echo do ll; do ll", &aliases), "echo do ll ; do ls -la");
+    }
+
+    // Tests that aliases can refer to other aliases, but not loop
+    #[test]
+    fn test_expand_recursive() {
+        let aliases = table(&[("ll", "l -a"), ("l", "ls -l"), ("ls", "ls -F")]);
+        assert_eq!(expand("ll", &aliases), "ls -F -l -a");
+
+        let aliases = table(&[("a", "b x"), ("b", "a y")]);
+        assert_eq!(expand("a", &aliases), "a y x");
+    }
+
+    // Tests that a trailing blank makes the next word eligible for expansion
+    #[test]
+    fn test_expand_trailing_space() {
+        let aliases = table(&[("sudo", "sudo "), ("ll", "ls -la"), ("s", "sudo")]);
+        assert_eq!(expand("sudo ll", &aliases), "sudo ls -la");
+        assert_eq!(expand("sudo sudo ll", &aliases), "sudo sudo ls -la");
+        assert_eq!(expand("s ll", &aliases), "sudo ls -la");
+
+        let aliases = table(&[("nosp", "sudo"), ("ll", "ls -la")]);
+        assert_eq!(expand("nosp ll", &aliases), "sudo ll");
+    }
+
+    // Tests which alias names are accepted
+    #[test]
+    fn test_valid_alias_name() {
+        assert!(valid_alias_name("ll"));
+        assert!(valid_alias_name("git-st"));
+        assert!(!valid_alias_name(""));
+        assert!(!valid_alias_name("a b"));
+        assert!(!valid_alias_name("a=b"));
+    }
+}
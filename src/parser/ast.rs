@@ -0,0 +1,203 @@
+//! ast.rs    Afnan Enayet
+//!
+//! The syntax tree that the parser builds out of a line of input, and that
+//! the shell executes. Words are kept the way the lexer produced them
+//! (quotes included), they are only expanded when the command runs
+
+use std::rc::Rc;
+
+/// A sequence of commands that run one after the other
+pub type List = Vec<Command>;
+
+/// A command
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// A command name with its arguments, or just variable assignments
+    Simple(Vec<String>),
+
+    /// `{ list; }`, which runs the list in the current shell
+    Group(List),
+
+    /// `( list )`, which runs the list in a copy of the shell
+    Subshell(List),
+
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    If(If),
+
+    /// `while list; do list; done` or `until list; do list; done`
+    Loop(Loop),
+
+    /// `for name [in word...]; do list; done`
+    For(For),
+
+    /// `case word in [pattern [| pattern]...) list;;]... esac`
+    Case(Case),
+
+    /// `[[ expression ]]`, a conditional expression
+    Cond(CondExpr),
+
+    /// `(( expression ))`, which succeeds if the arithmetic expression is
+    /// not 0. The expression is kept unexpanded
+    Arith(String),
+
+    /// `command | command...`, which runs the commands at the same time,
+    /// each one's output going to the next one's input
+    Pipeline(Vec<Command>),
+
+    /// `! pipeline`, which succeeds if the pipeline fails and fails if it
+    /// succeeds
+    Not(Box<Command>),
+
+    /// `command && command`, which runs the second command if the first
+    /// one succeeds
+    And(Box<Command>, Box<Command>),
+
+    /// `command || command`, which runs the second command if the first
+    /// one fails
+    Or(Box<Command>, Box<Command>),
+
+    /// `name() body` or `function name body`, which defines a function
+    FunctionDef(FunctionDef),
+
+    /// A command with redirections, which apply while it runs
+    Redirected(Box<Command>, Vec<Redirect>),
+}
+
+/// A redirection of one of the file descriptors of a command
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    /// The file descriptor that is redirected: the number before the
+    /// operator, or else 0 for input and 1 for output
+    pub fd: i32,
+
+    /// What the file descriptor is redirected to
+    pub op: RedirectOp,
+
+    /// The (unexpanded) word after the operator: a file name, a file
+    /// descriptor, the body of a here-document or a here-string
+    pub target: String,
+}
+
+/// The kinds of redirection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectOp {
+    /// `< file`
+    Read,
+
+    /// `> file`
+    Write,
+
+    /// `>> file`
+    Append,
+
+    /// `>| file`, which overwrites the file even if `noclobber` is set
+    Clobber,
+
+    /// `<> file`
+    ReadWrite,
+
+    /// `<& fd` or `>& fd`, which make the file descriptor a copy of `fd`,
+    /// or close it if `fd` is `-`
+    Duplicate,
+
+    /// `<< delimiter` or `<<- delimiter`. The body is expanded like the
+    /// inside of double quotes unless the delimiter was quoted
+    HereDoc { expand: bool },
+
+    /// `<<< word`, which reads the expanded word and a line break
+    HereString,
+}
+
+/// A function definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDef {
+    /// The name the function is called by
+    pub name: String,
+
+    /// The command that runs when the function is called. It is shared with
+    /// the shell's function table, so a definition doesn't have to be copied
+    pub body: Rc<Command>,
+}
+
+/// An `if` command
+#[derive(Debug, Clone, PartialEq)]
+pub struct If {
+    /// The condition and body of the `if` and of each `elif`, in order. The
+    /// first body whose condition succeeds is run
+    pub branches: Vec<(List, List)>,
+
+    /// The body of the `else`, which runs if no condition succeeded
+    pub otherwise: Option<List>,
+}
+
+/// A `while` or `until` loop
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    /// Whether this is an `until` loop, which runs until the condition
+    /// succeeds rather than while it does
+    pub until: bool,
+
+    /// The commands whose status decides whether to keep going
+    pub condition: List,
+
+    /// The commands run on each time around the loop
+    pub body: List,
+}
+
+/// A `for` loop
+#[derive(Debug, Clone, PartialEq)]
+pub struct For {
+    /// The variable set to each word in turn
+    pub name: String,
+
+    /// The words to loop over, which are expanded when the loop runs. If
+    /// there is no `in`, the loop goes over the positional parameters
+    pub words: Option<Vec<String>>,
+
+    /// The commands run for each word
+    pub body: List,
+}
+
+/// A `case` command
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    /// The word that is matched against the patterns
+    pub word: String,
+
+    /// The items, tried in order. Only the first one that matches runs
+    pub items: Vec<CaseItem>,
+}
+
+/// An item of a `case` command
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseItem {
+    /// Glob patterns, any of which can match the word
+    pub patterns: Vec<String>,
+
+    /// The commands run if a pattern matches
+    pub body: List,
+}
+
+/// An expression inside of `[[ ]]`. The words are expanded when the
+/// expression is evaluated, but aren't split into fields or globbed
+#[derive(Debug, Clone, PartialEq)]
+pub enum CondExpr {
+    /// `expression && expression`
+    And(Box<CondExpr>, Box<CondExpr>),
+
+    /// `expression || expression`
+    Or(Box<CondExpr>, Box<CondExpr>),
+
+    /// `! expression`
+    Not(Box<CondExpr>),
+
+    /// A unary operator like `-f` and its operand
+    Unary(String, String),
+
+    /// A binary operator like `==` and its operands, in the order
+    /// left operand, operator, right operand
+    Binary(String, String, String),
+
+    /// A word on its own, which is true if it isn't empty
+    Word(String),
+}
@@ -0,0 +1,191 @@
+//! brace.rs    Afnan Enayet
+//!
+//! Brace expansion, the first of the expansions. A word with an unquoted
+//! brace expression in it becomes several words:
+//! * `{a,b,c}` - one word for each of the comma separated parts
+//! * `{1..10}` or `{1..10..2}` - one for each number in the range, with an
+//!   optional step. If either end has a leading zero, the numbers are
+//!   padded with zeros to the same width
+//! * `{a..e}` or `{a..z..2}` - one for each letter in the range
+//!
+//! The text around the braces is added to each word, and brace expressions
+//! can be nested: `a{b,c{1..2}}` gives `ab ac1 ac2`. Braces that don't make
+//! up one of these (like `{}` or `{a}`) are left alone, and so are the
+//! braces of `${...}`. Expansion works on the words as they came from the
+//! lexer, so quotes are still there to tell quoted braces and commas apart
+
+use super::lexer::{backquote_end, brace_end, quoted_end, substitution_end};
+
+/// Expands the brace expressions in a word into the words they make up. A
+/// word without any gives just itself
+pub fn expand_braces(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+
+    match find_group(&chars) {
+        Some((start, end, parts)) => {
+            let prefix: String = chars[..start].iter().collect();
+            let suffix: String = chars[end..].iter().collect();
+
+            parts
+                .iter()
+                .flat_map(|part| expand_braces(&format!("{}{}{}", prefix, part, suffix)))
+                .collect()
+        }
+        None => vec![word.to_string()],
+    }
+}
+
+// Finds the first brace expression in `chars` that expands. Returns where
+// it starts and ends, and the text each of the words it gives has in its
+// place
+fn find_group(chars: &[char]) -> Option<(usize, usize, Vec<String>)> {
+    let mut i = 0;
+
+    while i < chars.len() {
+        if let Some(end) = skip_quoted(chars, i) {
+            i = end;
+            continue;
+        }
+
+        if chars[i] == '{' {
+            if let Some((end, parts)) = group_at(chars, i) {
+                return Some((i, end, parts));
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+// Reads the brace expression whose `{` is at `chars[start]`. Returns the
+// index after its `}` and its parts, or `None` if it isn't a list or a
+// range
+fn group_at(chars: &[char], start: usize) -> Option<(usize, Vec<String>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        if let Some(end) = skip_quoted(chars, i) {
+            i = end;
+            continue;
+        }
+
+        match chars[i] {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            '}' => break,
+            ',' if depth == 0 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if i >= chars.len() {
+        return None;
+    }
+
+    if commas.is_empty() {
+        let inner: String = chars[start + 1..i].iter().collect();
+        return sequence(&inner).map(|parts| (i + 1, parts));
+    }
+
+    let mut bounds = vec![start];
+    bounds.extend(commas);
+    bounds.push(i);
+    let parts = bounds.windows(2).map(|pair| chars[pair[0] + 1..pair[1]].iter().collect()).collect();
+    Some((i + 1, parts))
+}
+
+// Returns the index after the escaped character, quoted string or nested
+// expansion at `chars[i]`, or `None` if there isn't one there. A part that
+// is never closed runs to the end of the word
+fn skip_quoted(chars: &[char], i: usize) -> Option<usize> {
+    let end = match (chars[i], chars.get(i + 1)) {
+        ('\\', _) => Ok(i + 2),
+        ('\'', _) => quoted_end(chars, i + 1, '\''),
+        ('"', _) => quoted_end(chars, i + 1, '"'),
+        ('`', _) => backquote_end(chars, i + 1),
+        ('$', Some('(')) | ('<', Some('(')) | ('>', Some('(')) => substitution_end(chars, i + 2),
+        ('$', Some('{')) => brace_end(chars, i + 2, false),
+        _ => return None,
+    };
+    Some(end.unwrap_or(chars.len()).min(chars.len()))
+}
+
+// Expands the inside of a range, `start..end` or `start..end..step`, or
+// returns `None` if it isn't one
+fn sequence(text: &str) -> Option<Vec<String>> {
+    let bounds: Vec<&str> = text.split("..").collect();
+    let step = match bounds.len() {
+        2 => 1,
+        3 => bounds[2].parse::<i64>().ok()?.checked_abs()?.max(1),
+        _ => return None,
+    };
+
+    if let (Ok(first), Ok(last)) = (bounds[0].parse::<i64>(), bounds[1].parse::<i64>()) {
+        let width = if padded(bounds[0]) || padded(bounds[1]) {
+            bounds[0].len().max(bounds[1].len())
+        } else {
+            0
+        };
+        return Some(range(first, last, step).map(|n| format!("{:01$}", n, width)).collect());
+    }
+
+    let letter = |bound: &str| match bound.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Some(i64::from(*c)),
+        _ => None,
+    };
+    let (first, last) = (letter(bounds[0])?, letter(bounds[1])?);
+    Some(range(first, last, step).map(|c| (c as u8 as char).to_string()).collect())
+}
+
+// Returns whether a number in a range is written with a leading zero
+fn padded(number: &str) -> bool {
+    let digits = number.trim_start_matches('-');
+    digits.len() > 1 && digits.starts_with('0')
+}
+
+// Counts from `first` to `last` by `step`, up or down
+fn range(first: i64, last: i64, step: i64) -> impl Iterator<Item = i64> {
+    let count = (i128::from(first) - i128::from(last)).unsigned_abs() as u64 / step as u64;
+    let step = if first <= last { step } else { -step };
+    (0..=count).map(move |i| first + i as i64 * step)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests comma separated lists, with text around them and nested
+    #[test]
+    fn test_expand_lists() {
+        assert_eq!(expand_braces("src/{bin,lib,tests}"), vec!["src/bin", "src/lib", "src/tests"]);
+        assert_eq!(expand_braces("a{b,c{1,2},}d"), vec!["abd", "ac1d", "ac2d", "ad"]);
+        assert_eq!(expand_braces("{a,b}{1,2}"), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand_braces("{,x}"), vec!["", "x"]);
+    }
+
+    // Tests numeric and letter ranges, with steps and padding
+    #[test]
+    fn test_expand_ranges() {
+        assert_eq!(expand_braces("{1..5}"), vec!["1", "2", "3", "4", "5"]);
+        assert_eq!(expand_braces("{1..10..3}"), vec!["1", "4", "7", "10"]);
+        assert_eq!(expand_braces("{3..-3..-2}"), vec!["3", "1", "-1", "-3"]);
+        assert_eq!(expand_braces("x{08..11}"), vec!["x08", "x09", "x10", "x11"]);
+        assert_eq!(expand_braces("{a..e..2}{Y..Z}"), vec!["aY", "aZ", "cY", "cZ", "eY", "eZ"]);
+        assert_eq!(expand_braces("{c..a}"), vec!["c", "b", "a"]);
+        assert_eq!(expand_braces("{a,{1..2}}"), vec!["a", "1", "2"]);
+    }
+
+    // Tests braces that don't make up a brace expression
+    #[test]
+    fn test_expand_literal() {
+        for word in &["{}", "{a}", "{a", "a}", "{1..}", "{a..9}", "{ab..c}", "{1..2..x}", "${a,b}", "'{a,b}'", "\\{a,b}", "{a\\,b}", "$(x {a,b})"] {
+            assert_eq!(expand_braces(word), vec![word.to_string()]);
+        }
+        assert_eq!(expand_braces("{'a,b',c}"), vec!["'a,b'", "c"]);
+        assert_eq!(expand_braces("{}{a,b}"), vec!["{}a", "{}b"]);
+    }
+}
@@ -1,72 +1,286 @@
-/// config.rs    Afnan Enayet
-///
-/// The config module provides helper/convenience functions to parse
-/// the configuration file for the shell and write configuration file
-/// to default directory or any other directory
+//! config.rs    Afnan Enayet
+//!
+//! The config module provides helper/convenience functions to parse
+//! the configuration file for the shell and write configuration file
+//! to default directory or any other directory
+//!
+//! The config file is searched for in the following order:
+//! 1. the path given on the command line
+//! 2. `$ENSH_CONFIG`
+//! 3. `$XDG_CONFIG_HOME/ensh/config` (`~/.config/ensh/config` if unset)
+//! 4. `~/.ensh_config`
+//!
+//! The file is split into sections, each starting with a `[section]` header.
+//! Blank lines and lines starting with `#` are ignored. Lines that come
+//! before the first header are treated as paths, which keeps config files
+//! from older versions (a plain list of paths) working.
+//!
+//! ```text
+//! [paths]
+//! /usr/bin
+//!
+//! [prompt]
+//! symbol = >
+//! show_dir_stack = true
+//!
+//! [env]
+//! EDITOR = vim
+//!
+//! [aliases]
+//! ll = ls -la
+//!
+//! [options]
+//! auto_reload = true
+//! glob_nomatch = literal
+//! ```
 
 use std::io;
-use std::fs::{File};
-use std::path::Path;
+use std::env;
+use std::env::home_dir;
+use std::ffi::OsString;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::io::{Write, BufReader, BufRead};
-use consts::{DEF_CONFIG_FNAME, DEFAULT_PATHS};
-
-/// Loads a list of include paths from the config file. The function takes
-/// an optional string argument. If the argument is not present or the
-/// provided argument cannot be opened, then the function will attempt
-/// to load the default config file. If that cannot be loaded, the function
-/// will create a default config file in the default path
-pub fn load_paths_from_config(config_path: Option<&str>, def_paths: &Vec<String>) -> Vec<String> {
-    // Try to load given path, or use default if no string was supplied
-    let def_config_fp = format!("~/{}", DEF_CONFIG_FNAME);
-    let config_path = config_path.unwrap_or(def_config_fp.as_str());
-    let config_path = super::expand_path(config_path);
-
-    // See if the file can be opened
-    let default_exists = Path::new(&config_path).exists();
-
-    // Open the default file if the supplied location fails
-    // If the default file doesn't exist, create it
-    let file = match File::open(&config_path) {
-        Ok(file) => file,
-        Err(_) => {
-            // Try to read from default config
-            // Create a new default config if necessary
-            if !default_exists {
-                create_default_config(&config_path, def_paths).unwrap();
-            }
-            File::open(DEF_CONFIG_FNAME).unwrap()
-        }
+use super::glob::NoMatch;
+use consts::{CONFIG_ENV_VAR, DEF_CONFIG_FNAME, XDG_CONFIG_DIR, XDG_CONFIG_FNAME};
+
+/// Determines which config file the shell should load, following the search
+/// order described at the top of this module. A path that was asked for
+/// explicitly (as an argument or through `$ENSH_CONFIG`) has to exist, we
+/// return an error rather than quietly loading some other file. If none of
+/// the implicit locations have a config file, a default config is written to
+/// `~/.ensh_config` and that path is returned
+pub fn resolve_config_path(explicit: Option<&Path>, def_paths: &[String]) -> Result<PathBuf, io::Error> {
+    let explicit = explicit_config_path(explicit, env::var_os(CONFIG_ENV_VAR));
+    let home = home_dir();
+    let implicit = implicit_config_paths(env::var_os("XDG_CONFIG_HOME"), home.clone());
+    let fallback = home.map(|home| home.join(DEF_CONFIG_FNAME));
+    search_config(explicit, &implicit, fallback, def_paths)
+}
+
+// Returns the config that was asked for, by argument or else by the value
+// of `$ENSH_CONFIG`. Both can start with a tilde
+fn explicit_config_path(arg: Option<&Path>, env_value: Option<OsString>) -> Option<PathBuf> {
+    match arg {
+        Some(path) => Some(super::expand_path(path)),
+        None => env_value.map(|value| super::expand_path(Path::new(&value))),
+    }
+}
+
+// Returns the config locations that are searched when no config was asked
+// for explicitly, in the order they should be tried
+fn implicit_config_paths(xdg_config_home: Option<OsString>, home: Option<PathBuf>) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    // The XDG spec says to ignore the variable unless it's an absolute path
+    let xdg_dir = match xdg_config_home {
+        Some(ref dir) if Path::new(dir).is_absolute() => Some(PathBuf::from(dir)),
+        _ => home.as_ref().map(|home| home.join(".config")),
     };
 
-    let reader = BufReader::new(file);
-    let mut result = Vec::new();
+    if let Some(dir) = xdg_dir {
+        paths.push(dir.join(XDG_CONFIG_DIR).join(XDG_CONFIG_FNAME));
+    }
 
-    // Read config file line by line and load into vector
-    for line in reader.lines().enumerate() {
-        let line_result = line.1;
-        let path_str = line_result.unwrap().to_string().clone();
+    if let Some(home) = home {
+        paths.push(home.join(DEF_CONFIG_FNAME));
+    }
+    paths
+}
 
-        // Check if path is valid and add to vector
-        if Path::new(&path_str).exists() {
-            result.push(path_str);
+// Picks the config file out of the candidates. `fallback` is where a default
+// config gets created if no candidate exists
+fn search_config(
+    explicit: Option<PathBuf>,
+    implicit: &[PathBuf],
+    fallback: Option<PathBuf>,
+    def_paths: &[String],
+) -> Result<PathBuf, io::Error> {
+    if let Some(path) = explicit {
+        return if path.is_file() {
+            Ok(path)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("config file {} does not exist", path.display()),
+            ))
+        };
+    }
+
+    if let Some(path) = implicit.iter().find(|path| path.is_file()) {
+        return Ok(path.clone());
+    }
+
+    match fallback {
+        Some(path) => {
+            create_default_config(&path, def_paths)?;
+            Ok(path)
         }
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no config file found and no home directory to create one in",
+        )),
     }
-    result
+}
+
+/// The settings that are read from a config file
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Config {
+    /// The paths the shell will search for binaries (`[paths]`)
+    pub paths: Vec<PathBuf>,
+
+    /// The symbol printed in front of the input space (`symbol` in `[prompt]`)
+    pub prompt: Option<String>,
+
+    /// Whether the prompt shows the depth of the directory stack
+    /// (`show_dir_stack` in `[prompt]`)
+    pub show_dir_stack: bool,
+
+    /// Environment variables to export, in file order (`[env]`)
+    pub env: Vec<(String, String)>,
+
+    /// Aliases to define, in file order (`[aliases]`)
+    pub aliases: Vec<(String, String)>,
+
+    /// Whether the config should be reloaded when the file changes
+    /// (`auto_reload` in `[options]`)
+    pub auto_reload: bool,
+
+    /// What to do with a glob pattern that matches no files
+    /// (`glob_nomatch` in `[options]`)
+    pub glob_nomatch: NoMatch,
+}
+
+// The sections a config file can contain
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Section {
+    Paths,
+    Prompt,
+    Env,
+    Aliases,
+    Options,
+}
+
+/// Loads the config file at `config_path`. Paths that don't exist on this
+/// machine are skipped, anything that can't be understood is an error
+pub fn load_config<P: AsRef<Path>>(config_path: P) -> Result<Config, io::Error> {
+    let file = File::open(config_path)?;
+    parse_config(BufReader::new(file))
+}
+
+/// Parses config file contents from a reader. See the module documentation
+/// for the format
+pub fn parse_config<R: BufRead>(reader: R) -> Result<Config, io::Error> {
+    let mut config = Config::default();
+    let mut section = Section::Paths;
+
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+
+        // Skip blank lines and comments
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = match &line[1..line.len() - 1] {
+                "paths" => Section::Paths,
+                "prompt" => Section::Prompt,
+                "env" => Section::Env,
+                "aliases" => Section::Aliases,
+                "options" => Section::Options,
+                name => return Err(config_error(line_num, &format!("unknown section [{}]", name))),
+            };
+            continue;
+        }
+
+        if section == Section::Paths {
+            // Check if path is valid and add it with any tilde expanded,
+            // since the paths are searched as they are
+            let path = super::expand_path(Path::new(line));
+            if super::verify_path(&path) {
+                config.paths.push(path);
+            }
+            continue;
+        }
+
+        let (key, value) = match split_key_value(line) {
+            Some(pair) => pair,
+            None => return Err(config_error(line_num, "expected `key = value`")),
+        };
+
+        match (section, key) {
+            (Section::Prompt, "symbol") => config.prompt = Some(value.to_string()),
+            (Section::Prompt, "show_dir_stack") => config.show_dir_stack = parse_bool(line_num, key, value)?,
+            (Section::Env, _) => config.env.push((key.to_string(), value.to_string())),
+            (Section::Aliases, _) => {
+                if !super::alias::valid_alias_name(key) {
+                    return Err(config_error(line_num, &format!("invalid alias name `{}`", key)));
+                }
+                config.aliases.push((key.to_string(), value.to_string()))
+            }
+            (Section::Options, "auto_reload") => config.auto_reload = parse_bool(line_num, key, value)?,
+            (Section::Options, "glob_nomatch") => {
+                config.glob_nomatch = match value {
+                    "literal" => NoMatch::Literal,
+                    "error" => NoMatch::Error,
+                    "empty" => NoMatch::Empty,
+                    _ => return Err(config_error(line_num, "glob_nomatch must be literal, error or empty")),
+                }
+            }
+            _ => return Err(config_error(line_num, &format!("unknown setting `{}`", key))),
+        }
+    }
+    Ok(config)
+}
+
+// Splits a `key = value` line. Surrounding whitespace is trimmed from both
+// halves; a value can be wrapped in double quotes to keep whitespace
+fn split_key_value(line: &str) -> Option<(&str, &str)> {
+    let eq = line.find('=')?;
+    let key = line[..eq].trim();
+    let value = line[eq + 1..].trim();
+
+    if key.is_empty() {
+        return None;
+    }
+
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        Some((key, &value[1..value.len() - 1]))
+    } else {
+        Some((key, value))
+    }
+}
+
+// Parses the value of a setting that is either `true` or `false`
+fn parse_bool(line_num: usize, key: &str, value: &str) -> Result<bool, io::Error> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(config_error(line_num, &format!("{} must be true or false", key))),
+    }
+}
+
+// Builds the error returned for a malformed config line
+fn config_error(line_num: usize, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line_num + 1, msg))
 }
 
 /// Creates the default configuration file in the default location. Will
 /// return error if for some reason cannot it cannot write default config to path
 /// Expects `file_path` to be a valid file path, since it cannot verify the path
 /// of an unwritten file
-pub fn create_default_config(file_path: &str, def_paths: &Vec<String>) -> Result<(), io::Error> {
+pub fn create_default_config<P: AsRef<Path>>(file_path: P, def_paths: &[String]) -> Result<(), io::Error> {
     // Need to use openoptions to write to a file (the regular create file
     // creates a file in read-only mode)
     let mut file = File::create(file_path)?;
 
     // Write each path into the config file
+    file.write_all("[paths]\n".as_bytes())?;
     for line in def_paths {
-        file.write(line.as_bytes())?;
-        file.write("\n".as_bytes())?;
+        file.write_all(line.as_bytes())?;
+        file.write_all("\n".as_bytes())?;
     }
     file.sync_all()?;
     Ok(())
@@ -77,6 +291,8 @@ pub fn create_default_config(file_path: &str, def_paths: &Vec<String>) -> Result
 mod tests {
     use super::*;
     use std::env;
+    use std::fs;
+    use consts::DEFAULT_PATHS;
 
     // Returns a vector of default paths for use in tests
     fn create_def_paths() -> Vec<String> {
@@ -87,6 +303,14 @@ mod tests {
         paths
     }
 
+    // Returns an empty scratch directory under the temp dir
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
     // Test if the config file is able to write to the filesystem
     #[test]
     fn test_write_config_fs() {
@@ -102,8 +326,7 @@ mod tests {
         let tmp_dir = env::temp_dir();
         test_write_config_fs(); // need to write file before we read it
         let path = format!("{}/test_config_write", tmp_dir.to_str().unwrap());
-        let default_paths = create_def_paths();
-        load_paths_from_config(Some(&path), &default_paths);
+        load_config(&path).unwrap();
     }
 
     // Tests that the functions are able to both read and write to a file
@@ -114,8 +337,8 @@ mod tests {
         let config_path = format!("{}/test_config_rw", tmp_dir.to_str().unwrap());
         let default_paths = create_def_paths();
         create_default_config(&config_path, &default_paths).expect("Unable to write config file");
-        let paths = load_paths_from_config(Some(&config_path), &default_paths);
-        assert_eq!(paths.len(), default_paths.len());
+        let config = load_config(&config_path).unwrap();
+        assert_eq!(config.paths.len(), default_paths.len());
     }
 
     // Tests that function can create the default config if no config path is
@@ -132,4 +355,143 @@ mod tests {
         }
         create_default_config(tmp_fp.as_str(), &default_paths).unwrap();
     }
+
+    // Tests that reading a config that doesn't exist is an error rather than
+    // a panic
+    #[test]
+    fn test_read_missing_config() {
+        let dir = scratch_dir("ensh_config_missing");
+        assert!(load_config(dir.join("nope")).is_err());
+    }
+
+    // Tests that the argument wins over `$ENSH_CONFIG`, and that a tilde
+    // is expanded in either
+    #[test]
+    fn test_explicit_config_path() {
+        let home = home_dir().unwrap();
+        let env_value = Some(OsString::from("~/env_config"));
+        assert_eq!(explicit_config_path(None, env_value.clone()), Some(home.join("env_config")));
+        assert_eq!(explicit_config_path(Some(Path::new("~/arg")), env_value), Some(home.join("arg")));
+        assert_eq!(explicit_config_path(None, Some(OsString::from("/abs"))), Some(PathBuf::from("/abs")));
+        assert_eq!(explicit_config_path(None, None), None);
+    }
+
+    // Tests that the XDG location is searched before the home config, and
+    // that a relative XDG_CONFIG_HOME is ignored
+    #[test]
+    fn test_implicit_config_order() {
+        let home = PathBuf::from("/home/user");
+        let paths = implicit_config_paths(Some(OsString::from("/xdg")), Some(home.clone()));
+        assert_eq!(paths, vec![
+            PathBuf::from("/xdg/ensh/config"),
+            PathBuf::from("/home/user/.ensh_config"),
+        ]);
+
+        let paths = implicit_config_paths(Some(OsString::from("relative")), Some(home));
+        assert_eq!(paths[0], PathBuf::from("/home/user/.config/ensh/config"));
+    }
+
+    // Tests that an explicitly requested config that doesn't exist is an
+    // error, and that nothing gets created in its place
+    #[test]
+    fn test_explicit_config_missing() {
+        let dir = scratch_dir("ensh_config_explicit");
+        let explicit = dir.join("missing");
+        let fallback = dir.join("fallback");
+        let result = search_config(Some(explicit.clone()), &[], Some(fallback.clone()), &create_def_paths());
+        assert!(result.is_err());
+        assert!(!explicit.exists());
+        assert!(!fallback.exists());
+    }
+
+    // Tests that the first existing implicit config wins, and that the
+    // default config is only created at the fallback location
+    #[test]
+    fn test_search_implicit_config() {
+        let dir = scratch_dir("ensh_config_search");
+        let first = dir.join("first");
+        let second = dir.join("second");
+        let fallback = dir.join("fallback");
+        let def_paths = create_def_paths();
+        let candidates = vec![first.clone(), second.clone()];
+
+        // Nothing exists yet, so the default should be created
+        let found = search_config(None, &candidates, Some(fallback.clone()), &def_paths).unwrap();
+        assert_eq!(found, fallback);
+        assert!(fallback.is_file());
+
+        create_default_config(&second, &def_paths).unwrap();
+        let found = search_config(None, &candidates, Some(fallback.clone()), &def_paths).unwrap();
+        assert_eq!(found, second);
+
+        create_default_config(&first, &def_paths).unwrap();
+        let found = search_config(None, &candidates, Some(fallback), &def_paths).unwrap();
+        assert_eq!(found, first);
+    }
+
+    // Tests that a config from before sections existed is read as a list of
+    // paths
+    #[test]
+    fn test_parse_legacy_config() {
+        let config = parse_config("/\n/nonexistent\n".as_bytes()).unwrap();
+        assert_eq!(config.paths, vec!["/".to_string()]);
+        assert_eq!(config.prompt, None);
+    }
+
+    // Tests that a path starting with a tilde is kept with the tilde
+    // expanded
+    #[test]
+    fn test_parse_home_path() {
+        let config = parse_config("~/.
+~/ensh_nonexistent
+".as_bytes()).unwrap();
+        assert_eq!(config.paths, vec![home_dir().unwrap().join(".")]);
+    }
+
+    // Tests that every section of the config is parsed
+    #[test]
+    fn test_parse_sections() {
+        let contents = "\
+# paths first
+/
+[prompt]
+symbol = \"$ \"
+show_dir_stack = true
+[env]
+EDITOR = vim
+GREETING=hello world
+[aliases]
+ll = ls -la
+sudo = \"sudo \"
+[options]
+auto_reload = true
+glob_nomatch = empty
+";
+        let config = parse_config(contents.as_bytes()).unwrap();
+        assert_eq!(config.paths, vec!["/".to_string()]);
+        assert_eq!(config.prompt, Some("$ ".to_string()));
+        assert!(config.show_dir_stack);
+        assert_eq!(config.env, vec![
+            ("EDITOR".to_string(), "vim".to_string()),
+            ("GREETING".to_string(), "hello world".to_string()),
+        ]);
+        assert_eq!(config.aliases, vec![
+            ("ll".to_string(), "ls -la".to_string()),
+            ("sudo".to_string(), "sudo ".to_string()),
+        ]);
+        assert!(config.auto_reload);
+        assert_eq!(config.glob_nomatch, NoMatch::Empty);
+    }
+
+    // Tests that malformed configs are rejected with the offending line
+    #[test]
+    fn test_parse_bad_config() {
+        let err = parse_config("[bogus]\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+        assert!(parse_config("[env]\nNOVALUE\n".as_bytes()).is_err());
+        assert!(parse_config("[options]\nauto_reload = maybe\n".as_bytes()).is_err());
+        assert!(parse_config("[options]\nglob_nomatch = maybe\n".as_bytes()).is_err());
+        assert!(parse_config("[prompt]\ncolour = red\n".as_bytes()).is_err());
+        assert!(parse_config("[aliases]\nl l = ls\n".as_bytes()).is_err());
+    }
 }
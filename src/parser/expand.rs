@@ -0,0 +1,1593 @@
+//! expand.rs    Afnan Enayet
+//!
+//! The expand module turns the words from the lexer into the arguments that
+//! are passed to a command. Expansion happens in this order:
+//! 1. brace expansion, for `{a,b}` and `{1..10}` (see `brace`), which
+//!    turns one word into several
+//! 2. tilde expansion, for an unquoted `~` at the start of a word
+//! 3. parameter expansion, for `$name`, `${name}` and the special
+//!    parameters (`$1`, `$#`, `$@`, `$*`, `$?`, `$$` and `$0`), along
+//!    with array elements (`${name[i]}`, `${name[@]}`), the
+//!    `${name...}` modifiers (defaults, lengths, pattern removal and
+//!    replacement, substrings), and
+//!    command substitution, for `$(command)` and `` `command` ``, and
+//!    arithmetic expansion, for `$((expression))`
+//! 4. field splitting, on `$IFS`, of the results of unquoted expansions
+//! 5. filename expansion, for unquoted glob patterns
+//! 6. quote removal
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+use std::process;
+
+use shell::Shell;
+use vars::{self, split_subscript, valid_name, Key};
+use arith;
+use cmd_dispatch;
+use exec;
+use super::tilde_home;
+use super::lexer::{arith_end, backquote_end, brace_end, substitution_end, tokenize, Op, Token};
+use super::brace::expand_braces;
+use super::glob::{char_boundaries, expand_glob, has_wildcards, NoMatch, Pattern};
+
+// The name the shell gives for `$0`
+const SHELL_NAME: &str = "ensh";
+
+// The field separators used when `$IFS` isn't set
+const DEFAULT_IFS: &[u8] = b" \t\n";
+
+// Where a byte of a word came from, which decides the expansions that still
+// apply to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin {
+    // Unquoted text from the input, which can be a glob pattern
+    Literal,
+    // Quoted text, including the results of expansions inside quotes
+    Quoted,
+    // The result of an unquoted expansion, which is split into fields and
+    // can be a glob pattern
+    Expanded,
+}
+
+// A word being expanded, as bytes that each remember where they came from.
+// Expansions can bring in bytes that aren't valid UTF-8 (from a directory
+// name, say), so this can't be a string
+#[derive(Debug, Clone, Default)]
+struct Field {
+    bytes: Vec<(u8, Origin)>,
+
+    // Whether the field is kept even if it ends up empty, which it is if
+    // it had quotes in it (`""` is an empty argument, `$unset` is none)
+    keep: bool,
+}
+
+impl Field {
+    // Appends bytes that all came from the same place
+    fn push_bytes(&mut self, bytes: &[u8], origin: Origin) {
+        self.bytes.extend(bytes.iter().map(|&b| (b, origin)));
+    }
+
+    // Appends a character
+    fn push_char(&mut self, c: char, origin: Origin) {
+        let mut buf = [0; 4];
+        self.push_bytes(c.encode_utf8(&mut buf).as_bytes(), origin);
+    }
+
+    // Returns the bytes with the quoting information dropped
+    fn into_os_string(self) -> OsString {
+        OsString::from_vec(self.bytes.into_iter().map(|(b, _)| b).collect())
+    }
+}
+
+// The value of a parameter. Most are a single string, but `$@` and `$*`
+// are lists, which `$@` keeps apart and `$*` joins when quoted
+#[derive(Debug, Clone)]
+enum Value {
+    Scalar(OsString),
+    List(Vec<OsString>),
+    Joined(Vec<OsString>),
+}
+
+impl Value {
+    // Applies a function to each string in the value
+    fn map<F: FnMut(&[u8]) -> Vec<u8>>(self, mut f: F) -> Value {
+        let mut apply = |s: OsString| OsString::from_vec(f(s.as_bytes()));
+
+        match self {
+            Value::Scalar(s) => Value::Scalar(apply(s)),
+            Value::List(items) => Value::List(items.into_iter().map(apply).collect()),
+            Value::Joined(items) => Value::Joined(items.into_iter().map(apply).collect()),
+        }
+    }
+
+    // Returns whether the value is empty, which `${name:-word}` treats the
+    // same as unset
+    fn is_null(&self) -> bool {
+        match *self {
+            Value::Scalar(ref s) => s.is_empty(),
+            Value::List(ref items) | Value::Joined(ref items) => items.iter().all(|item| item.is_empty()),
+        }
+    }
+}
+
+/// An error found while expanding a word
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpandError {
+    /// A glob pattern didn't match anything and the shell is set to treat
+    /// that as an error
+    NoMatch(OsString),
+
+    /// A `${...}` expansion that doesn't make sense
+    BadSubstitution(String),
+
+    /// A command substitution that couldn't be parsed or run
+    CommandSubstitution(String),
+
+    /// An arithmetic expression that couldn't be evaluated
+    Arithmetic(String),
+
+    /// A `${name?message}` of a parameter that isn't set, or some other
+    /// misuse of a parameter
+    Parameter(String),
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExpandError::NoMatch(ref pattern) => write!(f, "no matches found: {}", pattern.to_string_lossy()),
+            ExpandError::BadSubstitution(ref text) => write!(f, "{}: bad substitution", text),
+            ExpandError::CommandSubstitution(ref e) => write!(f, "{}", e),
+            ExpandError::Arithmetic(ref e) | ExpandError::Parameter(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Expands each of the words into zero or more arguments
+pub fn expand_words(words: &[String], shell: &mut Shell) -> Result<Vec<OsString>, ExpandError> {
+    let mut args = Vec::new();
+
+    for word in words.iter().flat_map(|word| expand_braces(word)) {
+        args.extend(expand_word(&word, shell)?);
+    }
+    Ok(args)
+}
+
+/// Expands a word into exactly one string, without field splitting or
+/// filename expansion. This is how the value of an assignment is expanded
+pub fn expand_string(word: &str, shell: &mut Shell) -> Result<OsString, ExpandError> {
+    expand_assignment(word, shell).map(|(value, _)| value)
+}
+
+/// Expands the value of an assignment the way `expand_string` does. Also
+/// returns the status of the last command substitution in it, if there was
+/// one
+pub fn expand_assignment(word: &str, shell: &mut Shell) -> Result<(OsString, Option<i32>), ExpandError> {
+    let (fields, status) = Expander::new(shell).expand_with_status(word)?;
+    Ok((join_fields(fields).into_os_string(), status))
+}
+
+/// Expands a word into a single glob pattern, like a `case` pattern. The
+/// quoted parts of the word are escaped so they only match literally
+pub fn expand_pattern(word: &str, shell: &mut Shell) -> Result<OsString, ExpandError> {
+    let fields = Expander::new(shell).expand(word)?;
+    Ok(to_pattern(&join_fields(fields)))
+}
+
+/// Expands the body of a here-document. Parameters, command substitutions
+/// and arithmetic are expanded like inside double quotes, but quotes are
+/// just characters. A backslash only escapes `$`, `` ` ``, `\` and a line
+/// break, which joins two lines
+pub fn expand_here_doc(body: &str, shell: &mut Shell) -> Result<OsString, ExpandError> {
+    let chars: Vec<char> = body.chars().collect();
+    let mut expander = Expander::new(shell);
+    let mut i = 0;
+
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('\\', Some('\n')) => i += 2,
+            ('\\', Some(&next)) if "$`\\".contains(next) => {
+                expander.field().push_char(next, Origin::Quoted);
+                i += 2;
+            }
+            ('$', _) => i = expander.parameter(&chars, i, true)?,
+            ('`', _) => i = expander.backquoted(&chars, i, true)?,
+            (c, _) => {
+                expander.field().push_char(c, Origin::Quoted);
+                i += 1;
+            }
+        }
+    }
+    Ok(join_fields(expander.fields).into_os_string())
+}
+
+/// Evaluates an arithmetic expression, like the inside of `(( ))`, after
+/// expanding it like the inside of double quotes
+pub fn expand_arithmetic(expr: &str, shell: &mut Shell) -> Result<i64, ExpandError> {
+    let chars: Vec<char> = expr.chars().collect();
+    Expander::new(shell).arithmetic(&chars)
+}
+
+/// Expands a word into a single extended regular expression, like the
+/// right side of `=~`. The quoted parts of the word are escaped so they only
+/// match literally
+pub fn expand_regex(word: &str, shell: &mut Shell) -> Result<OsString, ExpandError> {
+    let fields = Expander::new(shell).expand(word)?;
+    let mut regex = Vec::new();
+
+    for (b, origin) in join_fields(fields).bytes {
+        if origin == Origin::Quoted && b"\\.[]()*+?{}|^$".contains(&b) {
+            regex.push(b'\\');
+        }
+        regex.push(b);
+    }
+    Ok(OsString::from_vec(regex))
+}
+
+/// Works out which element of an array `name[subscript]` picks out. The
+/// subscript of an associative array is expanded like the inside of
+/// double quotes, the subscript of an indexed array is an arithmetic
+/// expression
+pub fn expand_key(name: &str, subscript: &str, shell: &mut Shell) -> Result<Key, ExpandError> {
+    let chars: Vec<char> = subscript.chars().collect();
+    Expander::new(shell).key(name, &chars)
+}
+
+/// Expands the inside of an array assignment, `name=(a b c)`, into the
+/// value of the array. Elements can be given their key with `[key]=value`,
+/// which they need for an associative array (if `assoc` is set). Any other
+/// words are expanded like arguments and go after the last element
+pub fn expand_array(text: &str, assoc: bool, shell: &mut Shell) -> Result<vars::Value, ExpandError> {
+    let syntax_error = |text: &str| ExpandError::Parameter(format!("({}): syntax error in array", text));
+    let tokens = tokenize(text).map_err(|_| syntax_error(text))?;
+    let mut indexed = BTreeMap::new();
+    let mut named = BTreeMap::new();
+    let mut next = 0;
+
+    for token in tokens {
+        let word = match token {
+            Token::Word(word) => word,
+            Token::Op(Op::Newline) => continue,
+            _ => return Err(syntax_error(text)),
+        };
+
+        match split_keyed(&word) {
+            Some((key, value)) => {
+                let value = expand_string(value, shell)?;
+                match expand_key_for(key, assoc, shell)? {
+                    Key::Name(key) => {
+                        named.insert(key, value);
+                    }
+                    Key::Index(index) => {
+                        let index = usize::try_from(index)
+                            .map_err(|_| ExpandError::Parameter(format!("[{}]: bad array subscript", key)))?;
+                        indexed.insert(index, value);
+                        next = index + 1;
+                    }
+                }
+            }
+            None if assoc => {
+                return Err(ExpandError::Parameter(format!(
+                    "{}: must use subscript when assigning associative array",
+                    word
+                )))
+            }
+            None => {
+                for value in expand_word(&word, shell)? {
+                    indexed.insert(next, value);
+                    next += 1;
+                }
+            }
+        }
+    }
+
+    Ok(if assoc { vars::Value::Assoc(named) } else { vars::Value::Indexed(indexed) })
+}
+
+// Works out the key of an element in an array assignment
+fn expand_key_for(key: &str, assoc: bool, shell: &mut Shell) -> Result<Key, ExpandError> {
+    if assoc {
+        Ok(Key::Name(expand_string(key, shell)?.to_string_lossy().into_owned()))
+    } else {
+        expand_arithmetic(key, shell).map(Key::Index)
+    }
+}
+
+// Splits an element of an array assignment, `[key]=value`, into the key
+// and the value. Returns `None` if the element doesn't have a key
+fn split_keyed(word: &str) -> Option<(&str, &str)> {
+    let rest = word.strip_prefix('[')?;
+    let close = rest.find("]=")?;
+    Some((&rest[..close], &rest[close + 2..]))
+}
+
+// Joins fields into one, separated by spaces
+fn join_fields(fields: Vec<Field>) -> Field {
+    let mut joined = Field::default();
+
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            joined.push_bytes(b" ", Origin::Quoted);
+        }
+        joined.bytes.extend(field.bytes);
+    }
+    joined
+}
+
+// Expands a single word
+fn expand_word(word: &str, shell: &mut Shell) -> Result<Vec<OsString>, ExpandError> {
+    let ifs = shell.vars().get("IFS").map(OsString::into_vec);
+    let ifs = ifs.as_ref().map_or(DEFAULT_IFS, Vec::as_slice);
+    let mut args = Vec::new();
+
+    for field in Expander::new(shell).expand(word)? {
+        for piece in split_field(field, ifs) {
+            args.extend(expand_pathname(piece, shell.working_dir(), shell.glob_nomatch())?);
+        }
+    }
+    Ok(args)
+}
+
+// Walks through a word, removing quotes and expanding tildes, parameters
+// and command substitutions. A word usually gives one field, but `"$@"`
+// gives one for each positional parameter
+struct Expander<'a> {
+    shell: &'a mut Shell,
+    fields: Vec<Field>,
+
+    // The status of the last command substitution
+    status: Option<i32>,
+
+    // Set when a list like `$@` or `${name[@]}` turned out to be empty,
+    // which inside double quotes gives no field at all
+    vanished: bool,
+}
+
+impl<'a> Expander<'a> {
+    fn new(shell: &'a mut Shell) -> Expander<'a> {
+        Expander {
+            shell,
+            fields: vec![Field::default()],
+            status: None,
+            vanished: false,
+        }
+    }
+
+    // The field being added to
+    fn field(&mut self) -> &mut Field {
+        // There is always at least one field
+        let last = self.fields.len() - 1;
+        &mut self.fields[last]
+    }
+
+    // Expands the word into fields
+    fn expand(self, word: &str) -> Result<Vec<Field>, ExpandError> {
+        self.expand_with_status(word).map(|(fields, _)| fields)
+    }
+
+    // Expands the word into fields, also returning the status of the last
+    // command substitution in it
+    fn expand_with_status(mut self, word: &str) -> Result<(Vec<Field>, Option<i32>), ExpandError> {
+        let chars: Vec<char> = word.chars().collect();
+        self.unquoted(&chars)?;
+        Ok((self.fields, self.status))
+    }
+
+    // Expands unquoted text, starting with a tilde prefix if it has one
+    fn unquoted(&mut self, chars: &[char]) -> Result<(), ExpandError> {
+        let mut i = self.tilde(chars);
+
+        while i < chars.len() {
+            match chars[i] {
+                '\\' => {
+                    if let Some(&escaped) = chars.get(i + 1) {
+                        self.field().push_char(escaped, Origin::Quoted);
+                    }
+                    i += 2;
+                }
+                '\'' => {
+                    i += 1;
+                    while i < chars.len() && chars[i] != '\'' {
+                        self.field().push_char(chars[i], Origin::Quoted);
+                        i += 1;
+                    }
+                    self.field().keep = true;
+                    i += 1;
+                }
+                '"' => i = self.double_quoted(chars, i + 1)?,
+                '$' => i = self.parameter(chars, i, false)?,
+                '`' => i = self.backquoted(chars, i, false)?,
+                '<' | '>' if chars.get(i + 1) == Some(&'(') => i = self.process_substitution(chars, i)?,
+                c => {
+                    self.field().push_char(c, Origin::Literal);
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Expands the inside of double quotes, starting at `chars[start]`.
+    // Returns the index after the closing quote
+    fn double_quoted(&mut self, chars: &[char], start: usize) -> Result<usize, ExpandError> {
+        let mut i = start;
+
+        // `"$@"` with no positional parameters gives no field at all, so
+        // the quotes only keep the field if something else is in them
+        let mut kept = false;
+
+        while i < chars.len() && chars[i] != '"' {
+            match chars[i] {
+                // Inside double quotes a backslash only escapes the
+                // characters that are special there
+                '\\' => {
+                    match chars.get(i + 1) {
+                        Some(&next) if "$`\"\\".contains(next) => {
+                            self.field().push_char(next, Origin::Quoted);
+                            i += 2;
+                        }
+                        _ => {
+                            self.field().push_char('\\', Origin::Quoted);
+                            i += 1;
+                        }
+                    }
+                    kept = true;
+                }
+                '$' => {
+                    self.vanished = false;
+                    let end = self.parameter(chars, i, true)?;
+                    let text: String = chars[i..end].iter().collect();
+                    let args = (text != "$@" && text != "${@}") || !self.shell.vars().args().is_empty();
+                    kept |= args && !self.vanished;
+                    i = end;
+                }
+                '`' => {
+                    i = self.backquoted(chars, i, true)?;
+                    kept = true;
+                }
+                c => {
+                    self.field().push_char(c, Origin::Quoted);
+                    kept = true;
+                    i += 1;
+                }
+            }
+        }
+
+        if kept || i == start {
+            self.field().keep = true;
+        }
+        Ok(i + 1)
+    }
+
+    // Replaces a tilde prefix (an unquoted `~` up to the first `/`) with
+    // the directory it names: `~` is the home directory, `~user` is that
+    // user's home, `~+` is the working directory and `~-` is the previous
+    // one. If the directory is unknown the word is left alone. The
+    // replacement counts as quoted, so a home directory with a `*` in it
+    // won't be globbed. Returns the index of the first character after the
+    // prefix
+    fn tilde(&mut self, chars: &[char]) -> usize {
+        if chars.first() != Some(&'~') {
+            return 0;
+        }
+
+        let end = chars.iter().position(|&c| c == '/').unwrap_or(chars.len());
+        if chars[1..end].iter().any(|c| "'\"\\$".contains(*c)) {
+            return 0;
+        }
+
+        let name: String = chars[1..end].iter().collect();
+        let dir = match name.as_str() {
+            "+" => Some(self.shell.working_dir().to_path_buf()),
+            "-" => self.shell.previous_dir().map(Path::to_path_buf),
+            _ => tilde_home(&name),
+        };
+
+        match dir {
+            Some(dir) => {
+                self.field().push_bytes(dir.as_os_str().as_bytes(), Origin::Quoted);
+                end
+            }
+            None => 0,
+        }
+    }
+
+    // Expands the parameter at `chars[start]` (the `$`). A `$` that doesn't
+    // start a parameter is kept as it is. Returns the index after the
+    // parameter
+    fn parameter(&mut self, chars: &[char], start: usize, quoted: bool) -> Result<usize, ExpandError> {
+        let text_origin = if quoted { Origin::Quoted } else { Origin::Literal };
+        let (name, end) = match chars.get(start + 1) {
+            Some('(') if chars.get(start + 2) == Some(&'(') => {
+                let end = arith_end(chars, start + 1).map_err(|e| ExpandError::CommandSubstitution(e.to_string()))?;
+                if let Some(end) = end {
+                    let value = self.arithmetic(&chars[start + 3..end - 2])?;
+                    let origin = if quoted { Origin::Quoted } else { Origin::Expanded };
+                    self.field().push_bytes(value.to_string().as_bytes(), origin);
+                    return Ok(end);
+                }
+
+                // `$((a) (b))` is a command substitution of two subshells
+                let end = substitution_end(chars, start + 2)
+                    .map_err(|e| ExpandError::CommandSubstitution(e.to_string()))?;
+                let command: String = chars[start + 2..end - 1].iter().collect();
+                self.substitute(&command, quoted)?;
+                return Ok(end);
+            }
+            Some('(') => {
+                let end = substitution_end(chars, start + 2)
+                    .map_err(|e| ExpandError::CommandSubstitution(e.to_string()))?;
+                let command: String = chars[start + 2..end - 1].iter().collect();
+                self.substitute(&command, quoted)?;
+                return Ok(end);
+            }
+            Some('{') => {
+                let end = brace_end(chars, start + 2, quoted)
+                    .map_err(|_| ExpandError::BadSubstitution(chars[start..].iter().collect()))?;
+                self.braced(&chars[start..end], quoted)?;
+                return Ok(end);
+            }
+            Some(&c) if c.is_ascii_digit() || is_special(c.encode_utf8(&mut [0; 4])) => {
+                (c.to_string(), start + 2)
+            }
+            Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+                let len = chars[start + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .count();
+                (chars[start + 1..start + 1 + len].iter().collect(), start + 1 + len)
+            }
+            _ => {
+                self.field().push_char('$', text_origin);
+                return Ok(start + 1);
+            }
+        };
+
+        match self.lookup(&name) {
+            Some(value) => self.push_value(value, quoted),
+            None => self.unset(&name)?,
+        }
+        Ok(end)
+    }
+
+    // Deals with a parameter that isn't set being expanded, which is an
+    // error under `set -u`. `$@`, `$*` and whole arrays are allowed to be
+    // empty
+    fn unset(&self, name: &str) -> Result<(), ExpandError> {
+        let all = name == "@" || name == "*" || name.ends_with("[@]") || name.ends_with("[*]");
+        if self.shell.options().nounset && !all {
+            return Err(ExpandError::Parameter(format!("{}: unbound variable", name)));
+        }
+        Ok(())
+    }
+
+    // Expands `${...}`, which is all of `text`. Besides `${name}`, this
+    // can be:
+    // * `${name[subscript]}`, an element of an array, or all of them for a
+    //   subscript of `@` or `*`
+    // * `${#name}`, the length of the value, or `${#name[@]}`, the number
+    //   of elements
+    // * `${!name[@]}`, the keys of an array
+    // * `${name:-word}`, `${name:=word}`, `${name:?word}` and
+    //   `${name:+word}`, which depend on whether the parameter is set
+    // * `${name#pattern}`, `${name##pattern}`, `${name%pattern}` and
+    //   `${name%%pattern}`, which remove a prefix or a suffix
+    // * `${name/pattern/string}`, which replaces matches of the pattern
+    // * `${name:offset:length}`, a substring
+    fn braced(&mut self, text: &[char], quoted: bool) -> Result<(), ExpandError> {
+        let bad = || ExpandError::BadSubstitution(text.iter().collect());
+        let inner = &text[2..text.len() - 1];
+        let origin = if quoted { Origin::Quoted } else { Origin::Expanded };
+
+        let (prefix, inner) = match inner.first() {
+            Some(&c) if (c == '#' || c == '!') && inner.len() > 1 => (Some(c), &inner[1..]),
+            _ => (None, inner),
+        };
+
+        let len = param_len(inner);
+        if len == 0 {
+            return Err(bad());
+        }
+        let param: String = inner[..len].iter().collect();
+
+        match prefix {
+            Some('#') if len == inner.len() => {
+                let length = match self.lookup_param(&param)? {
+                    Some(Value::Scalar(value)) => char_boundaries(value.as_bytes()).len() - 1,
+                    Some(Value::List(items)) | Some(Value::Joined(items)) => items.len(),
+                    None => {
+                        self.unset(&param)?;
+                        0
+                    }
+                };
+                self.field().push_bytes(length.to_string().as_bytes(), origin);
+                return Ok(());
+            }
+            Some('!') if len == inner.len() => {
+                let keys = match split_subscript(&param) {
+                    Some((name, subscript)) if subscript == "@" || subscript == "*" => {
+                        self.shell.vars().value(name).map_or_else(Vec::new, |value| value.keys())
+                    }
+                    _ => return Err(bad()),
+                };
+                let value = if param.ends_with("[@]") { Value::List(keys) } else { Value::Joined(keys) };
+                self.push_value(value, quoted);
+                return Ok(());
+            }
+            Some(_) => return Err(bad()),
+            None => {}
+        }
+
+        let value = self.lookup_param(&param)?;
+        let name = param.as_str();
+
+        let alternative = matches!(&inner[len..], [':', '-' | '=' | '?' | '+', ..] | ['-' | '=' | '?' | '+', ..]);
+        if value.is_none() && !alternative {
+            self.unset(name)?;
+        }
+
+        let value = match &inner[len..] {
+            [] => value,
+            [':', op @ ('-' | '=' | '?' | '+'), word @ ..] => {
+                return self.alternative(name, value, *op, true, word, quoted);
+            }
+            [op @ ('-' | '=' | '?' | '+'), word @ ..] => return self.alternative(name, value, *op, false, word, quoted),
+            ['#', '#', pattern @ ..] => self.trim(value, pattern, true, true)?,
+            ['#', pattern @ ..] => self.trim(value, pattern, true, false)?,
+            ['%', '%', pattern @ ..] => self.trim(value, pattern, false, true)?,
+            ['%', pattern @ ..] => self.trim(value, pattern, false, false)?,
+            ['/', rest @ ..] => self.replace(value, rest, quoted)?,
+            [':', range @ ..] => self.substring(name, value, range)?,
+            _ => return Err(bad()),
+        };
+
+        if let Some(value) = value {
+            self.push_value(value, quoted);
+        }
+        Ok(())
+    }
+
+    // Expands `${name-word}`, `${name=word}`, `${name?word}` and
+    // `${name+word}`. `-` gives the word if the parameter is unset, `=`
+    // also assigns it, `?` fails with it as the message and `+` gives it
+    // only if the parameter is set. With a colon (`${name:-word}`), a
+    // parameter that is set but empty counts as unset
+    fn alternative(
+        &mut self,
+        name: &str,
+        value: Option<Value>,
+        op: char,
+        colon: bool,
+        word: &[char],
+        quoted: bool,
+    ) -> Result<(), ExpandError> {
+        match (op, value.filter(|value| !colon || !value.is_null())) {
+            ('+', Some(_)) | ('-', None) => {
+                let field = self.expand_inner(word, quoted)?;
+                self.push_field(field, quoted);
+            }
+            ('=', None) => {
+                let value = self.expand_inner(word, quoted)?.into_os_string();
+                self.assign(name, value.clone())?;
+                self.push_value(Value::Scalar(value), quoted);
+            }
+            ('?', None) => {
+                let message = if word.is_empty() {
+                    "parameter null or not set".to_string()
+                } else {
+                    self.expand_inner(word, quoted)?.into_os_string().to_string_lossy().into_owned()
+                };
+                return Err(ExpandError::Parameter(format!("{}: {}", name, message)));
+            }
+            ('+', None) => {}
+            (_, Some(value)) => self.push_value(value, quoted),
+            (_, None) => {}
+        }
+        Ok(())
+    }
+
+    // Expands `${name#pattern}` and the like, which remove the shortest (or
+    // longest) prefix or suffix of the value that matches the pattern
+    fn trim(
+        &mut self,
+        value: Option<Value>,
+        pattern: &[char],
+        prefix: bool,
+        longest: bool,
+    ) -> Result<Option<Value>, ExpandError> {
+        let pattern = self.pattern(pattern)?;
+
+        Ok(value.map(|value| {
+            value.map(|s| {
+                if prefix {
+                    let start = pattern.match_prefix(OsStr::from_bytes(s), longest).unwrap_or(0);
+                    s[start..].to_vec()
+                } else {
+                    let end = pattern.match_suffix(OsStr::from_bytes(s), longest).unwrap_or(s.len());
+                    s[..end].to_vec()
+                }
+            })
+        }))
+    }
+
+    // Expands `${name/pattern/string}`, which replaces the longest match of
+    // the pattern with the string. `//` replaces every match, and `/#` and
+    // `/%` only match at the start or the end of the value
+    fn replace(&mut self, value: Option<Value>, rest: &[char], quoted: bool) -> Result<Option<Value>, ExpandError> {
+        let (anchor, rest) = match rest.first() {
+            Some(&c) if "/#%".contains(c) => (Some(c), &rest[1..]),
+            _ => (None, rest),
+        };
+        let (pattern, replacement) = match find_separator(rest) {
+            Some(slash) => (&rest[..slash], &rest[slash + 1..]),
+            None => (rest, &[][..]),
+        };
+
+        let pattern = self.pattern(pattern)?;
+        let replacement = self.expand_inner(replacement, quoted)?.into_os_string().into_vec();
+        Ok(value.map(|value| value.map(|s| replace_matches(s, &pattern, &replacement, anchor))))
+    }
+
+    // Expands `${name:offset}` and `${name:offset:length}`, where the offset
+    // and the length are arithmetic expressions. A negative offset counts
+    // back from the end, and a negative length leaves that many characters
+    // off the end. For `$@` and `$*` they pick out positional parameters,
+    // with `$0` at offset 0
+    fn substring(&mut self, name: &str, value: Option<Value>, range: &[char]) -> Result<Option<Value>, ExpandError> {
+        let (offset, length) = match range.iter().position(|&c| c == ':') {
+            Some(colon) => (&range[..colon], Some(&range[colon + 1..])),
+            None => (range, None),
+        };
+        let offset = self.arithmetic(offset)?;
+        let length = match length {
+            Some(length) => Some(self.arithmetic(length)?),
+            None => None,
+        };
+
+        let slice_items = |mut items: Vec<OsString>| -> Result<Vec<OsString>, ExpandError> {
+            if name == "@" || name == "*" {
+                items.insert(0, OsString::from(SHELL_NAME));
+            }
+            let (start, end) = slice_bounds(items.len(), offset, length)?;
+            Ok(items[start..end].to_vec())
+        };
+
+        Ok(Some(match value {
+            Some(Value::Scalar(s)) => {
+                let boundaries = char_boundaries(s.as_bytes());
+                let (start, end) = slice_bounds(boundaries.len() - 1, offset, length)?;
+                Value::Scalar(OsStr::from_bytes(&s.as_bytes()[boundaries[start]..boundaries[end]]).to_os_string())
+            }
+            Some(Value::List(items)) => Value::List(slice_items(items)?),
+            Some(Value::Joined(items)) => Value::Joined(slice_items(items)?),
+            None => return Ok(None),
+        }))
+    }
+
+    // Expands the backquoted command at `chars[start]` (the opening
+    // backquote). Inside backquotes a backslash only escapes `$`, `` ` ``
+    // and `\`. Returns the index after the closing backquote
+    fn backquoted(&mut self, chars: &[char], start: usize, quoted: bool) -> Result<usize, ExpandError> {
+        let end = backquote_end(chars, start + 1).map_err(|e| ExpandError::CommandSubstitution(e.to_string()))?;
+        let mut command = String::new();
+        let mut i = start + 1;
+
+        while i < end - 1 {
+            match (chars[i], chars.get(i + 1)) {
+                ('\\', Some(&next)) if "$`\\".contains(next) => {
+                    command.push(next);
+                    i += 2;
+                }
+                (c, _) => {
+                    command.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        self.substitute(&command, quoted)?;
+        Ok(end)
+    }
+
+    // Runs a command and adds its output, without the trailing line
+    // breaks. Unquoted, the output is split into fields and globbed. The
+    // command's status becomes `$?`
+    fn substitute(&mut self, command: &str, quoted: bool) -> Result<(), ExpandError> {
+        let list = cmd_dispatch::parse_input(self.shell, command)
+            .map_err(|e| ExpandError::CommandSubstitution(e.to_string()))?;
+        let (mut output, status) = exec::capture(&list, self.shell)
+            .map_err(|e| ExpandError::CommandSubstitution(format!("command substitution: {}", e)))?;
+
+        while output.last() == Some(&b'\n') {
+            output.pop();
+        }
+
+        let origin = if quoted { Origin::Quoted } else { Origin::Expanded };
+        self.field().push_bytes(&output, origin);
+        self.shell.set_status(status);
+        self.status = Some(status);
+        Ok(())
+    }
+
+    // Expands the process substitution, `<(list)` or `>(list)`, that starts
+    // at `chars[start]` into the path of its pipe. Returns the index after
+    // the closing parenthesis
+    fn process_substitution(&mut self, chars: &[char], start: usize) -> Result<usize, ExpandError> {
+        let end = substitution_end(chars, start + 2).map_err(|e| ExpandError::CommandSubstitution(e.to_string()))?;
+        let command: String = chars[start + 2..end - 1].iter().collect();
+
+        let list = cmd_dispatch::parse_input(self.shell, &command)
+            .map_err(|e| ExpandError::CommandSubstitution(e.to_string()))?;
+        let path = exec::substitute_process(&list, chars[start] == '>', self.shell)
+            .map_err(|e| ExpandError::CommandSubstitution(format!("process substitution: {}", e)))?;
+
+        self.field().push_bytes(path.as_os_str().as_bytes(), Origin::Quoted);
+        Ok(end)
+    }
+
+    // Evaluates an arithmetic expression. Quotes in it are removed, and it
+    // is expanded like the inside of double quotes first
+    fn arithmetic(&mut self, expr: &[char]) -> Result<i64, ExpandError> {
+        let text = self.expand_inner(expr, true)?.into_os_string();
+        let text = text.to_string_lossy();
+        arith::eval(&text, self.shell.vars_mut()).map_err(|e| ExpandError::Arithmetic(format!("{}: {}", text.trim(), e)))
+    }
+
+    // Expands a word that is part of another expansion, like the default
+    // of `${name:-word}`, into a single field. Inside double quotes
+    // (`quoted`) the word is expanded as if it were quoted too, and any
+    // double quotes in it are removed
+    fn expand_inner(&mut self, chars: &[char], quoted: bool) -> Result<Field, ExpandError> {
+        let mut inner = Expander::new(self.shell);
+
+        if quoted {
+            let mut i = 0;
+            while i < chars.len() {
+                i = inner.double_quoted(chars, i)?;
+            }
+        } else {
+            inner.unquoted(chars)?;
+        }
+        if inner.status.is_some() {
+            self.status = inner.status;
+        }
+
+        let keep = inner.fields.iter().any(|field| field.keep);
+        let mut field = join_fields(inner.fields);
+        field.keep = keep;
+        Ok(field)
+    }
+
+    // Expands a word into a glob pattern for one of the expansions that
+    // match patterns. The outer quotes don't apply to the pattern, only
+    // quotes inside of it make characters match literally
+    fn pattern(&mut self, chars: &[char]) -> Result<Pattern, ExpandError> {
+        let field = self.expand_inner(chars, false)?;
+        Ok(Pattern::new(to_pattern(&field)))
+    }
+
+    // Adds a field expanded by `expand_inner`. Unquoted, its literal text
+    // is split and globbed like the rest of the expansion is
+    fn push_field(&mut self, field: Field, quoted: bool) {
+        let keep = field.keep;
+        let bytes = field.bytes.into_iter().map(|(b, origin)| match origin {
+            Origin::Literal if !quoted => (b, Origin::Expanded),
+            _ => (b, origin),
+        });
+
+        self.field().bytes.extend(bytes);
+        self.field().keep |= keep;
+    }
+
+    // Adds the value of a parameter
+    fn push_value(&mut self, value: Value, quoted: bool) {
+        let origin = if quoted { Origin::Quoted } else { Origin::Expanded };
+
+        match value {
+            Value::Scalar(value) => self.field().push_bytes(value.as_bytes(), origin),
+            Value::List(items) => self.push_list(&items, true, quoted),
+            Value::Joined(items) => self.push_list(&items, false, quoted),
+        }
+    }
+
+    // Adds a list like `$@` or `$*`. Unquoted, both give each item as its
+    // own field (which is then split further). Quoted, `$@` still gives one
+    // field each but `$*` joins them all with the first character of
+    // `$IFS`
+    fn push_list(&mut self, items: &[OsString], at: bool, quoted: bool) {
+        if quoted && !at {
+            let separator = match self.shell.vars().get("IFS") {
+                Some(ifs) => ifs.as_bytes().first().cloned(),
+                None => Some(b' '),
+            };
+
+            for (i, arg) in items.iter().enumerate() {
+                if let (true, Some(separator)) = (i > 0, separator) {
+                    self.field().push_bytes(&[separator], Origin::Quoted);
+                }
+                self.field().push_bytes(arg.as_bytes(), Origin::Quoted);
+            }
+            return;
+        }
+
+        if at && items.is_empty() {
+            self.vanished = true;
+        }
+
+        let origin = if quoted { Origin::Quoted } else { Origin::Expanded };
+        for (i, arg) in items.iter().enumerate() {
+            if i > 0 {
+                self.fields.push(Field {
+                    bytes: Vec::new(),
+                    keep: quoted,
+                });
+            }
+            self.field().push_bytes(arg.as_bytes(), origin);
+        }
+    }
+
+    // Assigns to a variable or an element of an array, for `${name=word}`
+    fn assign(&mut self, name: &str, value: OsString) -> Result<(), ExpandError> {
+        if let Some((array, subscript)) = split_subscript(name) {
+            let subscript: Vec<char> = subscript.chars().collect();
+            let key = self.key(array, &subscript)?;
+            if self.shell.vars_mut().set_element(array, key, value) {
+                return Ok(());
+            }
+            return Err(ExpandError::Parameter(format!("{}: bad array subscript", name)));
+        }
+
+        if !valid_name(name) {
+            return Err(ExpandError::Parameter(format!("${}: cannot assign in this way", name)));
+        }
+        self.shell.vars_mut().set(name, value);
+        Ok(())
+    }
+
+    // Works out which element of the array `name` a subscript picks out
+    fn key(&mut self, name: &str, subscript: &[char]) -> Result<Key, ExpandError> {
+        if self.shell.vars().is_assoc(name) {
+            let key = self.expand_inner(subscript, true)?.into_os_string();
+            Ok(Key::Name(key.to_string_lossy().into_owned()))
+        } else {
+            self.arithmetic(subscript).map(Key::Index)
+        }
+    }
+
+    // Returns the value of a parameter that may be an array element,
+    // `name[subscript]`. `${name[@]}` and `${name[*]}` are all the elements,
+    // like `$@` and `$*`
+    fn lookup_param(&mut self, param: &str) -> Result<Option<Value>, ExpandError> {
+        let (name, subscript) = match split_subscript(param) {
+            Some(split) => split,
+            None => return Ok(self.lookup(param)),
+        };
+
+        let value = self.shell.vars().value(name);
+        match subscript {
+            "@" => Ok(Some(Value::List(value.map_or_else(Vec::new, |value| value.values())))),
+            "*" => Ok(value.map(|value| Value::Joined(value.values()))),
+            _ => {
+                let subscript: Vec<char> = subscript.chars().collect();
+                let key = self.key(name, &subscript)?;
+                Ok(value.and_then(|value| value.element(&key).cloned()).map(Value::Scalar))
+            }
+        }
+    }
+
+    // Returns the value of a parameter, or `None` if it isn't set. `$@`
+    // and `$*` are unset when there are no positional parameters
+    fn lookup(&self, name: &str) -> Option<Value> {
+        let args = self.shell.vars().args();
+
+        match name {
+            "@" | "*" if args.is_empty() => None,
+            "@" => Some(Value::List(args.to_vec())),
+            "*" => Some(Value::Joined(args.to_vec())),
+            _ => self.value(name).map(Value::Scalar),
+        }
+    }
+
+    // Returns the value of a parameter other than `$@` and `$*`
+    fn value(&self, name: &str) -> Option<OsString> {
+        let vars = self.shell.vars();
+
+        match name {
+            "#" => Some(OsString::from(vars.args().len().to_string())),
+            "?" => Some(OsString::from(self.shell.status().to_string())),
+            "$" => Some(OsString::from(process::id().to_string())),
+            "0" => Some(OsString::from(SHELL_NAME)),
+            _ => match name.parse::<usize>() {
+                Ok(0) => Some(OsString::from(SHELL_NAME)),
+                Ok(n) => vars.args().get(n - 1).cloned(),
+                Err(_) => vars.get(name),
+            },
+        }
+    }
+}
+
+// Returns whether a name is one of the special parameters
+fn is_special(name: &str) -> bool {
+    ["@", "*", "#", "?", "$"].contains(&name)
+}
+
+// Returns the length of the parameter name at the start of `chars`: a
+// special parameter, the number of a positional parameter or a variable
+// name
+fn name_len(chars: &[char]) -> usize {
+    match chars.first() {
+        Some(c) if c.is_ascii_digit() => chars.iter().take_while(|c| c.is_ascii_digit()).count(),
+        Some(&c) if is_special(c.encode_utf8(&mut [0; 4])) => 1,
+        Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+            chars.iter().take_while(|c| c.is_ascii_alphanumeric() || **c == '_').count()
+        }
+        _ => 0,
+    }
+}
+
+// Returns the length of the parameter at the start of `chars`, which is
+// a name followed by a subscript if it is an array element
+fn param_len(chars: &[char]) -> usize {
+    let len = name_len(chars);
+    if len == 0 || chars.get(len) != Some(&'[') || !chars[0].is_ascii_alphabetic() && chars[0] != '_' {
+        return len;
+    }
+
+    let mut depth = 0;
+    for (i, &c) in chars.iter().enumerate().skip(len) {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 1 => return i + 1,
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    0
+}
+
+// Returns the index of the `/` that separates the pattern of
+// `${name/pattern/string}` from the string, skipping any that are quoted or
+// part of a nested expansion
+fn find_separator(chars: &[char]) -> Option<usize> {
+    let mut i = 0;
+
+    while i < chars.len() {
+        i = match (chars[i], chars.get(i + 1)) {
+            ('/', _) => return Some(i),
+            ('\\', _) => i + 2,
+            (quote @ ('\'' | '"'), _) => match chars[i + 1..].iter().position(|&c| c == quote) {
+                Some(offset) => i + offset + 2,
+                None => chars.len(),
+            },
+            ('$', Some('{')) => brace_end(chars, i + 2, false).unwrap_or(chars.len()),
+            ('$', Some('(')) => substitution_end(chars, i + 2).unwrap_or(chars.len()),
+            _ => i + 1,
+        };
+    }
+    None
+}
+
+// Replaces the longest match of a pattern in `s`, or every match if
+// `anchor` is `/`. An anchor of `#` or `%` only matches at the start or at
+// the end of `s`
+fn replace_matches(s: &[u8], pattern: &Pattern, replacement: &[u8], anchor: Option<char>) -> Vec<u8> {
+    match anchor {
+        Some('#') => match pattern.match_prefix(OsStr::from_bytes(s), true) {
+            Some(end) => [replacement, &s[end..]].concat(),
+            None => s.to_vec(),
+        },
+        Some('%') => match pattern.match_suffix(OsStr::from_bytes(s), true) {
+            Some(start) => [&s[..start], replacement].concat(),
+            None => s.to_vec(),
+        },
+        _ => {
+            let boundaries = char_boundaries(s);
+            let mut result = Vec::new();
+            let mut i = 0;
+
+            while i + 1 < boundaries.len() {
+                let start = boundaries[i];
+                match pattern.match_prefix(OsStr::from_bytes(&s[start..]), true) {
+                    // Empty matches would replace between every character
+                    Some(len) if len > 0 => {
+                        result.extend_from_slice(replacement);
+                        if anchor != Some('/') {
+                            result.extend_from_slice(&s[start + len..]);
+                            return result;
+                        }
+                        i = boundaries.iter().position(|&b| b == start + len).unwrap_or(boundaries.len());
+                    }
+                    _ => {
+                        result.extend_from_slice(&s[start..boundaries[i + 1]]);
+                        i += 1;
+                    }
+                }
+            }
+            result
+        }
+    }
+}
+
+// Works out the part of `len` characters (or items) that
+// `${name:offset:length}` covers, as a range of indices. An offset out of
+// range gives nothing, and a length that would end before the offset is an
+// error
+fn slice_bounds(len: usize, offset: i64, length: Option<i64>) -> Result<(usize, usize), ExpandError> {
+    let len = len as i64;
+    let start = if offset < 0 { len.saturating_add(offset) } else { offset };
+    if start < 0 || start > len {
+        return Ok((0, 0));
+    }
+
+    let end = match length {
+        Some(length) if length < 0 => len.saturating_add(length),
+        Some(length) => start.saturating_add(length).min(len),
+        None => len,
+    };
+    if end < start {
+        return Err(ExpandError::Parameter(format!("{}: substring expression < 0", length.unwrap_or(0))));
+    }
+    Ok((start as usize, end as usize))
+}
+
+// Splits a field on the `$IFS` characters that came from unquoted
+// expansions. Runs of whitespace separators count as one, and leading or
+// trailing ones are dropped. Every other separator ends a field, even an
+// empty one
+fn split_field(field: Field, ifs: &[u8]) -> Vec<Field> {
+    let mut pieces = Vec::new();
+    let mut piece = Field::default();
+
+    // Whether the last piece was ended by whitespace, in which case a
+    // separator right after it doesn't end another (empty) piece
+    let mut after_space = false;
+
+    for (b, origin) in field.bytes {
+        if origin != Origin::Expanded || !ifs.contains(&b) {
+            piece.bytes.push((b, origin));
+            after_space = false;
+            continue;
+        }
+
+        if b.is_ascii_whitespace() {
+            if !piece.bytes.is_empty() {
+                pieces.push(piece);
+                piece = Field::default();
+                after_space = true;
+            }
+        } else if after_space {
+            after_space = false;
+        } else {
+            pieces.push(piece);
+            piece = Field::default();
+        }
+    }
+
+    if !piece.bytes.is_empty() || (field.keep && pieces.is_empty()) {
+        pieces.push(piece);
+    }
+    pieces
+}
+
+/// Splits a line read by `read` into at most `count` fields on the `$IFS`
+/// characters, like the result of an expansion. Bytes marked as escaped
+/// never separate fields. The last field gets the rest of the line,
+/// separators and all, less any whitespace separators at its end
+pub fn split_line(line: &[(u8, bool)], count: usize, shell: &Shell) -> Vec<OsString> {
+    let ifs = shell.vars().get("IFS").map(OsString::into_vec);
+    let ifs = ifs.as_ref().map_or(DEFAULT_IFS, Vec::as_slice);
+    let separates = |&(b, escaped): &(u8, bool)| !escaped && ifs.contains(&b);
+    let space = |c: &(u8, bool)| separates(c) && c.0.is_ascii_whitespace();
+
+    let mut fields = Vec::new();
+    let mut i = line.iter().take_while(|c| space(c)).count();
+
+    while i < line.len() {
+        if fields.len() + 1 == count {
+            let end = line.len() - line[i..].iter().rev().take_while(|c| space(c)).count();
+            fields.push(OsString::from_vec(line[i..end].iter().map(|&(b, _)| b).collect()));
+            break;
+        }
+
+        let len = line[i..].iter().take_while(|c| !separates(c)).count();
+        fields.push(OsString::from_vec(line[i..i + len].iter().map(|&(b, _)| b).collect()));
+        i += len;
+
+        // A separator is any whitespace around at most one other separator
+        i += line[i..].iter().take_while(|c| space(c)).count();
+        if i < line.len() && separates(&line[i]) && !space(&line[i]) {
+            i += 1;
+            i += line[i..].iter().take_while(|c| space(c)).count();
+        }
+    }
+    fields
+}
+
+// Turns a field into a glob pattern, escaping the quoted characters that
+// would otherwise be special
+fn to_pattern(field: &Field) -> OsString {
+    let mut pattern = Vec::with_capacity(field.bytes.len());
+
+    for &(b, origin) in &field.bytes {
+        if origin == Origin::Quoted && b"*?[]\\".contains(&b) {
+            pattern.push(b'\\');
+        }
+        pattern.push(b);
+    }
+    OsString::from_vec(pattern)
+}
+
+// Expands a word that is a glob pattern into the files it matches in `cwd`.
+// A word only turns into more (or fewer) than one argument if it is a glob
+// pattern
+fn expand_pathname(field: Field, cwd: &Path, nomatch: NoMatch) -> Result<Vec<OsString>, ExpandError> {
+    let pattern = to_pattern(&field);
+    let unquoted = field.into_os_string();
+
+    if !has_wildcards(&pattern) {
+        return Ok(vec![unquoted]);
+    }
+
+    let matches = expand_glob(&pattern, cwd);
+    if !matches.is_empty() {
+        return Ok(matches);
+    }
+
+    match nomatch {
+        NoMatch::Literal => Ok(vec![unquoted]),
+        NoMatch::Error => Err(ExpandError::NoMatch(unquoted)),
+        NoMatch::Empty => Ok(Vec::new()),
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
+    use std::fs;
+    use parser::lexer::{tokenize, quote, Token};
+
+    // Returns a shell whose working directory holds a few files
+    fn shell_with_files(name: &str) -> Shell {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        for file in &["one.rs", "two.rs", "*.rs"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let mut shell = Shell::default();
+        assert!(shell.change_working_dir(&dir));
+        shell
+    }
+
+    // Splits a command into its words
+    fn words(cmd: &str) -> Vec<String> {
+        tokenize(cmd)
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Word(word) => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Splits and expands a command, giving back strings to compare against
+    fn expand(cmd: &str, shell: &mut Shell) -> Result<Vec<String>, ExpandError> {
+        let args = expand_words(&words(cmd), shell)?;
+        Ok(args.into_iter().map(|arg| arg.to_string_lossy().into_owned()).collect())
+    }
+
+    // Builds an unexpanded field
+    fn literal(text: &str) -> Field {
+        let mut field = Field::default();
+        field.push_bytes(text.as_bytes(), Origin::Literal);
+        field
+    }
+
+    // Tests that unquoted patterns are expanded and quoted ones aren't
+    #[test]
+    fn test_expand_quoting() {
+        let mut shell = shell_with_files("ensh_expand_quoting");
+        assert_eq!(expand("ls *.rs", &mut shell).unwrap(), vec!["ls", "*.rs", "one.rs", "two.rs"]);
+        assert_eq!(expand("ls '*.rs' \\*.rs \"*\".rs", &mut shell).unwrap(), vec!["ls", "*.rs", "*.rs", "*.rs"]);
+        assert_eq!(expand("ls 'o'*", &mut shell).unwrap(), vec!["ls", "one.rs"]);
+        assert_eq!(expand("echo '' \"\" a\"\"", &mut shell).unwrap(), vec!["echo", "", "", "a"]);
+    }
+
+    // Tests each of the ways a pattern without matches can be handled
+    #[test]
+    fn test_expand_no_match() {
+        let mut shell = shell_with_files("ensh_expand_no_match");
+        assert_eq!(expand("ls *.txt", &mut shell).unwrap(), vec!["ls", "*.txt"]);
+        let cwd = shell.working_dir();
+        assert_eq!(expand_pathname(literal("*.txt"), cwd, NoMatch::Empty).unwrap(), Vec::<OsString>::new());
+        assert_eq!(
+            expand_pathname(literal("*.txt"), cwd, NoMatch::Error),
+            Err(ExpandError::NoMatch(OsString::from("*.txt")))
+        );
+    }
+
+    // Tests that quoted special characters are escaped in patterns
+    #[test]
+    fn test_to_pattern() {
+        let mut shell = Shell::default();
+        let mut pattern = |word: &str| to_pattern(&Expander::new(&mut shell).expand(word).unwrap()[0]);
+        assert_eq!(pattern("'*'*"), OsString::from("\\**"));
+        assert_eq!(pattern("\\\\a"), OsString::from("\\\\a"));
+    }
+
+    // Tests that a working directory that isn't valid UTF-8 comes through
+    // tilde expansion unchanged
+    #[test]
+    fn test_expand_tilde_invalid_utf8() {
+        let dir = env::temp_dir().join(OsStr::from_bytes(b"ensh_expand_\xff"));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut shell = Shell::default();
+        assert!(shell.change_working_dir(&dir));
+        let args = expand_words(&words("ls ~+/x"), &mut shell).unwrap();
+        assert_eq!(args[1], dir.join("x").into_os_string());
+    }
+
+    // Tests that only an unquoted tilde at the start of a word is expanded
+    #[test]
+    fn test_expand_tilde() {
+        let mut shell = shell_with_files("ensh_expand_tilde");
+        let home = tilde_home("").unwrap().to_string_lossy().into_owned();
+        let args = expand("echo ~ ~/x a~b '~' \\~ \"~\"/x ~'root'", &mut shell).unwrap();
+        assert_eq!(args, vec![
+            "echo".to_string(),
+            home.clone(),
+            format!("{}/x", home),
+            "a~b".to_string(),
+            "~".to_string(),
+            "~".to_string(),
+            "~/x".to_string(),
+            "~root".to_string(),
+        ]);
+    }
+
+    // Tests the working directory and previous directory prefixes
+    #[test]
+    fn test_expand_tilde_dirs() {
+        let mut shell = Shell::default();
+        assert_eq!(expand("echo ~-", &mut shell).unwrap(), vec!["echo", "~-"]);
+
+        assert!(shell.change_working_dir(Path::new("/")));
+        let previous = shell.previous_dir().unwrap().to_string_lossy().into_owned();
+        assert_eq!(expand("echo ~+ ~+/x ~-", &mut shell).unwrap(), vec!["echo".to_string(), "/".to_string(), "//x".to_string(), previous]);
+    }
+
+    // Tests expanding variables, quoted and unquoted
+    #[test]
+    fn test_expand_variables() {
+        let mut shell = Shell::default();
+        shell.vars_mut().set("ensh_x", OsString::from("a  b"));
+        shell.vars_mut().set("ensh_e", OsString::new());
+
+        assert_eq!(expand("echo $ensh_x \"$ensh_x\" ${ensh_x}c", &mut shell).unwrap(), vec!["echo", "a", "b", "a  b", "a", "bc"]);
+        assert_eq!(expand("echo $ensh_e \"$ensh_e\" $ensh_unset '$ensh_x'", &mut shell).unwrap(), vec!["echo", "", "$ensh_x"]);
+        assert_eq!(expand("echo $ $1x a$ \\$ensh_x", &mut shell).unwrap(), vec!["echo", "$", "x", "a$", "$ensh_x"]);
+        let unterminated = expand_words(&["${ensh_x".to_string()], &mut shell);
+        assert_eq!(unterminated, Err(ExpandError::BadSubstitution("${ensh_x".to_string())));
+        assert_eq!(expand("echo ${a b} ", &mut shell), Err(ExpandError::BadSubstitution("${a b}".to_string())));
+        assert_eq!(expand("echo ${#a:-b}", &mut shell), Err(ExpandError::BadSubstitution("${#a:-b}".to_string())));
+    }
+
+    // Tests that with `set -u` expanding a parameter that isn't set fails,
+    // unless an operator gives it a default
+    #[test]
+    fn test_expand_nounset() {
+        let mut shell = Shell::default();
+        shell.vars_mut().set("ensh_x", OsString::from("a"));
+        shell.options_mut().nounset = true;
+
+        let unbound = Err(ExpandError::Parameter("ensh_unset: unbound variable".to_string()));
+        assert_eq!(expand("echo $ensh_unset", &mut shell), unbound);
+        assert_eq!(expand("echo \"${ensh_unset}\"", &mut shell), unbound);
+        assert_eq!(expand("echo ${#ensh_unset} ${ensh_unset%a}", &mut shell), unbound);
+        let expanded = expand("echo $ensh_x ${ensh_unset-b} ${ensh_unset:+c} \"$@\" ${ensh_unset[@]}", &mut shell);
+        assert_eq!(expanded.unwrap(), vec!["echo", "a", "b"]);
+    }
+
+    // Tests the expansions that depend on whether a parameter is set
+    #[test]
+    fn test_expand_alternative() {
+        let mut shell = Shell::default();
+        shell.vars_mut().set("ensh_set", OsString::from("v"));
+        shell.vars_mut().set("ensh_empty", OsString::new());
+
+        let line = "echo ${ensh_set:-a} ${ensh_empty:-a b} \"${ensh_empty-a b}\" ${ensh_unset-'a  b'}";
+        assert_eq!(expand(line, &mut shell).unwrap(), vec!["echo", "v", "a", "b", "", "a  b"]);
+        let line = "echo ${ensh_set:+\"x y\"} ${ensh_empty+x} ${ensh_empty:+x} ${ensh_unset+x}";
+        assert_eq!(expand(line, &mut shell).unwrap(), vec!["echo", "x y", "x"]);
+
+        assert_eq!(expand("echo ${ensh_assigned:=$ensh_set*2}", &mut shell).unwrap(), vec!["echo", "v*2"]);
+        assert_eq!(shell.vars().get("ensh_assigned"), Some(OsString::from("v*2")));
+        assert_eq!(
+            expand("echo ${1:=x}", &mut shell),
+            Err(ExpandError::Parameter("$1: cannot assign in this way".to_string()))
+        );
+
+        assert_eq!(expand("echo ${ensh_set:?}", &mut shell).unwrap(), vec!["echo", "v"]);
+        assert_eq!(
+            expand("echo ${ensh_empty:?}", &mut shell),
+            Err(ExpandError::Parameter("ensh_empty: parameter null or not set".to_string()))
+        );
+        assert_eq!(
+            expand("echo ${ensh_unset?\"is $ensh_set\"}", &mut shell),
+            Err(ExpandError::Parameter("ensh_unset: is v".to_string()))
+        );
+    }
+
+    // Tests that brace expansion comes before the other expansions
+    #[test]
+    fn test_expand_braces() {
+        let mut shell = Shell::default();
+        shell.vars_mut().set("ensh_brace", OsString::from("1 2"));
+
+        let line = "echo {a,${ensh_brace}}x \"{a,b}\" ${ensh_brace/1/{a,b}}";
+        assert_eq!(expand(line, &mut shell).unwrap(), vec!["echo", "ax", "1", "2x", "{a,b}", "{a,b}", "2"]);
+    }
+
+    // Tests lengths, prefix and suffix removal, replacement and substrings
+    #[test]
+    fn test_expand_modifiers() {
+        let mut shell = Shell::default();
+        shell.vars_mut().set("ensh_path", OsString::from("/usr/lib/lib.so.1"));
+        shell.vars_mut().set("ensh_pat", OsString::from("*/"));
+
+        let line = "echo ${#ensh_path} ${ensh_path#*/} ${ensh_path##$ensh_pat} ${ensh_path%.*} ${ensh_path%%.*}";
+        assert_eq!(expand(line, &mut shell).unwrap(), vec!["echo", "17", "usr/lib/lib.so.1", "lib.so.1", "/usr/lib/lib.so", "/usr/lib/lib"]);
+        assert_eq!(expand("echo ${ensh_path#'*/'} ${ensh_path%x}", &mut shell).unwrap(), vec!["echo", "/usr/lib/lib.so.1", "/usr/lib/lib.so.1"]);
+
+        let line = "echo ${ensh_path/lib/x} ${ensh_path//lib/x} ${ensh_path/#\\/usr/X} ${ensh_path/%[0-9]/2} ${ensh_path//l?b}";
+        assert_eq!(expand(line, &mut shell).unwrap(), vec!["echo", "/usr/x/lib.so.1", "/usr/x/x.so.1", "X/lib/lib.so.1", "/usr/lib/lib.so.2", "/usr//.so.1"]);
+        assert_eq!(expand("echo \"${ensh_path//\"/\"/ }\"", &mut shell).unwrap(), vec!["echo", " usr lib lib.so.1"]);
+
+        let line = "echo ${ensh_path:5} ${ensh_path:5:3} ${ensh_path: -4} ${ensh_path:1:-13} ${ensh_path:1+1:2*2}x ${ensh_path:99}";
+        assert_eq!(expand(line, &mut shell).unwrap(), vec!["echo", "lib/lib.so.1", "lib", "so.1", "usr", "sr/lx"]);
+        assert_eq!(
+            expand("echo ${ensh_path:4:-14}", &mut shell),
+            Err(ExpandError::Parameter("-14: substring expression < 0".to_string()))
+        );
+    }
+
+    // Tests the modifiers on the positional parameters, which apply to each
+    // of them
+    #[test]
+    fn test_expand_positional_modifiers() {
+        let mut shell = Shell::default();
+        let args = ["a.rs", "b.rs", "c.txt"].iter().map(OsString::from).collect();
+        shell.vars_mut().push_frame(args);
+
+        assert_eq!(expand("echo ${#@} \"${@%.rs}\"", &mut shell).unwrap(), vec!["echo", "3", "a", "b", "c.txt"]);
+        assert_eq!(expand("echo \"${*/./-}\"", &mut shell).unwrap(), vec!["echo", "a-rs b-rs c-txt"]);
+        assert_eq!(expand("echo \"${@:2}\" ${@:0:1} ${10:-none}", &mut shell).unwrap(), vec!["echo", "b.rs", "c.txt", "ensh", "none"]);
+    }
+
+    // Tests expanding elements, keys and lengths of arrays, and that
+    // `"${name[@]}"` gives one field for each element like `"$@"` does
+    #[test]
+    fn test_expand_arrays() {
+        let mut shell = Shell::default();
+        let array = expand_array("'a b' '' [5]=c d", false, &mut shell).unwrap();
+        shell.vars_mut().set_value("ensh_arr", array);
+        shell.vars_mut().set("i", OsString::from("4"));
+
+        assert_eq!(expand("echo \"${ensh_arr[@]}\"", &mut shell).unwrap(), vec!["echo", "a b", "", "c", "d"]);
+        assert_eq!(expand("echo ${ensh_arr[@]}", &mut shell).unwrap(), vec!["echo", "a", "b", "c", "d"]);
+        assert_eq!(expand("echo \"${ensh_arr[*]}\"", &mut shell).unwrap(), vec!["echo", "a b  c d"]);
+        assert_eq!(expand("echo ${!ensh_arr[@]} ${#ensh_arr[@]}", &mut shell).unwrap(), vec!["echo", "0", "1", "5", "6", "4"]);
+        assert_eq!(expand("echo \"$ensh_arr\" ${ensh_arr[i + 2]} ${ensh_arr[-1]}", &mut shell).unwrap(), vec!["echo", "a b", "d", "d"]);
+        assert_eq!(expand("echo ${#ensh_arr[0]} ${ensh_arr[3]:-unset} \"${ensh_arr[@]/?/x}\"", &mut shell).unwrap(), vec!["echo", "3", "unset", "x b", "", "x", "x"]);
+
+        assert_eq!(expand("echo \"${ensh_none[@]}\" \"${ensh_none[*]}\"", &mut shell).unwrap(), vec!["echo", ""]);
+        assert_eq!(expand("echo ${ensh_arr[7]:=e} ${ensh_arr[7]}", &mut shell).unwrap(), vec!["echo", "e", "e"]);
+        assert!(expand("echo ${!ensh_arr}", &mut shell).is_err());
+    }
+
+    // Tests associative arrays, whose keys are strings
+    #[test]
+    fn test_expand_assoc() {
+        let mut shell = Shell::default();
+        shell.vars_mut().set("k", OsString::from("two words"));
+        let array = expand_array("[one]=1 [\"$k\"]=2 [x]=$k", true, &mut shell).unwrap();
+        shell.vars_mut().set_value("ensh_map", array);
+
+        assert_eq!(expand("echo \"${!ensh_map[@]}\"", &mut shell).unwrap(), vec!["echo", "one", "two words", "x"]);
+        assert_eq!(expand("echo ${ensh_map[$k]} ${ensh_map[one]} \"${ensh_map[x]}\"", &mut shell).unwrap(), vec!["echo", "2", "1", "two words"]);
+        assert_eq!(expand("echo ${ensh_map[none]-unset} ${#ensh_map[*]}", &mut shell).unwrap(), vec!["echo", "unset", "3"]);
+        assert!(expand_array("a", true, &mut shell).is_err());
+    }
+
+    // Tests the positional and special parameters
+    #[test]
+    fn test_expand_special() {
+        let mut shell = Shell::default();
+        shell.set_status(3);
+        assert_eq!(expand("echo \"$@\" $# $? $0", &mut shell).unwrap(), vec!["echo", "0", "3", "ensh"]);
+
+        let args = ["a b", "", "c"].iter().map(OsString::from).collect();
+        shell.vars_mut().push_frame(args);
+        assert_eq!(expand("echo $#:$1:$3 ${1}", &mut shell).unwrap(), vec!["echo", "3:a", "b:c", "a", "b"]);
+        assert_eq!(expand("echo \"$@\"", &mut shell).unwrap(), vec!["echo", "a b", "", "c"]);
+        assert_eq!(expand("echo \"<$@>\"", &mut shell).unwrap(), vec!["echo", "<a b", "", "c>"]);
+        assert_eq!(expand("echo $@", &mut shell).unwrap(), vec!["echo", "a", "b", "c"]);
+        assert_eq!(expand("echo \"$*\"", &mut shell).unwrap(), vec!["echo", "a b  c"]);
+    }
+
+    // Tests splitting on separators other than whitespace
+    #[test]
+    fn test_split_field() {
+        let mut field = Field::default();
+        field.push_bytes(b" a::b : c ", Origin::Expanded);
+        let pieces: Vec<OsString> = split_field(field, b" :").into_iter().map(Field::into_os_string).collect();
+        assert_eq!(pieces, vec!["a", "", "b", "c"]);
+    }
+
+    // Tests that assignments are expanded to a single string
+    #[test]
+    fn test_expand_string() {
+        let mut shell = Shell::default();
+        shell.vars_mut().set("ensh_s", OsString::from("* x"));
+        assert_eq!(expand_string("$ensh_s.'$y'", &mut shell).unwrap(), OsString::from("* x.$y"));
+    }
+
+    // Tests that patterns keep their unquoted wildcards
+    #[test]
+    fn test_expand_pattern() {
+        let mut shell = Shell::default();
+        shell.vars_mut().set("ensh_p", OsString::from("a*"));
+        assert_eq!(expand_pattern("$ensh_p'?'\\[x]", &mut shell).unwrap(), OsString::from("a*\\?\\[x]"));
+        assert_eq!(expand_pattern("\"$ensh_p\"", &mut shell).unwrap(), OsString::from("a\\*"));
+    }
+
+    // Tests that quoted parts of regular expressions are escaped
+    #[test]
+    fn test_expand_regex() {
+        let mut shell = Shell::default();
+        assert_eq!(expand_regex("^a.'.'\\$", &mut shell).unwrap(), OsString::from("^a.\\.\\$"));
+    }
+
+    // Tests that quotes and escapes are removed
+    #[test]
+    fn test_expand_unquote() {
+        let mut shell = Shell::default();
+        let mut unquote = |word: &str| expand_string(word, &mut shell).unwrap();
+        assert_eq!(unquote("'a b'"), OsString::from("a b"));
+        assert_eq!(unquote("\"c \\\" \\d\""), OsString::from("c \" \\d"));
+        assert_eq!(unquote("e\\ f"), OsString::from("e f"));
+        assert_eq!(unquote("ll='ls -la'"), OsString::from("ll=ls -la"));
+        assert_eq!(unquote(&quote("it's a test")), OsString::from("it's a test"));
+    }
+
+    // Tests that command output is substituted, split into fields unless
+    // it's quoted, and stripped of its trailing line breaks
+    #[test]
+    fn test_command_substitution() {
+        let mut shell = Shell::default();
+        shell.set_paths(vec![PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+        assert_eq!(expand("echo $(printf 'a  b\\n\\n')", &mut shell).unwrap(), vec!["echo", "a", "b"]);
+        assert_eq!(expand("echo \"$(printf 'a  b\\n\\n')\"", &mut shell).unwrap(), vec!["echo", "a  b"]);
+        assert_eq!(expand("echo $(echo $(echo \")\"))", &mut shell).unwrap(), vec!["echo", ")"]);
+        assert_eq!(expand("echo x`echo \\`echo y\\``", &mut shell).unwrap(), vec!["echo", "xy"]);
+        assert_eq!(expand("echo $(f() { echo $1; }; f arg)", &mut shell).unwrap(), vec!["echo", "arg"]);
+
+        assert_eq!(expand("echo \"$(false)\" $?", &mut shell).unwrap(), vec!["echo", "", "1"]);
+        assert_eq!(shell.status(), 1);
+        assert_eq!(
+            expand("echo $(fi)", &mut shell),
+            Err(ExpandError::CommandSubstitution("syntax error near unexpected token `fi'".to_string()))
+        );
+    }
+}
@@ -0,0 +1,526 @@
+//! glob.rs    Afnan Enayet
+//!
+//! Glob patterns and filename expansion. A pattern can contain:
+//! * `*` - any string, including the empty string
+//! * `?` - any single character
+//! * `[...]` - any one of the enclosed characters or ranges (`[a-z]`),
+//!   or any character not enclosed if it starts with `!` or `^`
+//! * `**` - as a whole path component, any number of directories
+//!
+//! A backslash makes the next character match literally. Quoted characters
+//! in a word are escaped this way before the word is used as a pattern.
+//!
+//! Patterns and file names are matched as raw bytes rather than strings, so
+//! file names that aren't valid UTF-8 can still be matched and returned as
+//! they are
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+use std::str;
+
+/// What to do with a pattern that doesn't match any files
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NoMatch {
+    /// Pass the pattern along as a literal argument (like bash)
+    #[default]
+    Literal,
+
+    /// Fail the command (like zsh)
+    Error,
+
+    /// Remove the pattern from the arguments (like bash's `nullglob`)
+    Empty,
+}
+
+// A character of a pattern or a name. Bytes that aren't part of a valid
+// UTF-8 character are kept on their own, so `?` still matches exactly one
+// of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Unit {
+    Char(char),
+    Byte(u8),
+}
+
+// A single element of a compiled pattern
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Lit(Unit),
+    Any,
+    Star,
+    Class { negated: bool, ranges: Vec<(Unit, Unit)> },
+}
+
+/// A compiled glob pattern
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    /// Compiles a pattern. Compiling can't fail, a `[` without a closing
+    /// `]` just matches a literal `[`
+    pub fn new<S: AsRef<OsStr>>(pattern: S) -> Pattern {
+        let units = decode(pattern.as_ref().as_bytes());
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < units.len() {
+            match units[i] {
+                Unit::Char('\\') if i + 1 < units.len() => {
+                    tokens.push(Token::Lit(units[i + 1]));
+                    i += 1;
+                }
+                Unit::Char('*') => {
+                    // Consecutive stars match the same as a single one
+                    if tokens.last() != Some(&Token::Star) {
+                        tokens.push(Token::Star);
+                    }
+                }
+                Unit::Char('?') => tokens.push(Token::Any),
+                Unit::Char('[') => match parse_class(&units, i) {
+                    Some((class, end)) => {
+                        tokens.push(class);
+                        i = end;
+                    }
+                    None => tokens.push(Token::Lit(units[i])),
+                },
+                unit => tokens.push(Token::Lit(unit)),
+            }
+            i += 1;
+        }
+        Pattern { tokens }
+    }
+
+    /// Returns whether the pattern matches the whole of `s`
+    pub fn matches<S: AsRef<OsStr>>(&self, s: S) -> bool {
+        let units = decode(s.as_ref().as_bytes());
+        let tokens = &self.tokens;
+        let (mut t, mut i) = (0, 0);
+
+        // The position of the last star and the input position it was tried
+        // at, so we can backtrack and let the star take one more character
+        let mut star: Option<(usize, usize)> = None;
+
+        while i < units.len() {
+            if t < tokens.len() && tokens[t] == Token::Star {
+                star = Some((t, i));
+                t += 1;
+            } else if t < tokens.len() && matches_one(&tokens[t], units[i]) {
+                t += 1;
+                i += 1;
+            } else if let Some((star_t, star_i)) = star {
+                t = star_t + 1;
+                i = star_i + 1;
+                star = Some((star_t, star_i + 1));
+            } else {
+                return false;
+            }
+        }
+
+        tokens[t..].iter().all(|token| *token == Token::Star)
+    }
+
+    /// Returns the length in bytes of the shortest prefix of `s` that the
+    /// pattern matches, or the longest one if `longest` is set
+    pub fn match_prefix<S: AsRef<OsStr>>(&self, s: S, longest: bool) -> Option<usize> {
+        let bytes = s.as_ref().as_bytes();
+        let mut ends = char_boundaries(bytes);
+        if longest {
+            ends.reverse();
+        }
+        ends.into_iter().find(|&end| self.matches(OsStr::from_bytes(&bytes[..end])))
+    }
+
+    /// Returns where the shortest suffix of `s` that the pattern matches
+    /// starts, or where the longest one does if `longest` is set
+    pub fn match_suffix<S: AsRef<OsStr>>(&self, s: S, longest: bool) -> Option<usize> {
+        let bytes = s.as_ref().as_bytes();
+        let mut starts = char_boundaries(bytes);
+        if !longest {
+            starts.reverse();
+        }
+        starts.into_iter().find(|&start| self.matches(OsStr::from_bytes(&bytes[start..])))
+    }
+
+    /// Returns the string the pattern matches if it has no wildcards, with
+    /// any escapes removed
+    pub fn literal(&self) -> Option<OsString> {
+        let mut bytes = Vec::new();
+
+        for token in &self.tokens {
+            match *token {
+                Token::Lit(unit) => encode(unit, &mut bytes),
+                _ => return None,
+            }
+        }
+        Some(OsString::from_vec(bytes))
+    }
+
+    // Returns whether the pattern starts with a literal `.`, which is the
+    // only way for a pattern to match a hidden file
+    fn matches_hidden(&self) -> bool {
+        self.tokens.first() == Some(&Token::Lit(Unit::Char('.')))
+    }
+}
+
+// Splits bytes into characters, keeping any invalid UTF-8 as single bytes
+fn decode(bytes: &[u8]) -> Vec<Unit> {
+    let mut units = Vec::new();
+    let mut rest = bytes;
+
+    while !rest.is_empty() {
+        match str::from_utf8(rest) {
+            Ok(valid) => {
+                units.extend(valid.chars().map(Unit::Char));
+                break;
+            }
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                let bad_len = e.error_len().unwrap_or(invalid.len());
+
+                // The first part was just checked, so it can't fail
+                units.extend(str::from_utf8(valid).unwrap_or("").chars().map(Unit::Char));
+                units.extend(invalid[..bad_len].iter().map(|&b| Unit::Byte(b)));
+                rest = &invalid[bad_len..];
+            }
+        }
+    }
+    units
+}
+
+/// Returns the byte offsets where the characters of `bytes` start, plus
+/// its length. A byte that isn't part of a valid UTF-8 character counts as
+/// a character of its own
+pub fn char_boundaries(bytes: &[u8]) -> Vec<usize> {
+    let mut boundaries = vec![0];
+    let mut offset = 0;
+
+    for unit in decode(bytes) {
+        offset += match unit {
+            Unit::Char(c) => c.len_utf8(),
+            Unit::Byte(_) => 1,
+        };
+        boundaries.push(offset);
+    }
+    boundaries
+}
+
+// Appends the bytes of a unit
+fn encode(unit: Unit, bytes: &mut Vec<u8>) {
+    match unit {
+        Unit::Char(c) => {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+        Unit::Byte(b) => bytes.push(b),
+    }
+}
+
+// Parses a bracket expression starting at `units[start]` (the `[`).
+// Returns the class and the index of the closing `]`, or `None` if the
+// bracket is never closed
+fn parse_class(units: &[Unit], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = i < units.len() && (units[i] == Unit::Char('!') || units[i] == Unit::Char('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let first = i;
+
+    while i < units.len() {
+        let mut unit = units[i];
+
+        // A `]` right at the start is part of the set rather than the end
+        if unit == Unit::Char(']') && i > first {
+            return Some((Token::Class { negated, ranges }, i));
+        }
+
+        if unit == Unit::Char('\\') && i + 1 < units.len() {
+            i += 1;
+            unit = units[i];
+        }
+
+        if i + 2 < units.len() && units[i + 1] == Unit::Char('-') && units[i + 2] != Unit::Char(']') {
+            ranges.push((unit, units[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((unit, unit));
+            i += 1;
+        }
+    }
+    None
+}
+
+// Returns whether a non-star token matches a character
+fn matches_one(token: &Token, unit: Unit) -> bool {
+    match *token {
+        Token::Lit(expected) => unit == expected,
+        Token::Any => true,
+        Token::Star => false,
+        Token::Class { negated, ref ranges } => {
+            ranges.iter().any(|&(lo, hi)| lo <= unit && unit <= hi) != negated
+        }
+    }
+}
+
+/// Returns whether a pattern has any unescaped wildcards
+pub fn has_wildcards<S: AsRef<OsStr>>(pattern: S) -> bool {
+    Pattern::new(pattern).literal().is_none()
+}
+
+/// Expands a pattern into the paths that match it, sorted. Relative
+/// patterns are matched against `cwd` and the results are relative too.
+/// Hidden files are only matched by a component that starts with a `.`
+pub fn expand_glob<S: AsRef<OsStr>>(pattern: S, cwd: &Path) -> Vec<OsString> {
+    let pattern = pattern.as_ref().as_bytes();
+    let mut results = Vec::new();
+
+    let (prefix, rest): (&[u8], &[u8]) = match pattern.iter().position(|&b| b != b'/') {
+        Some(0) => (b"", pattern),
+        Some(start) => (b"/", &pattern[start..]),
+        None => (b"/", b""),
+    };
+
+    let mut components: Vec<&[u8]> = rest.split(|&b| b == b'/').collect();
+
+    // A trailing `**` matches everything below the directory
+    if components.last() == Some(&&b"**"[..]) {
+        components.push(b"*");
+    }
+
+    let dir = cwd.join(OsStr::from_bytes(prefix));
+    walk(prefix.to_vec(), &dir, &components, &mut results);
+    results.sort();
+    results.dedup();
+    results.into_iter().map(OsString::from_vec).collect()
+}
+
+// Matches the remaining path components against the entries of `dir`,
+// which is the directory `prefix` refers to. Complete matches are pushed
+// onto `results`
+fn walk(prefix: Vec<u8>, dir: &Path, components: &[&[u8]], results: &mut Vec<Vec<u8>>) {
+    let component = match components.first() {
+        Some(component) => *component,
+        None => {
+            results.push(prefix);
+            return;
+        }
+    };
+    let rest = &components[1..];
+
+    // A trailing slash means the match has to be a directory
+    if component.is_empty() {
+        if rest.is_empty() && dir.is_dir() {
+            results.push(prefix);
+        } else if !rest.is_empty() {
+            walk(prefix, dir, rest, results);
+        }
+        return;
+    }
+
+    if component == b"**" {
+        // Zero directories, then one or more
+        walk(prefix.clone(), dir, rest, results);
+        for name in list_dir(dir) {
+            let path = dir.join(OsStr::from_bytes(&name));
+            if !name.starts_with(b".") && is_real_dir(&path) {
+                walk(join_prefix(&prefix, &name, true), &path, components, results);
+            }
+        }
+        return;
+    }
+
+    let pattern = Pattern::new(OsStr::from_bytes(component));
+
+    // Components without wildcards don't need a directory listing
+    if let Some(name) = pattern.literal() {
+        let path = dir.join(&name);
+        if rest.is_empty() {
+            if fs::symlink_metadata(&path).is_ok() {
+                results.push(join_prefix(&prefix, name.as_bytes(), false));
+            }
+        } else if path.is_dir() {
+            walk(join_prefix(&prefix, name.as_bytes(), true), &path, rest, results);
+        }
+        return;
+    }
+
+    for name in list_dir(dir) {
+        if name.starts_with(b".") && !pattern.matches_hidden() {
+            continue;
+        }
+
+        if pattern.matches(OsStr::from_bytes(&name)) {
+            let path = dir.join(OsStr::from_bytes(&name));
+            if rest.is_empty() {
+                results.push(join_prefix(&prefix, &name, false));
+            } else if path.is_dir() {
+                walk(join_prefix(&prefix, &name, true), &path, rest, results);
+            }
+        }
+    }
+}
+
+// Appends a name to a match prefix, followed by a slash if more components
+// will follow
+fn join_prefix(prefix: &[u8], name: &[u8], slash: bool) -> Vec<u8> {
+    let mut joined = prefix.to_vec();
+    joined.extend_from_slice(name);
+    if slash {
+        joined.push(b'/');
+    }
+    joined
+}
+
+// Returns the names of the entries of a directory, or nothing if it can't
+// be read
+fn list_dir(dir: &Path) -> Vec<Vec<u8>> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().into_vec())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Returns whether a path is a directory that isn't a symlink, so that `**`
+// can't get caught in a symlink loop
+fn is_real_dir(path: &Path) -> bool {
+    fs::symlink_metadata(path).map(|meta| meta.is_dir()).unwrap_or(false)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    // Creates a directory tree to glob against
+    fn create_tree(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&root);
+
+        for dir in &["src/bin", "src/lib", ".hidden"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        for file in &["a.rs", "b.rs", "c.txt", ".dot.rs", "src/main.rs", "src/bin/tool.rs", "src/lib/x.txt", ".hidden/h.rs"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        root
+    }
+
+    // Tests the single character wildcards
+    #[test]
+    fn test_match_simple() {
+        assert!(Pattern::new("*.rs").matches("main.rs"));
+        assert!(Pattern::new("*.rs").matches(".rs"));
+        assert!(!Pattern::new("*.rs").matches("main.rc"));
+        assert!(Pattern::new("a?c").matches("abc"));
+        assert!(!Pattern::new("a?c").matches("ac"));
+        assert!(Pattern::new("*a*b*").matches("xxaxxbxx"));
+        assert!(!Pattern::new("*a*b").matches("xxaxxbxx"));
+        assert!(Pattern::new("").matches(""));
+        assert!(Pattern::new("**").matches("anything"));
+    }
+
+    // Tests bracket expressions
+    #[test]
+    fn test_match_class() {
+        assert!(Pattern::new("[abc]x").matches("bx"));
+        assert!(!Pattern::new("[abc]x").matches("dx"));
+        assert!(Pattern::new("[a-z][!0-9]").matches("qq"));
+        assert!(!Pattern::new("[a-z][!0-9]").matches("q1"));
+        assert!(Pattern::new("[^a]").matches("b"));
+        assert!(Pattern::new("[]]").matches("]"));
+        assert!(Pattern::new("[a-]").matches("-"));
+        assert!(Pattern::new("[ab").matches("[ab"));
+    }
+
+    // Tests that escaped wildcards only match themselves
+    #[test]
+    fn test_match_escaped() {
+        assert!(Pattern::new("\\*").matches("*"));
+        assert!(!Pattern::new("\\*").matches("a"));
+        assert_eq!(Pattern::new("a\\*b").literal(), Some(OsString::from("a*b")));
+        assert!(!has_wildcards("a\\*b"));
+        assert!(has_wildcards("a*b"));
+    }
+
+    // Tests matching the start and end of a string, shortest and longest
+    #[test]
+    fn test_match_prefix_suffix() {
+        let pattern = Pattern::new("*.");
+        assert_eq!(pattern.match_prefix("a.b.c", false), Some(2));
+        assert_eq!(pattern.match_prefix("a.b.c", true), Some(4));
+        assert_eq!(pattern.match_prefix("abc", true), None);
+
+        let pattern = Pattern::new(".*");
+        assert_eq!(pattern.match_suffix("a.b.c", false), Some(3));
+        assert_eq!(pattern.match_suffix("a.b.c", true), Some(1));
+        assert_eq!(Pattern::new("?").match_suffix("caf\u{e9}", false), Some(3));
+        assert_eq!(char_boundaries(b"a\xe9\xc3\xa9"), vec![0, 1, 2, 4]);
+    }
+
+    // Tests that bytes that aren't valid UTF-8 can be matched
+    #[test]
+    fn test_match_invalid_utf8() {
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        assert!(Pattern::new("*.txt").matches(name));
+        assert!(Pattern::new("caf?.txt").matches(name));
+        assert!(!Pattern::new("caf??.txt").matches(name));
+        assert!(Pattern::new(name).matches(name));
+        assert_eq!(Pattern::new(name).literal().unwrap(), name);
+    }
+
+    // Converts expected results for comparison
+    fn os(strings: &[&str]) -> Vec<OsString> {
+        strings.iter().map(OsString::from).collect()
+    }
+
+    // Tests matching files in a single directory
+    #[test]
+    fn test_expand_flat() {
+        let root = create_tree("ensh_glob_flat");
+        assert_eq!(expand_glob("*.rs", &root), os(&["a.rs", "b.rs"]));
+        assert_eq!(expand_glob(".*.rs", &root), os(&[".dot.rs"]));
+        assert_eq!(expand_glob("?.*", &root), os(&["a.rs", "b.rs", "c.txt"]));
+        assert_eq!(expand_glob("src/*/", &root), os(&["src/bin/", "src/lib/"]));
+        assert!(expand_glob("*.none", &root).is_empty());
+    }
+
+    // Tests matching across directories, including `**`
+    #[test]
+    fn test_expand_recursive() {
+        let root = create_tree("ensh_glob_recursive");
+        assert_eq!(expand_glob("src/*/*.rs", &root), os(&["src/bin/tool.rs"]));
+        assert_eq!(expand_glob("**/*.rs", &root), os(&["a.rs", "b.rs", "src/bin/tool.rs", "src/main.rs"]));
+        assert_eq!(expand_glob("src/**", &root), os(&["src/bin", "src/bin/tool.rs", "src/lib", "src/lib/x.txt", "src/main.rs"]));
+    }
+
+    // Tests that file names that aren't valid UTF-8 come back unchanged
+    #[test]
+    fn test_expand_invalid_utf8() {
+        let root = env::temp_dir().join("ensh_glob_bytes");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        fs::write(root.join(name), "").unwrap();
+
+        assert_eq!(expand_glob("*.txt", &root), vec![name.to_os_string()]);
+    }
+
+    // Tests that absolute patterns give absolute results
+    #[test]
+    fn test_expand_absolute() {
+        let root = create_tree("ensh_glob_absolute");
+        let pattern = format!("{}/*.txt", root.display());
+        assert_eq!(expand_glob(&pattern, Path::new("/")), os(&[&format!("{}/c.txt", root.display())]));
+    }
+}
@@ -0,0 +1,702 @@
+//! lexer.rs    Afnan Enayet
+//!
+//! The lexer splits a line of input into words and operators. Quotes and
+//! escapes are left in the words so that later stages can still tell quoted
+//! text apart from unquoted text; expansion strips them once they've served
+//! their purpose
+
+use std::fmt;
+
+use super::ParseError;
+use vars::valid_name;
+
+/// An operator that separates words
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `;`
+    Semi,
+    /// `;;`, which ends an item of a `case`
+    DoubleSemi,
+    /// `|`, which separates the patterns of a `case` item
+    Pipe,
+    /// `&&`, which runs the next command if the last one succeeded
+    AndIf,
+    /// `||`, which runs the next command if the last one failed
+    OrIf,
+    /// A line break
+    Newline,
+    /// `(`
+    LParen,
+    /// `)`
+    RParen,
+    /// `<`, which reads from a file
+    Less,
+    /// `>`, which writes to a file
+    Great,
+    /// `>>`, which appends to a file
+    DoubleGreat,
+    /// `>|`, which writes to a file even if `noclobber` is set
+    Clobber,
+    /// `<>`, which opens a file for reading and writing
+    LessGreat,
+    /// `<&`, which duplicates an input file descriptor
+    LessAnd,
+    /// `>&`, which duplicates an output file descriptor
+    GreatAnd,
+    /// `<<`, which starts a here-document
+    DoubleLess,
+    /// `<<-`, which starts a here-document with leading tabs removed
+    DoubleLessDash,
+    /// `<<<`, a here-string
+    TripleLess,
+}
+
+impl Op {
+    /// Returns whether the operator redirects a file descriptor, and so is
+    /// followed by a word rather than starting a new command
+    pub fn is_redirect(self) -> bool {
+        match self {
+            Op::Less
+            | Op::Great
+            | Op::DoubleGreat
+            | Op::Clobber
+            | Op::LessGreat
+            | Op::LessAnd
+            | Op::GreatAnd
+            | Op::DoubleLess
+            | Op::DoubleLessDash
+            | Op::TripleLess => true,
+            Op::Semi | Op::DoubleSemi | Op::Pipe | Op::AndIf | Op::OrIf | Op::Newline | Op::LParen | Op::RParen => false,
+        }
+    }
+
+    // The operator as it is written
+    fn text(self) -> &'static str {
+        match self {
+            Op::Semi => ";",
+            Op::DoubleSemi => ";;",
+            Op::Pipe => "|",
+            Op::AndIf => "&&",
+            Op::OrIf => "||",
+            Op::Newline => "\n",
+            Op::LParen => "(",
+            Op::RParen => ")",
+            Op::Less => "<",
+            Op::Great => ">",
+            Op::DoubleGreat => ">>",
+            Op::Clobber => ">|",
+            Op::LessGreat => "<>",
+            Op::LessAnd => "<&",
+            Op::GreatAnd => ">&",
+            Op::DoubleLess => "<<",
+            Op::DoubleLessDash => "<<-",
+            Op::TripleLess => "<<<",
+        }
+    }
+}
+
+/// A word or an operator from the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// A word, with any quoting still in it
+    Word(String),
+    /// An operator
+    Op(Op),
+    /// The expression of an arithmetic command, `(( expr ))`
+    Arith(String),
+    /// The number of the file descriptor a redirection applies to, like
+    /// the `2` of `2>`
+    IoNumber(i32),
+    /// The body of a here-document, which comes right after its delimiter
+    HereDoc(String),
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Op::Newline => write!(f, "newline"),
+            op => write!(f, "{}", op.text()),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Word(ref word) => write!(f, "{}", word),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::Arith(ref expr) => write!(f, "(({}))", expr),
+            Token::IoNumber(fd) => write!(f, "{}", fd),
+            Token::HereDoc(_) => write!(f, "here-document"),
+        }
+    }
+}
+
+// A here-document whose body hasn't been read yet: where its body goes in
+// the tokens, the line that ends it and whether leading tabs are removed
+struct PendingHereDoc {
+    index: usize,
+    delimiter: String,
+    strip_tabs: bool,
+}
+
+/// Splits input into words and operators. Words are split on unquoted
+/// whitespace and end at an operator. Quoting characters are kept in the
+/// words, and so are command substitutions (`$(...)` and backquotes) and
+/// parameter expansions (`${...}`), which can have spaces and operators
+/// inside. Returns an error if a quote, a command substitution or a
+/// parameter expansion is never closed
+///
+/// `((` starts an arithmetic command if it has a matching `))`, and the
+/// expression up to there becomes a single token.
+///
+/// A number right before a redirection is its file descriptor. The body of
+/// a here-document is read from the lines after the one with its `<<` on
+/// it, up to the delimiter, and becomes a token after the delimiter. Input
+/// that ends before the delimiter gives `ParseError::UnterminatedHereDoc`.
+///
+/// Between `[[` and `]]`, `<` and `>` compare strings rather than
+/// redirecting, and the word after `=~` is a regular expression, so `(`,
+/// `)` and `|` are part of it rather than operators.
+///
+/// A `#` that starts a word starts a comment, which runs to the end of the
+/// line. Inside a word, like `a#b` or `$#`, it is an ordinary character
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut word = String::new();
+
+    // Tracks whether a word has started, so that `''` still yields a word
+    let mut in_word = false;
+    let mut in_cond = false;
+    let mut i = 0;
+
+    // Set after `<<` or `<<-` (to whether tabs are stripped), until the
+    // delimiter is read
+    let mut heredoc: Option<bool> = None;
+    let mut pending: Vec<PendingHereDoc> = Vec::new();
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '#' && !in_word {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+
+        let regex = in_cond && tokens.last() == Some(&Token::Word("=~".to_string()));
+
+        // The parentheses of an array assignment, `name=(a b c)`, are part
+        // of the word
+        let array = c == '(' && in_word && word.strip_suffix('=').is_some_and(valid_name);
+
+        let op = match (c, chars.get(i + 1), chars.get(i + 2)) {
+            ('(', _, _) | (')', _, _) | ('|', _, _) if regex => None,
+            ('(', _, _) if array => None,
+            ('<', _, _) | ('>', _, _) if in_cond => None,
+            ('<', Some('('), _) | ('>', Some('('), _) => None,
+            (';', Some(';'), _) => Some(Op::DoubleSemi),
+            ('|', Some('|'), _) => Some(Op::OrIf),
+            ('&', Some('&'), _) => Some(Op::AndIf),
+            (';', _, _) => Some(Op::Semi),
+            ('|', _, _) => Some(Op::Pipe),
+            ('\n', _, _) => Some(Op::Newline),
+            ('(', _, _) => Some(Op::LParen),
+            (')', _, _) => Some(Op::RParen),
+            ('<', Some('<'), Some('<')) => Some(Op::TripleLess),
+            ('<', Some('<'), Some('-')) => Some(Op::DoubleLessDash),
+            ('<', Some('<'), _) => Some(Op::DoubleLess),
+            ('<', Some('&'), _) => Some(Op::LessAnd),
+            ('<', Some('>'), _) => Some(Op::LessGreat),
+            ('<', _, _) => Some(Op::Less),
+            ('>', Some('>'), _) => Some(Op::DoubleGreat),
+            ('>', Some('&'), _) => Some(Op::GreatAnd),
+            ('>', Some('|'), _) => Some(Op::Clobber),
+            ('>', _, _) => Some(Op::Great),
+            _ => None,
+        };
+
+        if op.is_some() || c.is_whitespace() {
+            if in_word {
+                match word.as_str() {
+                    "[[" => in_cond = true,
+                    "]]" => in_cond = false,
+                    _ => {}
+                }
+
+                match word.parse() {
+                    Ok(fd) if op.is_some_and(Op::is_redirect) && word.bytes().all(|b| b.is_ascii_digit()) => {
+                        tokens.push(Token::IoNumber(fd));
+                    }
+                    _ => push_word(&mut tokens, word.clone(), &mut heredoc, &mut pending),
+                }
+                word.clear();
+                in_word = false;
+            }
+
+            if op == Some(Op::LParen) && !in_cond {
+                if let Some(end) = arith_end(&chars, i)? {
+                    tokens.push(Token::Arith(chars[i + 2..end - 2].iter().collect()));
+                    i = end;
+                    continue;
+                }
+            }
+
+            match op {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.text().chars().count();
+
+                    match op {
+                        Op::DoubleLess => heredoc = Some(false),
+                        Op::DoubleLessDash => heredoc = Some(true),
+                        Op::Newline if !pending.is_empty() => i = read_here_docs(&chars, i, &mut pending, &mut tokens)?,
+                        _ => {}
+                    }
+                }
+                None => i += 1,
+            }
+            continue;
+        }
+
+        let end = match c {
+            '\\' => (i + 2).min(chars.len()),
+            '\'' | '"' => quoted_end(&chars, i + 1, c)?,
+            '`' => backquote_end(&chars, i + 1)?,
+            '$' | '<' | '>' if chars.get(i + 1) == Some(&'(') && !in_cond => substitution_end(&chars, i + 2)?,
+            '(' if array => substitution_end(&chars, i + 1)?,
+            '$' if chars.get(i + 1) == Some(&'{') => brace_end(&chars, i + 2, false)?,
+            _ => i + 1,
+        };
+        word.extend(&chars[i..end]);
+        in_word = true;
+        i = end;
+    }
+
+    if in_word {
+        push_word(&mut tokens, word, &mut heredoc, &mut pending);
+    }
+    match pending.first() {
+        Some(doc) => Err(ParseError::UnterminatedHereDoc(doc.delimiter.clone())),
+        None => Ok(tokens),
+    }
+}
+
+// Adds a word to the tokens. If it is the delimiter of a here-document, a
+// placeholder for the body is added after it, to be filled in once the
+// line ends
+fn push_word(tokens: &mut Vec<Token>, word: String, heredoc: &mut Option<bool>, pending: &mut Vec<PendingHereDoc>) {
+    let delimiter = heredoc.take().map(|strip_tabs| PendingHereDoc {
+        index: tokens.len() + 1,
+        delimiter: unquote(&word),
+        strip_tabs,
+    });
+
+    tokens.push(Token::Word(word));
+    if let Some(doc) = delimiter {
+        tokens.push(Token::HereDoc(String::new()));
+        pending.push(doc);
+    }
+}
+
+// Reads the bodies of the pending here-documents, one after the other,
+// from the lines starting at `chars[start]`. Returns the index after the
+// last delimiter line
+fn read_here_docs(
+    chars: &[char],
+    start: usize,
+    pending: &mut Vec<PendingHereDoc>,
+    tokens: &mut [Token],
+) -> Result<usize, ParseError> {
+    let mut i = start;
+
+    for doc in pending.drain(..) {
+        let mut body = String::new();
+
+        loop {
+            if i >= chars.len() {
+                return Err(ParseError::UnterminatedHereDoc(doc.delimiter));
+            }
+
+            let end = chars[i..].iter().position(|&c| c == '\n').map_or(chars.len(), |offset| i + offset);
+            let line: String = chars[i..end].iter().collect();
+            let line = if doc.strip_tabs { line.trim_start_matches('\t') } else { &line[..] };
+            i = (end + 1).min(chars.len());
+
+            if line == doc.delimiter {
+                break;
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+        tokens[doc.index] = Token::HereDoc(body);
+    }
+    Ok(i)
+}
+
+// Removes the quoting from a here-document's delimiter
+fn unquote(word: &str) -> String {
+    let mut unquoted = String::new();
+    let mut chars = word.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            '\'' | '"' => {}
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// Finds the end of a quoted string that starts at `chars[start]` (just
+/// after the opening quote). Backslashes only escape inside of double
+/// quotes, which can also have command substitutions in them. Returns the
+/// index after the closing quote
+pub fn quoted_end(chars: &[char], start: usize, quote: char) -> Result<usize, ParseError> {
+    let mut i = start;
+
+    while i < chars.len() {
+        match chars[i] {
+            c if c == quote => return Ok(i + 1),
+            '\\' if quote == '"' => i += 2,
+            '`' if quote == '"' => i = backquote_end(chars, i + 1)?,
+            '$' if quote == '"' && chars.get(i + 1) == Some(&'(') => i = substitution_end(chars, i + 2)?,
+            '$' if quote == '"' && chars.get(i + 1) == Some(&'{') => i = brace_end(chars, i + 2, true)?,
+            _ => i += 1,
+        }
+    }
+    Err(ParseError::UnterminatedQuote(quote))
+}
+
+/// Finds the end of a backquoted command that starts at `chars[start]`
+/// (just after the opening backquote). Returns the index after the closing
+/// backquote
+pub fn backquote_end(chars: &[char], start: usize) -> Result<usize, ParseError> {
+    let mut i = start;
+
+    while i < chars.len() {
+        match chars[i] {
+            '`' => return Ok(i + 1),
+            '\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+    Err(ParseError::UnterminatedQuote('`'))
+}
+
+/// Finds the end of a `$(...)` command substitution, or a `<(...)` or
+/// `>(...)` process substitution, whose command starts at `chars[start]`
+/// (just after the `(`). Parentheses in the command are
+/// matched up, unless they're quoted. Returns the index after the closing
+/// parenthesis
+pub fn substitution_end(chars: &[char], start: usize) -> Result<usize, ParseError> {
+    let mut i = start;
+    let mut depth = 1;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '\'' | '"' => i = quoted_end(chars, i + 1, chars[i])?,
+            '`' => i = backquote_end(chars, i + 1)?,
+            '(' => {
+                depth += 1;
+                i += 1;
+            }
+            ')' => {
+                depth -= 1;
+                i += 1;
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    Err(ParseError::UnterminatedSubstitution)
+}
+
+/// Finds the end of a `${...}` parameter expansion whose inside starts at
+/// `chars[start]` (just after the `${`). Braces in it are matched up,
+/// unless they're quoted. Inside double quotes (`quoted`) a
+/// single quote is an ordinary character. Returns the index after the
+/// closing brace
+pub fn brace_end(chars: &[char], start: usize, quoted: bool) -> Result<usize, ParseError> {
+    let mut i = start;
+    let mut depth = 0;
+
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('{', _) => {
+                depth += 1;
+                i += 1;
+            }
+            ('}', _) if depth > 0 => {
+                depth -= 1;
+                i += 1;
+            }
+            ('}', _) => return Ok(i + 1),
+            ('\\', _) => i += 2,
+            ('\'', _) if !quoted => i = quoted_end(chars, i + 1, '\'')?,
+            ('"', _) => i = quoted_end(chars, i + 1, '"')?,
+            ('`', _) => i = backquote_end(chars, i + 1)?,
+            ('$', Some('(')) => i = substitution_end(chars, i + 2)?,
+            ('$', Some('{')) => i = brace_end(chars, i + 2, quoted)?,
+            _ => i += 1,
+        }
+    }
+    Err(ParseError::UnterminatedParameter)
+}
+
+/// Finds the end of an arithmetic expression, `(( ... ))`, that starts with
+/// the parentheses at `chars[start]`. Returns `None` if the parentheses
+/// don't make up `((` and a matching `))`, like in `((a) (b))`, or else the
+/// index after the closing parentheses
+pub fn arith_end(chars: &[char], start: usize) -> Result<Option<usize>, ParseError> {
+    if chars.get(start) != Some(&'(') || chars.get(start + 1) != Some(&'(') {
+        return Ok(None);
+    }
+
+    let end = substitution_end(chars, start + 1)?;
+    if substitution_end(chars, start + 2)? + 1 == end {
+        Ok(Some(end))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns whether a word contains any quoting
+pub fn is_quoted(word: &str) -> bool {
+    word.contains(['\'', '"', '\\'])
+}
+
+/// Quotes a string so that it reads back as a single word, for output that
+/// the user may want to paste back into the shell
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Convenience function to build the expected token vectors
+    fn words(expected: &[&str]) -> Vec<Token> {
+        expected.iter().map(|s| Token::Word(s.to_string())).collect()
+    }
+
+    // Tests that input is split on whitespace
+    #[test]
+    fn test_split_plain_words() {
+        assert_eq!(tokenize("  ls  -la /tmp ").unwrap(), words(&["ls", "-la", "/tmp"]));
+        assert!(tokenize("   ").unwrap().is_empty());
+    }
+
+    // Tests that quoted whitespace doesn't split words and that the quotes
+    // are kept
+    #[test]
+    fn test_split_quoted_words() {
+        assert_eq!(
+            tokenize("echo 'a b' \"c \\\" d\" e\\ f ''").unwrap(),
+            words(&["echo", "'a b'", "\"c \\\" d\"", "e\\ f", "''"])
+        );
+    }
+
+    // Tests that operators end words, unless they are quoted
+    #[test]
+    fn test_split_operators() {
+        let tokens = tokenize("f(){ a;b\n';'\\(").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Word("f".to_string()),
+            Token::Op(Op::LParen),
+            Token::Op(Op::RParen),
+            Token::Word("{".to_string()),
+            Token::Word("a".to_string()),
+            Token::Op(Op::Semi),
+            Token::Word("b".to_string()),
+            Token::Op(Op::Newline),
+            Token::Word("';'\\(".to_string()),
+        ]);
+    }
+
+    // Tests the operators used by `case`
+    #[test]
+    fn test_split_case_operators() {
+        let tokens = tokenize("a|b) x;; ';;'").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Word("a".to_string()),
+            Token::Op(Op::Pipe),
+            Token::Word("b".to_string()),
+            Token::Op(Op::RParen),
+            Token::Word("x".to_string()),
+            Token::Op(Op::DoubleSemi),
+            Token::Word("';;'".to_string()),
+        ]);
+    }
+
+    // Tests `&&` and `||`, and that a regular expression in `[[ ]]` keeps
+    // its parentheses
+    #[test]
+    fn test_split_conditional() {
+        let tokens = tokenize("a&&b || [[ x =~ ^(a|b)$ && (y) ]]").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Word("a".to_string()),
+            Token::Op(Op::AndIf),
+            Token::Word("b".to_string()),
+            Token::Op(Op::OrIf),
+            Token::Word("[[".to_string()),
+            Token::Word("x".to_string()),
+            Token::Word("=~".to_string()),
+            Token::Word("^(a|b)$".to_string()),
+            Token::Op(Op::AndIf),
+            Token::Op(Op::LParen),
+            Token::Word("y".to_string()),
+            Token::Op(Op::RParen),
+            Token::Word("]]".to_string()),
+        ]);
+        assert_eq!(tokenize("echo =~ (").unwrap()[2], Token::Op(Op::LParen));
+    }
+
+    // Tests that command substitutions stay in one word, however much is
+    // in them
+    #[test]
+    fn test_split_substitution() {
+        let tokens = tokenize("echo a$(b (c) \")\" $(d)) \"$(e \"f)\")\" `g \\` h`; x").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Word("echo".to_string()),
+            Token::Word("a$(b (c) \")\" $(d))".to_string()),
+            Token::Word("\"$(e \"f)\")\"".to_string()),
+            Token::Word("`g \\` h`".to_string()),
+            Token::Op(Op::Semi),
+            Token::Word("x".to_string()),
+        ]);
+        assert_eq!(tokenize("echo $(a"), Err(ParseError::UnterminatedSubstitution));
+        assert_eq!(tokenize("echo `a"), Err(ParseError::UnterminatedQuote('`')));
+        assert_eq!(tokenize("echo \"$(a)"), Err(ParseError::UnterminatedQuote('"')));
+    }
+
+    // Tests that process substitutions are words, even right after a
+    // redirection operator
+    #[test]
+    fn test_split_process_substitution() {
+        let tokens = tokenize("diff <(sort a) x>(tee b)y < <(c)").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Word("diff".to_string()),
+            Token::Word("<(sort a)".to_string()),
+            Token::Word("x>(tee b)y".to_string()),
+            Token::Op(Op::Less),
+            Token::Word("<(c)".to_string()),
+        ]);
+        assert_eq!(tokenize("[[ a <(b) ]]").unwrap()[2], Token::Word("<".to_string()));
+        assert_eq!(tokenize("cat <(a"), Err(ParseError::UnterminatedSubstitution));
+    }
+
+    // Tests that the parentheses of an array assignment are part of the
+    // word, even across lines
+    #[test]
+    fn test_split_array_assignment() {
+        let tokens = tokenize("a=(1 ')' \"$(b)\"\n2) x[1]=y; f=x(").unwrap();
+        assert_eq!(tokens[..2], words(&["a=(1 ')' \"$(b)\"\n2)", "x[1]=y"])[..]);
+        assert_eq!(tokens[3..], [Token::Word("f=x".to_string()), Token::Op(Op::LParen)]);
+        assert_eq!(tokenize("a=(1"), Err(ParseError::UnterminatedSubstitution));
+    }
+
+    // Tests that a `#` starting a word comments out the rest of the line,
+    // here-documents included, but not the lines after it
+    #[test]
+    fn test_split_comments() {
+        assert_eq!(tokenize("echo a # note 'x").unwrap(), words(&["echo", "a"]));
+        assert!(tokenize("# comment").unwrap().is_empty());
+        let expected = vec![
+            Token::Word("x=1".to_string()),
+            Token::Op(Op::Newline),
+            Token::Word("y=2".to_string()),
+            Token::Op(Op::Semi),
+        ];
+        assert_eq!(tokenize("x=1 # c\ny=2;#z").unwrap(), expected);
+
+        let tokens = tokenize("cat <<EOF # <<X\n# body\nEOF\n").unwrap();
+        assert_eq!(tokens[3], Token::HereDoc("# body\n".to_string()));
+    }
+
+    // Tests that `#` is an ordinary character inside a word or quotes
+    #[test]
+    fn test_split_hash_in_word() {
+        let tokens = tokenize("echo a#b $# ${#x} '#' \\#").unwrap();
+        assert_eq!(tokens, words(&["echo", "a#b", "$#", "${#x}", "'#'", "\\#"]));
+    }
+
+    // Tests that parameter expansions stay in one word, with their nested
+    // expansions and quotes
+    #[test]
+    fn test_split_parameter() {
+        let tokens = tokenize("echo ${a:-b c} \"${d:-'}\" ${e#${f:-}'}'}x").unwrap();
+        assert_eq!(tokens, words(&["echo", "${a:-b c}", "\"${d:-'}\"", "${e#${f:-}'}'}x"]));
+        assert_eq!(tokenize("${a/x/{b}} c").unwrap(), words(&["${a/x/{b}}", "c"]));
+        assert_eq!(tokenize("echo ${a"), Err(ParseError::UnterminatedParameter));
+    }
+
+    // Tests that `(( ))` is one token, unless its parentheses match up
+    // some other way
+    #[test]
+    fn test_split_arithmetic() {
+        let tokens = tokenize("((a = (1+2) | 3 ));((b) (c))").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Arith("a = (1+2) | 3 ".to_string()),
+            Token::Op(Op::Semi),
+            Token::Op(Op::LParen),
+            Token::Op(Op::LParen),
+            Token::Word("b".to_string()),
+            Token::Op(Op::RParen),
+            Token::Op(Op::LParen),
+            Token::Word("c".to_string()),
+            Token::Op(Op::RParen),
+            Token::Op(Op::RParen),
+        ]);
+        assert_eq!(tokenize("echo $((1 + (2)))").unwrap(), words(&["echo", "$((1 + (2)))"]));
+        assert_eq!(tokenize("((1 +"), Err(ParseError::UnterminatedSubstitution));
+    }
+
+    // Tests redirection operators, file descriptor numbers and the bodies
+    // of here-documents, which come after the line they start on
+    #[test]
+    fn test_split_redirections() {
+        let tokens = tokenize("cat 2>&1 <<-'EOF' x>>y <<<z\n\tone\n\t$two\n\tEOF\necho").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Word("cat".to_string()),
+            Token::IoNumber(2),
+            Token::Op(Op::GreatAnd),
+            Token::Word("1".to_string()),
+            Token::Op(Op::DoubleLessDash),
+            Token::Word("'EOF'".to_string()),
+            Token::HereDoc("one\n$two\n".to_string()),
+            Token::Word("x".to_string()),
+            Token::Op(Op::DoubleGreat),
+            Token::Word("y".to_string()),
+            Token::Op(Op::TripleLess),
+            Token::Word("z".to_string()),
+            Token::Op(Op::Newline),
+            Token::Word("echo".to_string()),
+        ]);
+        assert_eq!(tokenize("a\\2>b '3'<c").unwrap()[1], Token::Op(Op::Great));
+        assert_eq!(tokenize("cat <<E\nabc\nE").unwrap()[3], Token::HereDoc("abc\n".to_string()));
+        assert_eq!(tokenize("cat <<E\nabc\n"), Err(ParseError::UnterminatedHereDoc("E".to_string())));
+    }
+
+    // Tests that an unclosed quote is an error
+    #[test]
+    fn test_split_unterminated_quote() {
+        assert_eq!(tokenize("echo 'abc"), Err(ParseError::UnterminatedQuote('\'')));
+        assert_eq!(tokenize("echo \"abc"), Err(ParseError::UnterminatedQuote('"')));
+    }
+
+    // Tests that quoted strings read back as the original string
+    #[test]
+    fn test_quote_round_trip() {
+        let original = "it's a test";
+        let quoted = quote(original);
+        assert_eq!(tokenize(&quoted).unwrap(), vec![Token::Word(quoted.clone())]);
+    }
+}
@@ -1,22 +1,85 @@
-/// mod.rs for parser
-///
-/// Contains helper functions to help with parsing user input
-///
-/// Mostly contains functions which help parse shell grammar and resolve
-/// pathnames
-///
+//! mod.rs for parser
+//!
+//! Contains helper functions to help with parsing user input
+//!
+//! Mostly contains functions which help parse shell grammar and resolve
+//! pathnames
+//!
 
 use std::io;
+use std::fmt;
 use std::env::home_dir;
-use std::path::Path;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+use sys;
 
 pub mod config;
+pub mod lexer;
+pub mod alias;
+pub mod glob;
+pub mod brace;
+pub mod expand;
+pub mod ast;
+pub mod syntax;
+
+/// An error found while parsing user input
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// A quote was opened but never closed
+    UnterminatedQuote(char),
+
+    /// A token showed up where it isn't allowed
+    UnexpectedToken(String),
+
+    /// The input ended in the middle of a command
+    UnexpectedEnd,
+
+    /// A `$(` command substitution was never closed
+    UnterminatedSubstitution,
+
+    /// A `${` parameter expansion was never closed
+    UnterminatedParameter,
 
-// Checks to see if path/file exists. Returns whether path string is
-// valid and points to something the shell can access
-pub fn verify_path(path: &str) -> bool {
-    let full_path = expand_path(path);
-    Path::new(full_path.as_str()).exists()
+    /// The input ended before the delimiter of a here-document
+    UnterminatedHereDoc(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::UnterminatedQuote(quote) => write!(f, "unterminated {} quote", quote),
+            ParseError::UnexpectedToken(ref token) => write!(f, "syntax error near unexpected token `{}'", token),
+            ParseError::UnexpectedEnd => write!(f, "syntax error: unexpected end of input"),
+            ParseError::UnterminatedSubstitution => write!(f, "unterminated command substitution"),
+            ParseError::UnterminatedParameter => write!(f, "unterminated parameter expansion"),
+            ParseError::UnterminatedHereDoc(ref delimiter) => {
+                write!(f, "here-document delimited by end of input (wanted `{}')", delimiter)
+            }
+        }
+    }
+}
+
+impl ParseError {
+    /// Returns whether the input was fine as far as it went, but stopped
+    /// in the middle of a command or a quote. More input could complete it
+    pub fn is_incomplete(&self) -> bool {
+        match *self {
+            ParseError::UnterminatedQuote(_)
+            | ParseError::UnexpectedEnd
+            | ParseError::UnterminatedSubstitution
+            | ParseError::UnterminatedParameter
+            | ParseError::UnterminatedHereDoc(_) => true,
+            ParseError::UnexpectedToken(_) => false,
+        }
+    }
+}
+
+// Checks to see if path/file exists. Returns whether path is valid and
+// points to something the shell can access
+pub fn verify_path(path: &Path) -> bool {
+    expand_path(path).exists()
 }
 
 // Normalizes and expands path to its full absolute path, for use with
@@ -24,74 +87,107 @@ pub fn verify_path(path: &str) -> bool {
 // nonexistent, the function will throw an error
 //
 // Ex: ~/example -> /Users/user/example
-pub fn norm_abs_path(path: &str) -> Result<String, io::Error> {
-    let expanded_path = expand_path(path);
-    let path = Path::new(expanded_path.as_str()).canonicalize();
+pub fn norm_abs_path(path: &Path) -> Result<PathBuf, io::Error> {
+    expand_path(path).canonicalize()
+}
+
+/// Joins `path` onto the logical directory `base` and removes `.` and `..`
+/// components lexically, so symlinks in the path are kept as they were
+/// typed rather than resolved. An absolute `path` replaces `base`
+pub fn logical_path(base: &Path, path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
 
-    // If path exists
-    match path {
-        Ok(path) => Ok(path.as_os_str().to_str().unwrap().to_string()),
-        Err(e) => Err(e),
+    for component in base.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other.as_os_str()),
+        }
     }
+    result
 }
 
-// Simply replaces a "~" in a path string with the $HOME value
-// Will fail if there is no $HOME defined in the user's path
-pub fn expand_path(path: &str) -> String {
-    let home_dir = home_dir().unwrap();
-    let home_str = home_dir.to_str().unwrap();
-    path.replace("~", home_str)
+// Replaces a leading `~` or `~user` in a path with that user's home
+// directory. A tilde anywhere else is left alone, as is the whole path if
+// the home directory can't be found
+pub fn expand_path(path: &Path) -> PathBuf {
+    match split_tilde(path.as_os_str()) {
+        Some((user, rest)) => match tilde_home(user) {
+            Some(home) => {
+                let mut expanded = home.into_os_string();
+                expanded.push(rest);
+                PathBuf::from(expanded)
+            }
+            None => path.to_path_buf(),
+        },
+        None => path.to_path_buf(),
+    }
 }
 
-// Condenses a path so that an absolute path is condensed and normalized
-// to a path relative to the home directory
-pub fn condense_path(path: &str) -> Result<String, io::Error> {
-    let expanded_path = expand_path(path);
-    let home = get_home_str().unwrap();
-    let home_str = home.as_str();
-    let path_str = path.replace(home_str, "~");
-    let result = Path::new(expanded_path.as_str()).canonicalize();
-
-    // Check if path exists, if so, return string representation
-    match result {
-        Ok(_) => Ok(path_str),
-        Err(e) => Err(e),
+/// Splits a word that starts with `~` into the name after the tilde and the
+/// rest of the word (from the first `/` on). Returns `None` if the word
+/// doesn't start with a tilde
+pub fn split_tilde(word: &OsStr) -> Option<(&OsStr, &OsStr)> {
+    let bytes = word.as_bytes();
+    if bytes.first() != Some(&b'~') {
+        return None;
     }
-}
 
-// Gets the string representation for the path to the home directory. In Unix,
-// this is generally the HOME variable
-fn get_home_str() -> Option<String> {
-    let home = home_dir();
+    let end = bytes.iter().position(|&b| b == b'/').unwrap_or(bytes.len());
+    Some((OsStr::from_bytes(&bytes[1..end]), OsStr::from_bytes(&bytes[end..])))
+}
 
-    // If home variable is found, convert path to string. Otherwise return
-    // an error
-    match home {
-        Some(home) => Some(home.to_str().unwrap().to_string()),
-        None => None,
+/// Returns the home directory of the user named after a tilde, or of the
+/// current user if the name is empty
+pub fn tilde_home<S: AsRef<OsStr>>(user: S) -> Option<PathBuf> {
+    let user = user.as_ref();
+    if user.is_empty() {
+        home_dir()
+    } else {
+        sys::user_home(user)
     }
 }
 
+// Condenses a path so that an absolute path is condensed and normalized
+// to a path relative to the home directory. Only a leading home directory
+// is condensed. Since the result is only for display, bytes that aren't
+// valid UTF-8 are replaced
+pub fn condense_path(path: &Path) -> Result<String, io::Error> {
+    expand_path(path).canonicalize()?;
+
+    let condensed = match home_dir() {
+        Some(ref home) if path == home => "~".to_string(),
+        Some(ref home) if path.starts_with(home) => match path.strip_prefix(home) {
+            Ok(rest) => format!("~/{}", rest.to_string_lossy()),
+            Err(_) => path.to_string_lossy().into_owned(),
+        },
+        _ => path.to_string_lossy().into_owned(),
+    };
+    Ok(condensed)
+}
+
 // Unit tests
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::env;
+    use std::fs;
 
     // Tests that the norm_abs_path can convert from a relative path to an
     // absolute path, given a valid path as an input
     #[test]
     fn test_norm_abs_path_valid_path() {
-        let path = "~";
-        let abs_path = norm_abs_path(path).unwrap(); // this should not panic
-        let correct_abs_path = get_home_str().unwrap();
+        let abs_path = norm_abs_path(Path::new("~")).unwrap(); // this should not panic
+        let correct_abs_path = home_dir().unwrap().canonicalize().unwrap();
         assert_eq!(correct_abs_path, abs_path);
     }
 
     // Tests the norm_abs path with an path
     #[test]
     fn test_norm_abs_path_invalid_path() {
-        let path = "invalid";
-        let abs_path = norm_abs_path(path);
+        let abs_path = norm_abs_path(Path::new("invalid"));
 
         // Should throw an error with an invalid path
         match abs_path {
@@ -104,7 +200,7 @@ mod tests {
     // need to be expanded at all
     #[test]
     fn test_norm_abs_path_with_abs_path() {
-        let path = "/";
+        let path = Path::new("/");
         let abs_path = norm_abs_path(path);
         assert_eq!(path, abs_path.unwrap());
     }
@@ -112,17 +208,16 @@ mod tests {
     // Tests if path can be condensed with a valid path name
     #[test]
     fn test_condense_path_valid_path() {
-        let path = get_home_str().unwrap();
+        let path = home_dir().unwrap();
         let correct_condensed_path = "~";
-        let condensed_path = condense_path(path.as_str());
+        let condensed_path = condense_path(&path);
         assert_eq!(correct_condensed_path, condensed_path.unwrap());
     }
 
     // Tests if path can be condensed with an invalid path name
     #[test]
     fn test_condense_path_invalid_path() {
-        let path = "nonexistent";
-        let condensed_path = condense_path(path);
+        let condensed_path = condense_path(Path::new("nonexistent"));
 
         match condensed_path {
             Ok(_) => panic!("Test failed"),
@@ -135,14 +230,54 @@ mod tests {
     #[test]
     fn test_condense_path_already_condensed() {
         let path = "~";
-        let condensed_path = condense_path(path).unwrap();
+        let condensed_path = condense_path(Path::new(path)).unwrap();
         assert_eq!(path, condensed_path);
     }
 
-    // Tests if function can properly retrieve the HOME path string
+    // Tests that a path that isn't valid UTF-8 is only made lossy for
+    // display
+    #[test]
+    fn test_condense_path_invalid_utf8() {
+        let dir = env::temp_dir().join(OsStr::from_bytes(b"ensh_condense_\xff"));
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(condense_path(&dir).unwrap(), dir.to_string_lossy());
+        assert_eq!(norm_abs_path(&dir).unwrap().file_name(), dir.file_name());
+    }
+
+    // Tests that logical paths are normalized without touching the
+    // filesystem
+    #[test]
+    fn test_logical_path() {
+        let base = Path::new("/a/link");
+        assert_eq!(logical_path(base, Path::new("..")), PathBuf::from("/a"));
+        assert_eq!(logical_path(base, Path::new("./b/../c")), PathBuf::from("/a/link/c"));
+        assert_eq!(logical_path(base, Path::new("/x/./y/..")), PathBuf::from("/x"));
+        assert_eq!(logical_path(base, Path::new("../../../..")), PathBuf::from("/"));
+    }
+
+    // Tests that only a leading tilde is expanded
+    #[test]
+    fn test_expand_path_tilde_position() {
+        let home = home_dir().unwrap();
+        assert_eq!(expand_path(Path::new("~/src")), home.join("src"));
+        assert_eq!(expand_path(Path::new("a~b")), Path::new("a~b"));
+        assert_eq!(expand_path(Path::new("/tmp/~")), Path::new("/tmp/~"));
+    }
+
+    // Tests that `~user` looks the user up. Assumes there is a root user
+    #[test]
+    fn test_expand_path_user() {
+        let root_home = sys::user_home(OsStr::new("root")).unwrap();
+        assert_eq!(expand_path(Path::new("~root/x")), root_home.join("x"));
+        assert_eq!(expand_path(Path::new("~no-such-user-for-ensh/x")), Path::new("~no-such-user-for-ensh/x"));
+    }
+
+    // Tests splitting a word at its tilde prefix
     #[test]
-    fn test_get_home_path_str() {
-        let home_str = get_home_str();
-        assert!(home_str.is_some());
+    fn test_split_tilde() {
+        let split = |word: &'static str| split_tilde(OsStr::new(word));
+        assert_eq!(split("~"), Some((OsStr::new(""), OsStr::new(""))));
+        assert_eq!(split("~bob/a/b"), Some((OsStr::new("bob"), OsStr::new("/a/b"))));
+        assert_eq!(split("x~"), None);
     }
 }
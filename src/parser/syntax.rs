@@ -0,0 +1,840 @@
+//! syntax.rs    Afnan Enayet
+//!
+//! A recursive descent parser that builds a syntax tree out of the tokens
+//! from the lexer. The grammar it understands, roughly:
+//!
+//! ```text
+//! list     := and_or ((';' | newline) and_or)*
+//! and_or   := pipeline (('&&' | '||') newline* pipeline)*
+//! pipeline := ['!'] command ('|' newline* command)*
+//! command  := simple | compound redirect* | function
+//! compound := group | subshell | if | loop | for | case | cond | arith
+//! simple   := (word | redirect)+
+//! redirect := [io_number] ('<' | '>' | '>>' | '>|' | '<>' | '<&' | '>&' | '<<<') word
+//!           | [io_number] ('<<' | '<<-') word here_doc
+//! group    := '{' list '}'
+//! subshell := '(' list ')'
+//! if       := 'if' list 'then' list ('elif' list 'then' list)* ['else' list] 'fi'
+//! loop     := ('while' | 'until') list 'do' list 'done'
+//! for      := 'for' name ['in' word* (';' | newline)] 'do' list 'done'
+//! case     := 'case' word 'in' (['('] word ('|' word)* ')' list [';;'])* 'esac'
+//! cond     := '[[' cond_or ']]'
+//! arith    := '((' expression '))'
+//! function := name '(' ')' group | 'function' name ['(' ')'] group
+//!
+//! cond_or      := cond_and ('||' cond_and)*
+//! cond_and     := cond_not ('&&' cond_not)*
+//! cond_not     := '!' cond_not | cond_primary
+//! cond_primary := '(' cond_or ')' | unary_op word | word binary_op word | word
+//! ```
+//!
+//! Reserved words like `{`, `if` and `done` are only recognized where a
+//! command can start, so `echo done` still passes `done` along as an
+//! argument. Line breaks are allowed anywhere a `;` is, and after the
+//! reserved words that start a list
+//!
+//! Input that stops in the middle of a command gives
+//! `ParseError::UnexpectedEnd`, which the prompt uses to tell that it should
+//! read another line
+
+use std::rc::Rc;
+
+use super::ParseError;
+use super::ast::{Case, CaseItem, Command, CondExpr, For, FunctionDef, If, List, Loop, Redirect, RedirectOp};
+use super::lexer::{is_quoted, Op, Token};
+use cond::{is_binary_op, is_unary_op};
+use vars::valid_name;
+
+// Reserved words that end a list rather than starting a command
+const TERMINATORS: &[&str] = &["}", "then", "elif", "else", "fi", "do", "done", "esac"];
+
+// Words that can't be used as function names
+const RESERVED: &[&str] = &[
+    "{", "}", "!", "function", "if", "then", "elif", "else", "fi", "while", "until", "for", "do", "done", "case",
+    "esac", "in", "[[", "]]",
+];
+
+// The binary operators of `[[ ]]` on top of the ones `test` has
+const COND_BINARY_OPS: &[&str] = &["=~"];
+
+/// Parses a whole line of tokens into a list of commands
+pub fn parse(tokens: Vec<Token>) -> Result<List, ParseError> {
+    let mut parser = Parser { tokens, pos: 0 };
+    let list = parser.list()?;
+
+    match parser.peek() {
+        None => Ok(list),
+        Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+    }
+}
+
+// The tokens being parsed and how far the parser has got
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    // Returns the next token without consuming it
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    // Returns the word `offset` tokens ahead, if that token is a word
+    fn peek_word(&self, offset: usize) -> Option<&str> {
+        match self.tokens.get(self.pos + offset) {
+            Some(Token::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    // Consumes the next token
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    // Returns the error for the next token, which is somewhere it isn't
+    // allowed
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError::UnexpectedToken(token.to_string()),
+            None => ParseError::UnexpectedEnd,
+        }
+    }
+
+    // Consumes the next token, which has to be `expected`
+    fn expect(&mut self, expected: &Token) -> Result<(), ParseError> {
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    // Consumes the next token, which has to be the reserved word `expected`
+    fn expect_word(&mut self, expected: &str) -> Result<(), ParseError> {
+        if self.peek_word(0) == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    // Consumes any line breaks
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Op(Op::Newline)) {
+            self.pos += 1;
+        }
+    }
+
+    // Parses commands separated by `;` or line breaks, up to the end of the
+    // input, an operator that can't start a command or a reserved word that
+    // ends the list
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut list = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                Some(Token::Word(word)) if TERMINATORS.contains(&word.as_str()) => break,
+                Some(&Token::Op(Op::LParen)) => {}
+                Some(Token::Op(op)) if !op.is_redirect() => break,
+                None | Some(Token::HereDoc(_)) => break,
+                Some(_) => {}
+            }
+
+            list.push(self.and_or()?);
+
+            match self.peek() {
+                Some(&Token::Op(Op::Semi)) | Some(&Token::Op(Op::Newline)) => self.pos += 1,
+                _ => break,
+            }
+        }
+        Ok(list)
+    }
+
+    // Parses a list that has to contain at least one command, like the
+    // body of a group or a loop
+    fn compound_list(&mut self) -> Result<List, ParseError> {
+        let list = self.list()?;
+
+        if list.is_empty() {
+            Err(self.unexpected())
+        } else {
+            Ok(list)
+        }
+    }
+
+    // Parses pipelines joined by `&&` and `||`, which bind left to right
+    fn and_or(&mut self) -> Result<Command, ParseError> {
+        let mut command = self.pipeline()?;
+
+        loop {
+            let op = match self.peek() {
+                Some(&Token::Op(op @ Op::AndIf)) | Some(&Token::Op(op @ Op::OrIf)) => op,
+                _ => return Ok(command),
+            };
+            self.pos += 1;
+            self.skip_newlines();
+
+            let next = Box::new(self.pipeline()?);
+            command = match op {
+                Op::AndIf => Command::And(Box::new(command), next),
+                _ => Command::Or(Box::new(command), next),
+            };
+        }
+    }
+
+    // Parses commands joined by `|`, which can be negated with a leading
+    // `!`. A single command isn't wrapped in a pipeline
+    fn pipeline(&mut self) -> Result<Command, ParseError> {
+        if self.peek_word(0) == Some("!") {
+            self.pos += 1;
+            return Ok(Command::Not(Box::new(self.pipeline()?)));
+        }
+
+        let mut commands = vec![self.command()?];
+
+        while self.peek() == Some(&Token::Op(Op::Pipe)) {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+
+        if commands.len() == 1 {
+            Ok(commands.remove(0))
+        } else {
+            Ok(Command::Pipeline(commands))
+        }
+    }
+
+    // Parses a single command
+    fn command(&mut self) -> Result<Command, ParseError> {
+        if let Some(Token::Arith(expr)) = self.peek() {
+            let expr = expr.clone();
+            self.pos += 1;
+            return self.redirected(Command::Arith(expr));
+        }
+        if self.peek() == Some(&Token::Op(Op::LParen)) {
+            let command = self.subshell()?;
+            return self.redirected(command);
+        }
+        if self.at_redirect() {
+            return self.simple();
+        }
+
+        let command = match self.peek_word(0) {
+            Some("{") => self.group()?,
+            Some("[[") => self.cond_command()?,
+            Some("if") => self.if_command()?,
+            Some("while") => self.loop_command(false)?,
+            Some("until") => self.loop_command(true)?,
+            Some("for") => self.for_command()?,
+            Some("case") => self.case_command()?,
+            Some("function") => {
+                self.pos += 1;
+                let name = self.function_name()?;
+                if self.peek() == Some(&Token::Op(Op::LParen)) {
+                    self.pos += 1;
+                    self.expect(&Token::Op(Op::RParen))?;
+                }
+                return self.function_body(name);
+            }
+            Some(_) if self.tokens.get(self.pos + 1) == Some(&Token::Op(Op::LParen)) => {
+                let name = self.function_name()?;
+                self.pos += 1;
+                self.expect(&Token::Op(Op::RParen))?;
+                return self.function_body(name);
+            }
+            Some(_) => return self.simple(),
+            None => return Err(self.unexpected()),
+        };
+        self.redirected(command)
+    }
+
+    // Parses the redirections after a compound command, which apply to the
+    // whole of it
+    fn redirected(&mut self, command: Command) -> Result<Command, ParseError> {
+        let mut redirects = Vec::new();
+
+        while self.at_redirect() {
+            redirects.push(self.redirect()?);
+        }
+
+        if redirects.is_empty() {
+            Ok(command)
+        } else {
+            Ok(Command::Redirected(Box::new(command), redirects))
+        }
+    }
+
+    // Parses a simple command: the words and redirections up to the next
+    // operator
+    fn simple(&mut self) -> Result<Command, ParseError> {
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    words.push(word.clone());
+                    self.pos += 1;
+                }
+                _ if self.at_redirect() => redirects.push(self.redirect()?),
+                _ => break,
+            }
+        }
+
+        let command = Command::Simple(words);
+        if redirects.is_empty() {
+            Ok(command)
+        } else {
+            Ok(Command::Redirected(Box::new(command), redirects))
+        }
+    }
+
+    // Returns whether the next token starts a redirection
+    fn at_redirect(&self) -> bool {
+        match self.peek() {
+            Some(Token::IoNumber(_)) => true,
+            Some(&Token::Op(op)) => op.is_redirect(),
+            _ => false,
+        }
+    }
+
+    // Parses a redirection: an optional file descriptor, the operator and
+    // its word, and for a here-document the body after that
+    fn redirect(&mut self) -> Result<Redirect, ParseError> {
+        let fd = match self.peek() {
+            Some(&Token::IoNumber(fd)) => {
+                self.pos += 1;
+                Some(fd)
+            }
+            _ => None,
+        };
+        let op = match self.peek() {
+            Some(&Token::Op(op)) if op.is_redirect() => op,
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+
+        let default_fd = match op {
+            Op::Less | Op::LessGreat | Op::LessAnd | Op::DoubleLess | Op::DoubleLessDash | Op::TripleLess => 0,
+            _ => 1,
+        };
+
+        // A redirection without its word is a mistake, even at the end of
+        // the input
+        let word = match self.next() {
+            Some(Token::Word(word)) => word,
+            Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+            None => return Err(ParseError::UnexpectedToken(Op::Newline.to_string())),
+        };
+
+        let (op, target) = match op {
+            Op::DoubleLess | Op::DoubleLessDash => match self.next() {
+                Some(Token::HereDoc(body)) => (RedirectOp::HereDoc { expand: !is_quoted(&word) }, body),
+                _ => return Err(ParseError::UnexpectedEnd),
+            },
+            Op::Less => (RedirectOp::Read, word),
+            Op::Great => (RedirectOp::Write, word),
+            Op::DoubleGreat => (RedirectOp::Append, word),
+            Op::Clobber => (RedirectOp::Clobber, word),
+            Op::LessGreat => (RedirectOp::ReadWrite, word),
+            Op::TripleLess => (RedirectOp::HereString, word),
+            _ => (RedirectOp::Duplicate, word),
+        };
+
+        Ok(Redirect { fd: fd.unwrap_or(default_fd), op, target })
+    }
+
+    // Parses `{ list }`
+    fn group(&mut self) -> Result<Command, ParseError> {
+        self.pos += 1;
+        let list = self.compound_list()?;
+        self.expect_word("}")?;
+        Ok(Command::Group(list))
+    }
+
+    // Parses `( list )`
+    fn subshell(&mut self) -> Result<Command, ParseError> {
+        self.pos += 1;
+        let list = self.compound_list()?;
+        self.expect(&Token::Op(Op::RParen))?;
+        Ok(Command::Subshell(list))
+    }
+
+    // Parses `if list then list [elif list then list]... [else list] fi`
+    fn if_command(&mut self) -> Result<Command, ParseError> {
+        self.pos += 1;
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+
+        loop {
+            let condition = self.compound_list()?;
+            self.expect_word("then")?;
+            branches.push((condition, self.compound_list()?));
+
+            match self.peek_word(0) {
+                Some("elif") => self.pos += 1,
+                Some("else") => {
+                    self.pos += 1;
+                    otherwise = Some(self.compound_list()?);
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        self.expect_word("fi")?;
+        Ok(Command::If(If { branches, otherwise }))
+    }
+
+    // Parses `while list do list done` or `until list do list done`
+    fn loop_command(&mut self, until: bool) -> Result<Command, ParseError> {
+        self.pos += 1;
+        let condition = self.compound_list()?;
+        let body = self.do_group()?;
+        Ok(Command::Loop(Loop { until, condition, body }))
+    }
+
+    // Parses `for name [in word...;] do list done`
+    fn for_command(&mut self) -> Result<Command, ParseError> {
+        self.pos += 1;
+        let name = match self.peek_word(0) {
+            Some(name) if valid_name(name) => name.to_string(),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        self.skip_newlines();
+
+        let words = if self.peek_word(0) == Some("in") {
+            self.pos += 1;
+            let mut words = Vec::new();
+            while let Some(Token::Word(word)) = self.peek() {
+                words.push(word.clone());
+                self.pos += 1;
+            }
+
+            // The words have to be ended before `do`, or it would be
+            // taken as one of them
+            match self.peek() {
+                Some(&Token::Op(Op::Semi)) | Some(&Token::Op(Op::Newline)) => self.pos += 1,
+                _ => return Err(self.unexpected()),
+            }
+            Some(words)
+        } else {
+            if self.peek() == Some(&Token::Op(Op::Semi)) {
+                self.pos += 1;
+            }
+            None
+        };
+
+        let body = self.do_group()?;
+        Ok(Command::For(For { name, words, body }))
+    }
+
+    // Parses `do list done`, the body of a loop
+    fn do_group(&mut self) -> Result<List, ParseError> {
+        self.skip_newlines();
+        self.expect_word("do")?;
+        let body = self.compound_list()?;
+        self.expect_word("done")?;
+        Ok(body)
+    }
+
+    // Parses `case word in [[(] pattern [| pattern]...) list ;;]... esac`.
+    // The `;;` can be left off the last item
+    fn case_command(&mut self) -> Result<Command, ParseError> {
+        self.pos += 1;
+        let word = match self.next() {
+            Some(Token::Word(word)) => word,
+            Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+            None => return Err(ParseError::UnexpectedEnd),
+        };
+        self.skip_newlines();
+        self.expect_word("in")?;
+
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek_word(0) == Some("esac") {
+                self.pos += 1;
+                break;
+            }
+
+            if self.peek() == Some(&Token::Op(Op::LParen)) {
+                self.pos += 1;
+            }
+            let mut patterns = vec![self.pattern()?];
+            while self.peek() == Some(&Token::Op(Op::Pipe)) {
+                self.pos += 1;
+                patterns.push(self.pattern()?);
+            }
+            self.expect(&Token::Op(Op::RParen))?;
+
+            let body = self.list()?;
+            items.push(CaseItem { patterns, body });
+
+            match self.peek() {
+                Some(&Token::Op(Op::DoubleSemi)) => self.pos += 1,
+                _ => {
+                    self.expect_word("esac")?;
+                    break;
+                }
+            }
+        }
+        Ok(Command::Case(Case { word, items }))
+    }
+
+    // Consumes one of the patterns of a `case` item
+    fn pattern(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Token::Word(word)) => {
+                let word = word.clone();
+                self.pos += 1;
+                Ok(word)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    // Parses `[[ expression ]]`
+    fn cond_command(&mut self) -> Result<Command, ParseError> {
+        self.pos += 1;
+        let expr = self.cond_or()?;
+        self.expect_word("]]")?;
+        Ok(Command::Cond(expr))
+    }
+
+    // Parses conditional expressions joined by `||`
+    fn cond_or(&mut self) -> Result<CondExpr, ParseError> {
+        let mut expr = self.cond_and()?;
+
+        while self.peek() == Some(&Token::Op(Op::OrIf)) {
+            self.pos += 1;
+            expr = CondExpr::Or(Box::new(expr), Box::new(self.cond_and()?));
+        }
+        Ok(expr)
+    }
+
+    // Parses conditional expressions joined by `&&`
+    fn cond_and(&mut self) -> Result<CondExpr, ParseError> {
+        let mut expr = self.cond_not()?;
+
+        while self.peek() == Some(&Token::Op(Op::AndIf)) {
+            self.pos += 1;
+            expr = CondExpr::And(Box::new(expr), Box::new(self.cond_not()?));
+        }
+        Ok(expr)
+    }
+
+    // Parses a conditional expression that may be negated with `!`
+    fn cond_not(&mut self) -> Result<CondExpr, ParseError> {
+        if self.peek_word(0) == Some("!") {
+            self.pos += 1;
+            return Ok(CondExpr::Not(Box::new(self.cond_not()?)));
+        }
+        self.cond_primary()
+    }
+
+    // Parses a parenthesized expression, a test with an operator or a
+    // single word
+    fn cond_primary(&mut self) -> Result<CondExpr, ParseError> {
+        if self.peek() == Some(&Token::Op(Op::LParen)) {
+            self.pos += 1;
+            let expr = self.cond_or()?;
+            self.expect(&Token::Op(Op::RParen))?;
+            return Ok(expr);
+        }
+
+        let word = match self.peek_word(0) {
+            Some("]]") | None => return Err(self.unexpected()),
+            Some(word) => word.to_string(),
+        };
+        self.pos += 1;
+
+        // An operator only counts as one if it has an operand, so that
+        // `[[ -f ]]` tests whether "-f" is empty
+        match self.peek_word(0) {
+            Some(op) if is_binary_op(op) || COND_BINARY_OPS.contains(&op) => {
+                let op = op.to_string();
+                self.pos += 1;
+                match self.peek_word(0) {
+                    Some(right) if right != "]]" => {
+                        let right = right.to_string();
+                        self.pos += 1;
+                        Ok(CondExpr::Binary(word, op, right))
+                    }
+                    _ => Err(self.unexpected()),
+                }
+            }
+            Some(operand) if is_unary_op(&word) && operand != "]]" => {
+                let operand = operand.to_string();
+                self.pos += 1;
+                Ok(CondExpr::Unary(word, operand))
+            }
+            _ => Ok(CondExpr::Word(word)),
+        }
+    }
+
+    // Consumes the name of a function being defined
+    fn function_name(&mut self) -> Result<String, ParseError> {
+        match self.next() {
+            Some(Token::Word(name)) => {
+                if is_quoted(&name) || RESERVED.contains(&name.as_str()) || name.contains(['$', '=', '`']) {
+                    Err(ParseError::UnexpectedToken(name))
+                } else {
+                    Ok(name)
+                }
+            }
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+
+    // Parses the body of a function, which may start on the next line
+    fn function_body(&mut self, name: String) -> Result<Command, ParseError> {
+        self.skip_newlines();
+
+        if self.peek_word(0) != Some("{") {
+            return Err(self.unexpected());
+        }
+
+        let body = Rc::new(self.group()?);
+        Ok(Command::FunctionDef(FunctionDef { name, body }))
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::lexer::tokenize;
+
+    // Tokenizes and parses a line
+    fn parse_str(input: &str) -> Result<List, ParseError> {
+        parse(tokenize(input).unwrap())
+    }
+
+    // Builds a simple command from its words
+    fn simple(words: &[&str]) -> Command {
+        Command::Simple(words.iter().map(|word| word.to_string()).collect())
+    }
+
+    // Tests that commands are separated by semicolons and line breaks
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(parse_str("a b; c\n\nd;").unwrap(), vec![simple(&["a", "b"]), simple(&["c"]), simple(&["d"])]);
+        assert!(parse_str("").unwrap().is_empty());
+        assert_eq!(parse_str("; a"), Err(ParseError::UnexpectedToken(";".to_string())));
+    }
+
+    // Tests brace groups, and that `}` is only special in command position
+    #[test]
+    fn test_parse_group() {
+        assert_eq!(parse_str("{ a; b; }").unwrap(), vec![Command::Group(vec![simple(&["a"]), simple(&["b"])])]);
+        assert_eq!(parse_str("echo { }").unwrap(), vec![simple(&["echo", "{", "}"])]);
+        assert_eq!(parse_str("{ a }"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(parse_str("{ }"), Err(ParseError::UnexpectedToken("}".to_string())));
+        assert_eq!(parse_str("a }"), Ok(vec![simple(&["a", "}"])]));
+    }
+
+    // Tests subshells, and that `( )` still works as a case pattern and in a
+    // function definition
+    #[test]
+    fn test_parse_subshell() {
+        let expected = vec![Command::Subshell(vec![simple(&["a"]), Command::Subshell(vec![simple(&["b"])])])];
+        assert_eq!(parse_str("(a; (b))").unwrap(), expected);
+        assert_eq!(parse_str("(\na\n)\nc").unwrap(), vec![Command::Subshell(vec![simple(&["a"])]), simple(&["c"])]);
+        assert!(parse_str("case x in (a) (b);; esac").is_ok());
+        assert!(parse_str("f() { (a); }").is_ok());
+        assert_eq!(parse_str("(a"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(parse_str("()"), Err(ParseError::UnexpectedToken(")".to_string())));
+        assert_eq!(parse_str("a)"), Err(ParseError::UnexpectedToken(")".to_string())));
+    }
+
+    // Tests that redirections after a compound command apply to all of it
+    #[test]
+    fn test_parse_compound_redirect() {
+        let out = Redirect { fd: 1, op: RedirectOp::Write, target: "out".to_string() };
+        let expected = vec![
+            Command::Redirected(Box::new(Command::Group(vec![simple(&["a"])])), vec![out.clone()]),
+            Command::Redirected(Box::new(Command::Subshell(vec![simple(&["b"])])), vec![out.clone()]),
+        ];
+        assert_eq!(parse_str("{ a; } >out; (b) >out").unwrap(), expected);
+        assert_eq!(parse_str("{ a; } b"), Err(ParseError::UnexpectedToken("b".to_string())));
+    }
+
+    // Tests both ways of defining a function
+    #[test]
+    fn test_parse_function() {
+        let body = Rc::new(Command::Group(vec![simple(&["echo", "$1"])]));
+        let expected = vec![Command::FunctionDef(FunctionDef { name: "f".to_string(), body })];
+
+        assert_eq!(parse_str("f() { echo $1; }").unwrap(), expected);
+        assert_eq!(parse_str("f ()\n{\necho $1\n}").unwrap(), expected);
+        assert_eq!(parse_str("function f { echo $1; }").unwrap(), expected);
+        assert_eq!(parse_str("function f() { echo $1; }").unwrap(), expected);
+        assert_eq!(parse_str("f() echo"), Err(ParseError::UnexpectedToken("echo".to_string())));
+        assert_eq!(parse_str("'f'() { a; }"), Err(ParseError::UnexpectedToken("'f'".to_string())));
+        assert_eq!(parse_str("f() {"), Err(ParseError::UnexpectedEnd));
+    }
+
+    // Tests `if` with `elif` and `else`, over several lines
+    #[test]
+    fn test_parse_if() {
+        let expected = vec![Command::If(If {
+            branches: vec![(vec![simple(&["a"])], vec![simple(&["b"])]), (vec![simple(&["c"])], vec![simple(&["d"])])],
+            otherwise: Some(vec![simple(&["e", "fi"])]),
+        })];
+        assert_eq!(parse_str("if a; then b; elif c; then d; else e fi; fi").unwrap(), expected);
+        assert_eq!(parse_str("if a\nthen\n  b\nelif c\nthen d\nelse\n e fi\nfi").unwrap(), expected);
+        assert_eq!(parse_str("if a; then fi"), Err(ParseError::UnexpectedToken("fi".to_string())));
+        assert_eq!(parse_str("if a; then b"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(parse_str("fi"), Err(ParseError::UnexpectedToken("fi".to_string())));
+    }
+
+    // Tests `while` and `until` loops
+    #[test]
+    fn test_parse_loop() {
+        let expected = |until| vec![Command::Loop(Loop { until, condition: vec![simple(&["a"])], body: vec![simple(&["b"])] })];
+        assert_eq!(parse_str("while a; do b; done").unwrap(), expected(false));
+        assert_eq!(parse_str("until a\ndo\nb\ndone").unwrap(), expected(true));
+        assert_eq!(parse_str("while a; do b; done; x").unwrap().len(), 2);
+        assert_eq!(parse_str("while a; b"), Err(ParseError::UnexpectedEnd));
+    }
+
+    // Tests `for` loops with and without a word list
+    #[test]
+    fn test_parse_for() {
+        let body = vec![simple(&["echo", "$x"])];
+        let words = Some(vec!["a".to_string(), "do".to_string()]);
+        let expected = |words| vec![Command::For(For { name: "x".to_string(), words, body: body.clone() })];
+        assert_eq!(parse_str("for x in a do; do echo $x; done").unwrap(), expected(words));
+        assert_eq!(parse_str("for x in; do echo $x; done").unwrap(), expected(Some(Vec::new())));
+        assert_eq!(parse_str("for x\ndo echo $x\ndone").unwrap(), expected(None));
+        assert_eq!(parse_str("for x; do echo $x; done").unwrap(), expected(None));
+        assert_eq!(parse_str("for 1x; do a; done"), Err(ParseError::UnexpectedToken("1x".to_string())));
+        assert_eq!(parse_str("for x in a"), Err(ParseError::UnexpectedEnd));
+    }
+
+    // Tests `case` items, with and without their optional parts
+    #[test]
+    fn test_parse_case() {
+        let expected = vec![Command::Case(Case {
+            word: "$x".to_string(),
+            items: vec![
+                CaseItem { patterns: vec!["a".to_string(), "b*".to_string()], body: vec![simple(&["one"])] },
+                CaseItem { patterns: vec!["c".to_string()], body: Vec::new() },
+                CaseItem { patterns: vec!["*".to_string()], body: vec![simple(&["two"]), simple(&["three"])] },
+            ],
+        })];
+        assert_eq!(parse_str("case $x in a|b*) one;; (c) ;; *) two; three;; esac").unwrap(), expected);
+        assert_eq!(parse_str("case $x\nin\na | b*)\n one\n;;\nc);;\n*) two\nthree\nesac").unwrap(), expected);
+        assert_eq!(parse_str("case x in esac").unwrap(), vec![Command::Case(Case { word: "x".to_string(), items: Vec::new() })]);
+        assert_eq!(parse_str("case x in a) b"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(parse_str("case x in a b"), Err(ParseError::UnexpectedToken("b".to_string())));
+    }
+
+    // Tests that `&&` and `||` bind left to right and can be followed by a
+    // line break
+    #[test]
+    fn test_parse_and_or() {
+        let expected = vec![Command::Or(
+            Box::new(Command::And(Box::new(simple(&["a"])), Box::new(simple(&["b"])))),
+            Box::new(simple(&["c"])),
+        )];
+        assert_eq!(parse_str("a && b || c").unwrap(), expected);
+        assert_eq!(parse_str("a &&\nb ||\n\nc").unwrap(), expected);
+        assert_eq!(parse_str("a &&"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(parse_str("&& a"), Err(ParseError::UnexpectedToken("&&".to_string())));
+    }
+
+    // Tests that pipelines bind tighter than `&&` and `||`
+    #[test]
+    fn test_parse_pipeline() {
+        let pipeline = Command::Pipeline(vec![simple(&["a"]), simple(&["b", "c"]), simple(&["d"])]);
+        assert_eq!(parse_str("a | b c |\n d").unwrap(), vec![pipeline]);
+
+        let expected = vec![Command::And(
+            Box::new(Command::Pipeline(vec![simple(&["a"]), simple(&["b"])])),
+            Box::new(simple(&["c"])),
+        )];
+        assert_eq!(parse_str("a | b && c").unwrap(), expected);
+        assert_eq!(parse_str("a |"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(parse_str("| a"), Err(ParseError::UnexpectedToken("|".to_string())));
+    }
+
+    // Tests that `!` negates a whole pipeline, binds tighter than `&&` and
+    // is only special where a command starts
+    #[test]
+    fn test_parse_not() {
+        let expected = vec![Command::And(
+            Box::new(Command::Not(Box::new(Command::Pipeline(vec![simple(&["a"]), simple(&["b"])])))),
+            Box::new(Command::Not(Box::new(simple(&["c"])))),
+        )];
+        assert_eq!(parse_str("! a | b && ! c").unwrap(), expected);
+
+        let expected = vec![Command::If(If {
+            branches: vec![(vec![Command::Not(Box::new(simple(&["a"])))], vec![simple(&["b"])])],
+            otherwise: None,
+        })];
+        assert_eq!(parse_str("if ! a; then b; fi").unwrap(), expected);
+        assert_eq!(parse_str("a ! b '!'").unwrap(), vec![simple(&["a", "!", "b", "'!'"])]);
+        assert_eq!(parse_str("'!' a").unwrap(), vec![simple(&["'!'", "a"])]);
+        assert_eq!(parse_str("!"), Err(ParseError::UnexpectedEnd));
+    }
+
+    // Tests the precedence of the operators in `[[ ]]`
+    #[test]
+    fn test_parse_cond() {
+        let word = |w: &str| Box::new(CondExpr::Word(w.to_string()));
+        let expected = vec![Command::Cond(CondExpr::Or(
+            word("a"),
+            Box::new(CondExpr::And(
+                Box::new(CondExpr::Not(Box::new(CondExpr::Unary("-f".to_string(), "x".to_string())))),
+                Box::new(CondExpr::Binary("$y".to_string(), "=~".to_string(), "^(a|b)".to_string())),
+            )),
+        ))];
+        assert_eq!(parse_str("[[ a || ! -f x && $y =~ ^(a|b) ]]").unwrap(), expected);
+        assert_eq!(parse_str("[[ -f ]]").unwrap(), vec![Command::Cond(*word("-f"))]);
+        assert_eq!(parse_str("[[ ( a ) ]]").unwrap(), vec![Command::Cond(*word("a"))]);
+        assert_eq!(parse_str("[[ a == ]]"), Err(ParseError::UnexpectedToken("]]".to_string())));
+        assert_eq!(parse_str("[[ ]]"), Err(ParseError::UnexpectedToken("]]".to_string())));
+        assert_eq!(parse_str("[[ a"), Err(ParseError::UnexpectedEnd));
+    }
+
+    // Tests that `(( ))` is a command of its own
+    #[test]
+    fn test_parse_arith() {
+        let expected = vec![Command::And(Box::new(Command::Arith(" x < 3 ".to_string())), Box::new(simple(&["a"])))];
+        assert_eq!(parse_str("(( x < 3 )) && a").unwrap(), expected);
+        assert_eq!(parse_str("echo ((1))"), Err(ParseError::UnexpectedToken("((1))".to_string())));
+    }
+
+    // Tests that redirections can go anywhere among the words of a simple
+    // command, with the default file descriptor for their operator
+    #[test]
+    fn test_parse_redirect() {
+        let redirect = |fd, op, target: &str| Redirect { fd, op, target: target.to_string() };
+        let expected = vec![Command::Redirected(Box::new(simple(&["cat", "a"])), vec![
+            redirect(0, RedirectOp::Read, "in"),
+            redirect(2, RedirectOp::Duplicate, "1"),
+            redirect(0, RedirectOp::HereDoc { expand: false }, "x\n"),
+            redirect(1, RedirectOp::Append, "out"),
+        ])];
+        assert_eq!(parse_str("cat <in 2>&1 a <<'E' >>out\nx\nE\n").unwrap(), expected);
+
+        let expected = vec![Command::Redirected(Box::new(simple(&[])), vec![redirect(1, RedirectOp::Write, "f")])];
+        assert_eq!(parse_str(">f").unwrap(), expected);
+        assert_eq!(parse_str("cat <"), Err(ParseError::UnexpectedToken("newline".to_string())));
+        assert_eq!(parse_str("cat < ;"), Err(ParseError::UnexpectedToken(";".to_string())));
+    }
+}
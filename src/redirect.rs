@@ -0,0 +1,187 @@
+//! redirect.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Summary
+//! The redirect module points the file descriptors of a command somewhere
+//! else while it runs. Redirections are applied to the shell's own file
+//! descriptors with `dup2`, so they work the same for builtins, functions
+//! and compound commands as for programs, which inherit them. The original
+//! file descriptors are saved out of the way first and put back once the
+//! command is done
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::process;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::env;
+
+use shell::Shell;
+use parser::ast::{Redirect, RedirectOp};
+use parser::expand::{expand_here_doc, expand_string, expand_words};
+use sys;
+
+// Saved file descriptors are moved at least this high, out of the way of
+// the ones scripts use
+const SAVED_FD_MIN: RawFd = 10;
+
+// Counts the here-documents written, to give each a file name of its own
+static HERE_DOCS: AtomicUsize = AtomicUsize::new(0);
+
+/// Redirections that have been applied. Dropping this puts the file
+/// descriptors back the way they were
+pub struct Applied {
+    // Each redirected file descriptor with the copy of what it was before,
+    // or `None` if it wasn't open
+    saved: Vec<(RawFd, Option<RawFd>)>,
+}
+
+impl Drop for Applied {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+
+        for &(fd, saved) in self.saved.iter().rev() {
+            match saved {
+                Some(saved) => {
+                    let _ = sys::dup2(saved, fd);
+                    let _ = sys::close(saved);
+                }
+                None => {
+                    let _ = sys::close(fd);
+                }
+            }
+        }
+    }
+}
+
+// Where a file descriptor is redirected to
+enum Source {
+    File(File),
+    Fd(RawFd),
+    Close,
+}
+
+/// Applies redirections in order. Returns an error message if a target
+/// couldn't be expanded or opened, in which case the ones that were
+/// already applied are undone
+pub fn apply(redirects: &[Redirect], shell: &mut Shell) -> Result<Applied, String> {
+    // Anything already written has to go where it was meant to
+    let _ = io::stdout().flush();
+    let mut applied = Applied { saved: Vec::new() };
+
+    for redirect in redirects {
+        let source = open(redirect, shell)?;
+        applied.redirect(redirect.fd, source)?;
+    }
+    Ok(applied)
+}
+
+impl Applied {
+    // Points `fd` at a source, after saving what it was
+    fn redirect(&mut self, fd: RawFd, source: Source) -> Result<(), String> {
+        let saved = sys::dup_above(fd, SAVED_FD_MIN).ok();
+        self.saved.push((fd, saved));
+
+        let result = match source {
+            Source::File(file) => {
+                let raw = file.into_raw_fd();
+                if raw == fd {
+                    Ok(())
+                } else {
+                    let result = sys::dup2(raw, fd);
+                    let _ = sys::close(raw);
+                    result
+                }
+            }
+            Source::Fd(from) => sys::dup2(from, fd),
+            Source::Close => sys::close(fd).or(Ok(())),
+        };
+        result.map_err(|e| format!("{}: {}", fd, e))
+    }
+}
+
+// Opens what a redirection points to
+fn open(redirect: &Redirect, shell: &mut Shell) -> Result<Source, String> {
+    let target = &redirect.target;
+
+    let contents = match redirect.op {
+        RedirectOp::HereDoc { expand: false } => target.as_bytes().to_vec(),
+        RedirectOp::HereDoc { expand: true } => {
+            expand_here_doc(target, shell).map_err(|e| e.to_string())?.as_bytes().to_vec()
+        }
+        RedirectOp::HereString => {
+            let mut contents = expand_string(target, shell).map_err(|e| e.to_string())?.as_bytes().to_vec();
+            contents.push(b'\n');
+            contents
+        }
+        RedirectOp::Duplicate => {
+            let word = expand_string(target, shell).map_err(|e| e.to_string())?;
+            return match word.to_str() {
+                Some("-") => Ok(Source::Close),
+                Some(fd) => fd.parse().map(Source::Fd).map_err(|_| format!("{}: ambiguous redirect", target)),
+                None => Err(format!("{}: ambiguous redirect", target)),
+            };
+        }
+        _ => return open_file(redirect, shell),
+    };
+
+    temp_file(&contents).map(Source::File).map_err(|e| format!("here-document: {}", e))
+}
+
+// Opens the file a redirection names, relative to the working directory
+fn open_file(redirect: &Redirect, shell: &mut Shell) -> Result<Source, String> {
+    let mut names = expand_words(slice::from_ref(&redirect.target), shell).map_err(|e| e.to_string())?;
+    if names.len() != 1 {
+        return Err(format!("{}: ambiguous redirect", redirect.target));
+    }
+    let name = names.remove(0);
+
+    let mut options = OpenOptions::new();
+    match redirect.op {
+        RedirectOp::Read => options.read(true),
+        RedirectOp::Write | RedirectOp::Clobber => options.write(true).create(true).truncate(true),
+        RedirectOp::Append => options.append(true).create(true),
+        _ => options.read(true).write(true).create(true),
+    };
+
+    let path = shell.working_dir().join(&name);
+    let failed = |e: io::Error| format!("{}: {}", name.to_string_lossy(), e);
+
+    // With `set -C`, `>` only creates files, so one that is already there
+    // isn't truncated, even if it appears after it was checked for. Things
+    // that aren't regular files, like `/dev/null`, are still opened. `>|`
+    // always truncates
+    if redirect.op == RedirectOp::Write && shell.options().noclobber {
+        return match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => Ok(Source::File(file)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => match fs::metadata(&path) {
+                Ok(ref metadata) if metadata.is_file() => {
+                    Err(format!("{}: cannot overwrite existing file", name.to_string_lossy()))
+                }
+                _ => OpenOptions::new().write(true).open(&path).map(Source::File).map_err(failed),
+            },
+            Err(e) => Err(failed(e)),
+        };
+    }
+
+    options.open(&path).map(Source::File).map_err(failed)
+}
+
+// Writes the contents of a here-document to a file that is already
+// deleted, and returns it open for reading from the start. A pipe could
+// fill up before the command gets to read from it
+fn temp_file(contents: &[u8]) -> io::Result<File> {
+    let count = HERE_DOCS.fetch_add(1, Ordering::SeqCst);
+    let path = env::temp_dir().join(format!("ensh-here-{}-{}", process::id(), count));
+
+    let mut file = OpenOptions::new().read(true).write(true).create_new(true).mode(0o600).open(&path)?;
+    fs::remove_file(&path)?;
+    file.write_all(contents)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
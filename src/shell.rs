@@ -1,121 +1,648 @@
-/// shell.rs
-///
-/// # Author
-/// Afnan Enayet
-///
-/// # Summary
-/// The ensh module provides a structure and implementation of a shell object.
-/// It contains state variables that the shell needs to keep track of, such as:
-/// * the current working directory
-/// * command history
-/// * the number of lines that have been input and output
-/// * the PATH directories
-///
-/// This object is used to represent a shell session and hold shell state
-/// information. It's important to note that this module has side effects
-/// and will affect the environment's working directory as a side effect
-/// of changing the shell's working directory
-
-use std::path::PathBuf;
-use std::env;
+//! shell.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Summary
+//! The ensh module provides a structure and implementation of a shell object.
+//! It contains state variables that the shell needs to keep track of, such as:
+//! * the current working directory
+//! * command history
+//! * the number of lines that have been input and output
+//! * the PATH directories
+//! * the settings loaded from the config file
+//! * the directories that have been visited, for `j`
+//! * the shell's variables, functions and the status of the last command
+//! * the options turned on with `set`
+//! * the commands set with `trap`
+//!
+//! This object is used to represent a shell session and hold shell state
+//! information. It's important to note that this module has side effects
+//! and will affect the environment's working directory as a side effect
+//! of changing the shell's working directory
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{env, fs, io, mem};
 
 use parser;
+use parser::config::Config;
+use parser::glob::NoMatch;
+use parser::ast::Command;
+use vars::Variables;
+use frecency::{self, FrecencyDb};
+use interface::print_err;
+use consts::SHELL_PROMPT;
+use sys;
+use libc;
 
 /// A shell and its associated information is associated here, including
 /// the current working directory, the input history, and the paths that
 /// the shell will search
 #[derive(Debug)]
-#[derive(Default)]
 pub struct Shell {
-    /// The current working directory
+    /// The current working directory. This is the logical path, so any
+    /// symlinks are kept the way the user typed them
     working_dir: PathBuf,
 
+    /// The working directory before the last change of directory
+    previous_dir: Option<PathBuf>,
+
+    /// The directory stack used by `pushd` and `popd`, most recent first.
+    /// The working directory is the (implicit) top of the stack
+    dir_stack: Vec<PathBuf>,
+
+    /// The directories that have been visited, ranked by frecency
+    frecency: FrecencyDb,
+
     /// The user's input history
+    #[allow(dead_code)]
     input_history: Vec<String>,
 
     /// The number of lines outputted
+    #[allow(dead_code)]
     output_count: u64,
 
     /// The paths the shell will search for binaries/commands
-    paths: Vec<String>,
+    paths: Vec<PathBuf>,
+
+    /// The config file the shell's settings were loaded from
+    config_path: Option<PathBuf>,
+
+    /// The modification time of the config file when it was last loaded
+    config_mtime: Option<SystemTime>,
+
+    /// Whether the config is reloaded when the file changes between prompts
+    auto_reload: bool,
+
+    /// The symbol shown in front of the input space
+    prompt: String,
+
+    /// Whether the prompt shows the depth of the directory stack
+    show_dir_stack: bool,
+
+    /// The environment variables that were set from the config file
+    config_env: Vec<String>,
+
+    /// The user's aliases, by name
+    aliases: BTreeMap<String, String>,
+
+    /// The aliases that were defined by the config file
+    config_aliases: Vec<String>,
+
+    /// What to do with a glob pattern that matches no files
+    glob_nomatch: NoMatch,
+
+    /// The shell's variables and positional parameters
+    vars: Variables,
+
+    /// The functions that have been defined, by name
+    functions: BTreeMap<String, Rc<Command>>,
+
+    /// The exit status of the last command, `$?`
+    status: i32,
+
+    /// Set while the commands that are running are being cut short
+    unwinding: Option<Unwind>,
+
+    /// How many loops are running in the current function (or outside of
+    /// any function), which `break` and `continue` can act on
+    loop_depth: usize,
+
+    /// How many conditions are running, like the condition of an `if` or
+    /// the left side of `&&`, where a failing command doesn't trigger
+    /// `set -e`
+    condition_depth: usize,
+
+    /// The options set with `set`
+    options: Options,
+
+    /// Whether commands are being typed in at a terminal, rather than read
+    /// from a pipe or a file
+    interactive: bool,
+
+    /// The commands set with `trap`, by the name of the signal (without
+    /// `SIG`) or of `EXIT`, `ERR` or `DEBUG`. An empty command ignores the
+    /// signal
+    traps: BTreeMap<String, String>,
+
+    /// Set while a trap is running, so that it doesn't set off more traps
+    in_trap: bool,
+
+    /// The processes started for process substitutions that haven't been
+    /// waited for yet, each with the shell's end of its pipe
+    substitutions: Vec<(libc::pid_t, fs::File)>,
 }
 
-// Methods for shell
-impl Shell {
+/// The reason the commands that are running are being cut short
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unwind {
+    /// `return` was run, which ends the function that is running
+    Return,
+
+    /// `break n` was run, which ends the `n` innermost loops
+    Break(usize),
+
+    /// A command failed with `set -e` on, which ends the shell with the
+    /// command's status
+    Exit(i32),
+
+    /// `continue n` was run, which ends the `n - 1` innermost loops and
+    /// starts the next time around the one outside them
+    Continue(usize),
+}
+
+/// The options turned on and off with `set`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Options {
+    /// `-e`: the shell exits when a command fails, unless it is part of a
+    /// condition
+    pub errexit: bool,
+
+    /// `-u`: expanding a variable that isn't set is an error
+    pub nounset: bool,
+
+    /// `-x`: each command is printed to standard error, after expansion and
+    /// with `$PS4` in front, before it runs
+    pub xtrace: bool,
+
+    /// `-o pipefail`: a pipeline fails with the status of the last command
+    /// in it that failed, rather than the status of its last command
+    pub pipefail: bool,
+
+    /// `-C`: `>` won't overwrite a file that exists, only `>|` will
+    pub noclobber: bool,
+
+    /// `-n`: commands are read and checked, but not run. An interactive
+    /// shell ignores this, since nothing could turn it off again
+    pub noexec: bool,
+}
+
+impl Options {
+    /// The options with their names for `set -o`, and their letters for
+    /// `set -x` and the like if they have one
+    pub const NAMES: &'static [(&'static str, Option<char>)] = &[
+        ("errexit", Some('e')),
+        ("nounset", Some('u')),
+        ("xtrace", Some('x')),
+        ("pipefail", None),
+        ("noclobber", Some('C')),
+        ("noexec", Some('n')),
+    ];
+
+    /// Returns whether the option with the given name is on
+    pub fn is_set(&self, name: &str) -> Option<bool> {
+        let mut options = *self;
+        options.by_name(name).map(|flag| *flag)
+    }
+
+    /// Returns the option with the given name, for changing it
+    pub fn by_name(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "nounset" => Some(&mut self.nounset),
+            "xtrace" => Some(&mut self.xtrace),
+            "pipefail" => Some(&mut self.pipefail),
+            "noclobber" => Some(&mut self.noclobber),
+            "noexec" => Some(&mut self.noexec),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Shell {
     /// Default constructor for the shell. Will initialize with default
     /// values and return a Shell struct. Default initial working directory
     /// is `/`
     fn default() -> Shell {
         Shell {
             working_dir: PathBuf::from("/"),
+            previous_dir: None,
+            dir_stack: Vec::new(),
+            frecency: FrecencyDb::default(),
             input_history: Vec::new(),
             output_count: 0,
             paths: Vec::new(),
+            config_path: None,
+            config_mtime: None,
+            auto_reload: false,
+            prompt: SHELL_PROMPT.to_string(),
+            show_dir_stack: false,
+            config_env: Vec::new(),
+            aliases: BTreeMap::new(),
+            config_aliases: Vec::new(),
+            glob_nomatch: NoMatch::default(),
+            vars: Variables::default(),
+            functions: BTreeMap::new(),
+            status: 0,
+            unwinding: None,
+            loop_depth: 0,
+            condition_depth: 0,
+            interactive: false,
+            traps: BTreeMap::new(),
+            in_trap: false,
+            options: Options::default(),
+            substitutions: Vec::new(),
         }
     }
+}
 
+// Methods for shell
+impl Shell {
     /// Change the shell's working directory. Will return an a boolean
     /// indicating whether the working directory was successfully changed
-    /// or not
-    pub fn change_working_dir(&mut self, wd: &str) -> bool {
-        let path_obj = PathBuf::from(wd);
+    /// or not. `PWD` and `OLDPWD` are exported so child processes see them,
+    /// and the visit is recorded for `j`
+    pub fn change_working_dir(&mut self, wd: &Path) -> bool {
+        let path_obj = wd.to_path_buf();
 
         // Only change wd if it exists
         if env::set_current_dir(&path_obj).is_ok() {
-            self.working_dir = path_obj;
+            let previous = mem::replace(&mut self.working_dir, path_obj);
+            env::set_var("PWD", &self.working_dir);
+            env::set_var("OLDPWD", &previous);
+            self.previous_dir = Some(previous);
+
+            // The visit is only remembered in memory until the shell exits,
+            // so `cd` in a loop doesn't rewrite the database every time
+            self.frecency.visit(&self.working_dir, frecency::now());
             true
         } else {
             false
         }
     }
 
-    /// Returns a string representation of the shell's current working directory
-    pub fn get_cwd(&self) -> &str {
-        self.working_dir.to_str().unwrap()
+    /// Returns the shell's current working directory
+    pub fn working_dir(&self) -> &Path {
+        &self.working_dir
+    }
+
+    /// Returns the working directory from before the last change of
+    /// directory, if there was one
+    pub fn previous_dir(&self) -> Option<&Path> {
+        self.previous_dir.as_deref()
+    }
+
+    /// Returns the shell's working directory with all symlinks resolved
+    pub fn physical_dir(&self) -> PathBuf {
+        self.working_dir.canonicalize().unwrap_or_else(|_| self.working_dir.clone())
+    }
+
+    /// Returns the directory stack, most recently pushed first. The working
+    /// directory isn't included
+    pub fn dir_stack(&self) -> &[PathBuf] {
+        &self.dir_stack
     }
 
+    /// Replaces the directory stack
+    pub fn set_dir_stack(&mut self, stack: Vec<PathBuf>) {
+        self.dir_stack = stack;
+    }
 
-    /// Searches for and detects whether the binary exists, searching the paths
-    /// that were loaded from the config file
-    pub fn find_bin(&self, bin_name: &str) -> bool {
-        let mut bin_found = false;
-        let bin_name = bin_name.trim();
+    /// Returns the database of visited directories
+    pub fn frecency(&self) -> &FrecencyDb {
+        &self.frecency
+    }
 
+    /// Loads the database of visited directories from a file, which further
+    /// visits will be saved to
+    pub fn load_frecency(&mut self, file: PathBuf) -> Result<(), io::Error> {
+        self.frecency = FrecencyDb::load(file)?;
+        Ok(())
+    }
+
+    /// Saves the database of visited directories to its file
+    pub fn save_frecency(&self) -> Result<(), io::Error> {
+        self.frecency.save()
+    }
+
+    /// Drops a directory from the database of visited directories
+    pub fn forget_dir(&mut self, dir: &Path) {
+        self.frecency.remove(dir);
+    }
+
+    /// Returns a string representation of the shell's current working
+    /// directory, for display. Bytes that aren't valid UTF-8 are replaced
+    pub fn get_cwd(&self) -> Cow<'_, str> {
+        self.working_dir.to_string_lossy()
+    }
+
+    /// Searches for a binary in the paths that were loaded from the config
+    /// file, returning its full path if it exists
+    pub fn find_bin(&self, bin_name: &OsStr) -> Option<PathBuf> {
         // Don't accept blank names
-        if bin_name.is_empty() {
-            return false;
+        if bin_name.is_empty() || bin_name.as_bytes().iter().all(u8::is_ascii_whitespace) {
+            return None;
         }
 
         // Searching every path in the paths vector for the binary
-        for path in self.paths.clone() {
-            let full_bin_path_str = path + "/" + bin_name;
-            bin_found = PathBuf::from(full_bin_path_str).exists();
+        self.paths.iter().map(|path| path.join(bin_name)).find(|path| path.exists())
+    }
+
+    /// Returns the names of the executables in the paths that were loaded
+    /// from the config file. Names that aren't valid UTF-8 are left out
+    pub fn executables(&self) -> Vec<String> {
+        let mut names = Vec::new();
+
+        for path in &self.paths {
+            let entries = match fs::read_dir(path) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let executable = fs::metadata(entry.path())
+                    .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+                    .unwrap_or(false);
 
-            if bin_found {
-                return true;
+                if let (true, Ok(name)) = (executable, entry.file_name().into_string()) {
+                    names.push(name);
+                }
             }
         }
-        bin_found
+        names
     }
 
-    // Set include paths for shell using path strings
-    pub fn set_paths(&mut self, paths: Vec<String>) {
+    // Set include paths for shell
+    pub fn set_paths(&mut self, paths: Vec<PathBuf>) {
         self.paths = paths;
     }
 
-    /// Set include paths from a config file. Pass in a string with the path to
-    /// the config file. Assumes the string is valid
-    pub fn load_paths(&mut self, config_path: Option<&str>, default_paths: &Vec<String>) {
-        self.paths = parser::config::load_paths_from_config(config_path, &default_paths);
+    /// Returns the symbol that is shown in front of the input space
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    /// Returns whether the prompt should show the depth of the directory stack
+    pub fn show_dir_stack(&self) -> bool {
+        self.show_dir_stack
+    }
+
+    /// Returns the shell's aliases, sorted by name
+    pub fn aliases(&self) -> &BTreeMap<String, String> {
+        &self.aliases
+    }
+
+    /// Defines an alias, replacing any existing alias with the same name
+    pub fn set_alias(&mut self, name: &str, value: &str) {
+        self.aliases.insert(name.to_string(), value.to_string());
+    }
+
+    /// Removes an alias. Returns whether the alias existed
+    pub fn remove_alias(&mut self, name: &str) -> bool {
+        self.aliases.remove(name).is_some()
+    }
+
+    /// Removes every alias
+    pub fn clear_aliases(&mut self) {
+        self.aliases.clear();
+    }
+
+    /// Returns the shell's variables
+    pub fn vars(&self) -> &Variables {
+        &self.vars
+    }
+
+    /// Returns the shell's variables, for changing them
+    pub fn vars_mut(&mut self) -> &mut Variables {
+        &mut self.vars
+    }
+
+    /// Returns the body of a function, if one is defined with the name
+    pub fn function(&self, name: &str) -> Option<Rc<Command>> {
+        self.functions.get(name).cloned()
+    }
+
+    /// Returns the names of the functions that are defined
+    pub fn function_names(&self) -> Vec<String> {
+        self.functions.keys().cloned().collect()
+    }
+
+    /// Defines a function, replacing any existing function with the same
+    /// name
+    pub fn define_function(&mut self, name: &str, body: Rc<Command>) {
+        self.functions.insert(name.to_string(), body);
+    }
+
+    /// Returns the exit status of the last command
+    pub fn status(&self) -> i32 {
+        self.status
+    }
+
+    /// Records the exit status of the last command
+    pub fn set_status(&mut self, status: i32) {
+        self.status = status;
+    }
+
+    /// Returns why the running commands are being cut short, if they are
+    pub fn unwinding(&self) -> Option<Unwind> {
+        self.unwinding
+    }
+
+    /// Starts or stops cutting the running commands short
+    pub fn set_unwinding(&mut self, unwinding: Option<Unwind>) {
+        self.unwinding = unwinding;
+    }
+
+    /// Returns how many loops `break` and `continue` can act on
+    pub fn loop_depth(&self) -> usize {
+        self.loop_depth
+    }
+
+    /// Sets how many loops `break` and `continue` can act on
+    pub fn set_loop_depth(&mut self, depth: usize) {
+        self.loop_depth = depth;
+    }
+
+    /// Returns how many conditions are running, which `set -e` ignores
+    /// failures in
+    pub fn condition_depth(&self) -> usize {
+        self.condition_depth
+    }
+
+    /// Sets how many conditions are running
+    pub fn set_condition_depth(&mut self, depth: usize) {
+        self.condition_depth = depth;
+    }
+
+    /// Returns the options set with `set`
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Returns the options set with `set`, for changing them
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    /// Returns whether commands are being typed in at a terminal
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
+    /// Sets whether commands are being typed in at a terminal
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+    }
+
+    /// Returns the commands set with `trap`, by the signal they are for
+    pub fn traps(&self) -> &BTreeMap<String, String> {
+        &self.traps
+    }
+
+    /// Returns the command set with `trap` for a signal
+    pub fn trap(&self, name: &str) -> Option<&str> {
+        self.traps.get(name).map(String::as_str)
+    }
+
+    /// Sets the command to run for a signal, or goes back to what the
+    /// signal normally does if it is `None`
+    pub fn set_trap(&mut self, name: &str, command: Option<String>) {
+        match command {
+            Some(command) => self.traps.insert(name.to_string(), command),
+            None => self.traps.remove(name),
+        };
+    }
+
+    /// Returns whether a trap is running
+    pub fn in_trap(&self) -> bool {
+        self.in_trap
+    }
+
+    /// Sets whether a trap is running
+    pub fn set_in_trap(&mut self, in_trap: bool) {
+        self.in_trap = in_trap;
+    }
+
+    /// Keeps track of a process started for a process substitution, and
+    /// the shell's end of its pipe, until the command using it is done
+    pub fn add_substitution(&mut self, pid: libc::pid_t, pipe: fs::File) {
+        self.substitutions.push((pid, pipe));
+    }
+
+    /// Returns how many process substitutions are still open. Passing this
+    /// to `finish_substitutions` later finishes only the ones started since
+    pub fn substitution_count(&self) -> usize {
+        self.substitutions.len()
+    }
+
+    /// Closes the pipes of the process substitutions after the first
+    /// `from`, and waits for their processes to exit
+    pub fn finish_substitutions(&mut self, from: usize) {
+        if from >= self.substitutions.len() {
+            return;
+        }
+
+        for (pid, pipe) in self.substitutions.split_off(from) {
+            drop(pipe);
+            let _ = sys::wait(pid);
+        }
+    }
+
+    /// Closes the pipes of all the process substitutions without waiting
+    /// for them. A forked copy of the shell does this, since the processes
+    /// aren't its own
+    pub fn forget_substitutions(&mut self) {
+        self.substitutions.clear();
+    }
+
+    /// Returns what should happen to a glob pattern that matches no files
+    pub fn glob_nomatch(&self) -> NoMatch {
+        self.glob_nomatch
+    }
+
+    /// Load the shell's settings from a config file. `config_path` is the
+    /// config file that was asked for, if any; otherwise the default
+    /// locations are searched. If no config can be loaded, the error is
+    /// reported and the default paths are used instead
+    pub fn load_config(&mut self, config_path: Option<&Path>, default_paths: &[String]) {
+        let loaded = parser::config::resolve_config_path(config_path, default_paths)
+            .and_then(|path| {
+                self.config_path = Some(path);
+                self.reload_config()
+            });
+
+        if let Err(e) = loaded {
+            print_err(&format!("ensh: could not load config: {}", e));
+            self.set_paths(default_paths.iter().map(PathBuf::from).collect());
+        }
+    }
+
+    /// Re-reads the config file the shell was started with, replacing the
+    /// paths, prompt, alias and environment settings. If the file can't be read or
+    /// parsed, the current settings are kept and the error is returned
+    pub fn reload_config(&mut self) -> Result<(), io::Error> {
+        let path = match self.config_path {
+            Some(ref path) => path.clone(),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no config file was loaded")),
+        };
+
+        // Record the modification time before parsing so a broken file is
+        // only reported once rather than at every prompt
+        self.config_mtime = modified_time(&path);
+        let config = parser::config::load_config(&path)?;
+        self.apply_config(config);
+        Ok(())
+    }
+
+    /// Returns whether the config should be reloaded because automatic
+    /// reloading is on and the file was modified since it was last loaded
+    pub fn config_changed(&self) -> bool {
+        match self.config_path {
+            Some(ref path) if self.auto_reload => modified_time(path) != self.config_mtime,
+            _ => false,
+        }
     }
+
+    // Replaces the shell's settings with the ones from a freshly loaded
+    // config
+    fn apply_config(&mut self, config: Config) {
+        self.set_paths(config.paths);
+        self.prompt = config.prompt.unwrap_or_else(|| SHELL_PROMPT.to_string());
+        self.show_dir_stack = config.show_dir_stack;
+        self.auto_reload = config.auto_reload;
+        self.glob_nomatch = config.glob_nomatch;
+
+        // Variables that were dropped from the config shouldn't linger in
+        // the environment
+        for name in &self.config_env {
+            if !config.env.iter().any(|(new_name, _)| new_name == name) {
+                env::remove_var(name);
+            }
+        }
+
+        self.config_env = config.env.iter().map(|(name, _)| name.clone()).collect();
+        for (name, value) in config.env {
+            env::set_var(name, value);
+        }
+
+        // Same goes for aliases, though ones defined at the prompt are kept
+        for name in &self.config_aliases {
+            if !config.aliases.iter().any(|(new_name, _)| new_name == name) {
+                self.aliases.remove(name);
+            }
+        }
+
+        self.config_aliases = config.aliases.iter().map(|(name, _)| name.clone()).collect();
+        self.aliases.extend(config.aliases);
+    }
+}
+
+// Returns the modification time of a file, if it can be determined
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
 }
 
 // unit tests
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::env::temp_dir;
-    use super::parser::config::create_default_config;
 
     // Returns a vector with some sample default paths for the purposes of
     // testing
@@ -127,10 +654,15 @@ mod tests {
         ]
     }
 
+    // The sample default paths as the shell stores them
+    fn default_path_bufs() -> Vec<PathBuf> {
+        create_default_path_vec().iter().map(PathBuf::from).collect()
+    }
+
     // Check if shell initializes properly
     #[test]
     fn test_default_shell_init() {
-        let shell = Shell::default();
+        let _shell = Shell::default();
     }
 
     // Tests if shell can load any paths from the config file
@@ -141,11 +673,9 @@ mod tests {
         let mut shell = Shell::default();
         // vector with paths we want to write
         let def_paths_vec = create_default_path_vec();
-        let fp_str = tmp_dir.as_path().to_str().unwrap();
-
-        parser::config::create_default_config(&fp_str, &def_paths_vec);
-        shell.load_paths(Some(&fp_str), &def_paths_vec);
-        assert!(shell.paths.len() > 0);
+        parser::config::create_default_config(&tmp_dir, &def_paths_vec).unwrap();
+        shell.load_config(Some(&tmp_dir), &def_paths_vec);
+        assert!(!shell.paths.is_empty());
     }
 
     // Tests if the shell can look for a file, in this case a binary,
@@ -154,10 +684,19 @@ mod tests {
     #[test]
     fn test_search_bin() {
         let mut shell = Shell::default();
-        let def_paths_vec = create_default_path_vec();
-        shell.paths = def_paths_vec;
-        assert!(!shell.find_bin(""));
-        assert!(shell.find_bin("cat"));
+        shell.set_paths(default_path_bufs());
+        assert_eq!(shell.find_bin(OsStr::new("")), None);
+        assert!(shell.find_bin(OsStr::new("cat")).unwrap().ends_with("cat"));
+    }
+
+    // Tests that the executables in the shell's paths are listed. Assumes
+    // cat is available in one of the paths
+    #[test]
+    fn test_executables() {
+        let mut shell = Shell::default();
+        assert!(shell.executables().is_empty());
+        shell.set_paths(default_path_bufs());
+        assert!(shell.executables().iter().any(|name| name == "cat"));
     }
 
     // tests to see if the shell can properly change working directories
@@ -165,16 +704,66 @@ mod tests {
     fn test_cwd() {
         // Shell test setup
         let mut shell = Shell::default();
-        let def_paths_vec = create_default_path_vec();
-        shell.paths = def_paths_vec;
-        assert!(shell.change_working_dir("/"));
+        shell.set_paths(default_path_bufs());
+        assert!(shell.change_working_dir(Path::new("/")));
     }
 
     // Tests that the working directory of the shell is properly output as a string
     #[test]
     fn test_print_wd() {
         let mut shell = Shell::default();
-        shell.change_working_dir("/");
+        shell.change_working_dir(Path::new("/"));
         assert_eq!(shell.get_cwd(), "/");
     }
+
+    // Tests that a working directory that isn't valid UTF-8 can be entered
+    // and is only made lossy for display
+    #[test]
+    fn test_non_utf8_wd() {
+        let dir = env::temp_dir().join(OsStr::from_bytes(b"ensh_wd_\xfe"));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut shell = Shell::default();
+        assert!(shell.change_working_dir(&dir));
+        assert_eq!(shell.working_dir(), dir.as_path());
+        assert!(shell.get_cwd().ends_with("ensh_wd_\u{fffd}"));
+    }
+
+    // Tests that reloading picks up changes to the config file and that
+    // environment variables removed from the config are unset
+    #[test]
+    fn test_reload_config() {
+        let mut tmp_path = env::temp_dir();
+        tmp_path.push("config_reload");
+        let fp_str = tmp_path.as_path();
+        let mut shell = Shell::default();
+
+        fs::write(fp_str, "[prompt]\nsymbol = $\n[env]\nENSH_RELOAD_TEST = 1\n[aliases]\nll = ls -l\n").unwrap();
+        shell.load_config(Some(fp_str), &create_default_path_vec());
+        shell.set_alias("la", "ls -a");
+        assert_eq!(shell.prompt(), "$");
+        assert_eq!(env::var("ENSH_RELOAD_TEST").unwrap(), "1");
+        assert!(shell.aliases().contains_key("ll"));
+
+        fs::write(fp_str, "[paths]\n/\n").unwrap();
+        shell.reload_config().unwrap();
+        assert_eq!(shell.prompt(), SHELL_PROMPT);
+        assert_eq!(shell.paths, vec![PathBuf::from("/")]);
+        assert!(env::var("ENSH_RELOAD_TEST").is_err());
+        assert!(!shell.aliases().contains_key("ll"));
+        assert!(shell.aliases().contains_key("la"));
+
+        // A broken config leaves the current settings alone
+        fs::write(fp_str, "[nonsense]\n").unwrap();
+        assert!(shell.reload_config().is_err());
+        assert_eq!(shell.paths, vec![PathBuf::from("/")]);
+    }
+
+    // Tests that a shell without a config file has nothing to reload
+    #[test]
+    fn test_reload_without_config() {
+        let mut shell = Shell::default();
+        assert!(shell.reload_config().is_err());
+        assert!(!shell.config_changed());
+    }
 }
@@ -0,0 +1,101 @@
+//! suggest.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Summary
+//! The suggest module finds the commands a mistyped command name was most
+//! likely meant to be. Names are compared by edit distance, counting a swap
+//! of two neighbouring characters as a single edit since that's the most
+//! common typo (`gti` for `git`)
+
+use std::cmp;
+
+// The most suggestions that are shown for one command
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Returns the number of single character insertions, deletions,
+/// substitutions and swaps of adjacent characters it takes to turn `a`
+/// into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // dist[i][j] is the distance between the first i characters of a and
+    // the first j characters of b
+    let mut dist = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in dist.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in dist[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut best = cmp::min(dist[i - 1][j] + 1, dist[i][j - 1] + 1);
+            best = cmp::min(best, dist[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = cmp::min(best, dist[i - 2][j - 2] + 1);
+            }
+            dist[i][j] = best;
+        }
+    }
+    dist[a.len()][b.len()]
+}
+
+/// Returns the candidates that are close enough to `name` to be worth
+/// suggesting, closest first. Longer names are allowed more edits
+pub fn suggestions<I, S>(name: &str, candidates: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let max_distance = cmp::max(1, name.chars().count() / 3);
+    let mut close: Vec<(usize, String)> = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let candidate = candidate.as_ref();
+            let distance = edit_distance(name, candidate);
+
+            if distance > 0 && distance <= max_distance {
+                Some((distance, candidate.to_string()))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    close.sort();
+    close.dedup();
+    close.into_iter().take(MAX_SUGGESTIONS).map(|(_, candidate)| candidate).collect()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests the edit distance, including swapped characters
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("git", "git"), 0);
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(edit_distance("sl", "ls"), 1);
+        assert_eq!(edit_distance("car", "cat"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    // Tests that only close candidates are suggested, closest first and
+    // without duplicates
+    #[test]
+    fn test_suggestions() {
+        let candidates = ["git", "gitk", "grep", "git", "gti"];
+        assert_eq!(suggestions("gti", candidates), vec!["git"]);
+        assert_eq!(suggestions("gitt", candidates), vec!["git", "gitk"]);
+        assert!(suggestions("zzz", candidates).is_empty());
+    }
+}
@@ -0,0 +1,396 @@
+//! sys.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Summary
+//! The sys module wraps the few libc calls the shell needs that the standard
+//! library doesn't provide. Everything unsafe is kept in here, behind safe
+//! functions
+
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use std::{mem, ptr};
+
+use libc;
+
+/// Looks up the home directory of a user in the passwd database. Returns
+/// `None` if there is no such user
+pub fn user_home(name: &OsStr) -> Option<PathBuf> {
+    let c_name = CString::new(name.as_bytes()).ok()?;
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+
+    loop {
+        let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result: *mut libc::passwd = ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(c_name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result)
+        };
+
+        // The entry didn't fit in the buffer, try again with a bigger one
+        if rc == libc::ERANGE {
+            let len = buf.len() * 2;
+            buf.resize(len, 0);
+            continue;
+        }
+
+        if rc != 0 || result.is_null() || pwd.pw_dir.is_null() {
+            return None;
+        }
+
+        let dir = unsafe { CStr::from_ptr(pwd.pw_dir) };
+        return Some(PathBuf::from(OsStr::from_bytes(dir.to_bytes())));
+    }
+}
+
+/// Checks whether the user running the shell may access a file in the
+/// given way: `libc::R_OK`, `libc::W_OK` or `libc::X_OK`
+pub fn access(path: &Path, mode: libc::c_int) -> bool {
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => unsafe { libc::access(c_path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+/// Returns whether a file descriptor refers to a terminal
+pub fn isatty(fd: libc::c_int) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+/// Reads a single byte from a file descriptor, without reading ahead, so
+/// whatever comes after it is left for the next reader. Returns `None` at
+/// the end of the input
+pub fn read_byte(fd: RawFd) -> io::Result<Option<u8>> {
+    let mut byte = 0u8;
+
+    loop {
+        match unsafe { libc::read(fd, &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+            -1 => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+            0 => return Ok(None),
+            _ => return Ok(Some(byte)),
+        }
+    }
+}
+
+/// Waits up to `timeout` for a file descriptor to have input (or to reach
+/// its end). Returns whether it does
+pub fn wait_readable(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    let mut poll = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    let millis = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+
+    match unsafe { libc::poll(&mut poll, 1, millis) } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Turns the echoing of typed characters on or off for a terminal
+pub fn set_echo(fd: RawFd, echo: bool) -> io::Result<()> {
+    unsafe {
+        let mut termios: libc::termios = mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if echo {
+            termios.c_lflag |= libc::ECHO;
+        } else {
+            termios.c_lflag &= !libc::ECHO;
+        }
+        if libc::tcsetattr(fd, libc::TCSAFLUSH, &termios) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether `s` contains a match for the POSIX extended regular
+/// expression `regex`, or `None` if the expression isn't valid
+pub fn regex_match(regex: &OsStr, s: &OsStr) -> Option<bool> {
+    let c_regex = CString::new(regex.as_bytes()).ok()?;
+    let c_s = CString::new(s.as_bytes()).ok()?;
+
+    unsafe {
+        let mut compiled: libc::regex_t = mem::zeroed();
+        if libc::regcomp(&mut compiled, c_regex.as_ptr(), libc::REG_EXTENDED | libc::REG_NOSUB) != 0 {
+            return None;
+        }
+        let rc = libc::regexec(&compiled, c_s.as_ptr(), 0, ptr::null_mut(), 0);
+        libc::regfree(&mut compiled);
+        Some(rc == 0)
+    }
+}
+
+/// Which side of a `fork` the caller is on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fork {
+    /// The new process
+    Child,
+
+    /// The original process, with the new process's ID
+    Parent(libc::pid_t),
+}
+
+/// Starts a copy of the shell process. The copy has only the thread that
+/// called this, so it should do no more than run commands and exit with
+/// `exit_child`
+pub fn fork() -> io::Result<Fork> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(Fork::Child),
+        pid => Ok(Fork::Parent(pid)),
+    }
+}
+
+/// Creates a pipe, returning its read end and its write end. Neither end is
+/// inherited by programs the shell runs, unless it is moved onto one of
+/// their standard streams with `dup2`
+pub fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { Ok((File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]))) }
+}
+
+/// Makes `to` refer to the same file as `from`, like `2>&1` does
+pub fn dup2(from: RawFd, to: RawFd) -> io::Result<()> {
+    if unsafe { libc::dup2(from, to) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Duplicates a file descriptor onto the lowest free one at or above
+/// `min`. The copy isn't inherited by programs the shell runs
+pub fn dup_above(fd: RawFd, min: RawFd) -> io::Result<RawFd> {
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, min) } {
+        -1 => Err(io::Error::last_os_error()),
+        copy => Ok(copy),
+    }
+}
+
+/// Duplicates a file descriptor onto the lowest free one at or above
+/// `min`, like `dup_above`, except that programs the shell runs inherit
+/// the copy
+pub fn dup_inheritable(fd: RawFd, min: RawFd) -> io::Result<RawFd> {
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD, min) } {
+        -1 => Err(io::Error::last_os_error()),
+        copy => Ok(copy),
+    }
+}
+
+/// Closes a file descriptor
+pub fn close(fd: RawFd) -> io::Result<()> {
+    match unsafe { libc::close(fd) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Waits for a child process to finish. Returns its exit status, which
+/// for a process killed by a signal is 128 plus the signal number
+pub fn wait(pid: libc::pid_t) -> io::Result<i32> {
+    let mut status = 0;
+
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } != -1 {
+            break;
+        }
+
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+
+    if libc::WIFSIGNALED(status) {
+        Ok(128 + libc::WTERMSIG(status))
+    } else {
+        Ok(libc::WEXITSTATUS(status))
+    }
+}
+
+/// Ends a process started by `fork`, without running any of the cleanup the
+/// original process will still do itself
+pub fn exit_child(status: i32) -> ! {
+    unsafe { libc::_exit(status) }
+}
+
+/// The signals `trap` knows, by their names without `SIG`
+pub const SIGNALS: &[(&str, libc::c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+// The signals that have arrived since they were last taken, one bit each
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// What happens when a signal arrives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Disposition {
+    /// Whatever the signal normally does
+    Default,
+
+    /// Nothing
+    Ignore,
+
+    /// The signal is recorded, to be picked up with `take_signals`
+    Catch,
+}
+
+// Records a signal that arrived. Only async-signal-safe things can happen
+// in here
+extern "C" fn record_signal(signal: libc::c_int) {
+    PENDING.fetch_or(1 << signal, Ordering::SeqCst);
+}
+
+/// Changes what happens when a signal arrives. A caught signal doesn't
+/// interrupt reads and waits, and programs the shell runs go back to the
+/// default for it
+pub fn set_signal(signal: libc::c_int, disposition: Disposition) -> io::Result<()> {
+    let handler = match disposition {
+        Disposition::Default => libc::SIG_DFL,
+        Disposition::Ignore => libc::SIG_IGN,
+        Disposition::Catch => record_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+    };
+
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handler;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(signal, &action, ptr::null_mut()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the caught signals that have arrived since the last call, in
+/// order of their numbers
+pub fn take_signals() -> Vec<libc::c_int> {
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    (1..64).filter(|signal| pending & (1 << signal) != 0).collect()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that a user's home can be found. Assumes there is a root user
+    #[test]
+    fn test_user_home() {
+        assert!(user_home(OsStr::new("root")).is_some());
+        assert_eq!(user_home(OsStr::new("no-such-user-for-ensh")), None);
+        assert_eq!(user_home(OsStr::new("bad\0name")), None);
+    }
+
+    // Tests that access checks use the file's permissions
+    #[test]
+    fn test_access() {
+        assert!(access(Path::new("/"), libc::R_OK));
+        assert!(access(Path::new("/bin/sh"), libc::X_OK));
+        assert!(!access(Path::new("/no/such/file/for/ensh"), libc::R_OK));
+    }
+
+    // Tests extended regular expressions
+    #[test]
+    fn test_regex_match() {
+        assert_eq!(regex_match(OsStr::new("^(ab|c)+$"), OsStr::new("abcab")), Some(true));
+        assert_eq!(regex_match(OsStr::new("b"), OsStr::new("abc")), Some(true));
+        assert_eq!(regex_match(OsStr::new("^b"), OsStr::new("abc")), Some(false));
+        assert_eq!(regex_match(OsStr::new("a("), OsStr::new("a")), None);
+    }
+
+    // Tests that a forked child's output comes through a pipe and its
+    // status is collected
+    #[test]
+    fn test_fork_pipe() {
+        use std::io::{Read, Write};
+        use std::os::unix::io::AsRawFd;
+
+        let (mut reader, mut writer) = pipe().unwrap();
+        match fork().unwrap() {
+            Fork::Child => {
+                drop(reader);
+                let _ = writer.write_all(b"from child");
+                exit_child(3);
+            }
+            Fork::Parent(pid) => {
+                drop(writer);
+                let mut output = String::new();
+                reader.read_to_string(&mut output).unwrap();
+                assert_eq!(output, "from child");
+                assert_eq!(wait(pid).unwrap(), 3);
+                assert!(dup2(reader.as_raw_fd(), -1).is_err());
+
+                let copy = dup_above(reader.as_raw_fd(), 20).unwrap();
+                assert!(copy >= 20);
+                assert!(close(copy).is_ok());
+                assert!(close(copy).is_err());
+            }
+        }
+    }
+
+    // Tests that caught signals are recorded and ignored ones aren't. This
+    // runs in a child, so the other tests' threads don't take the signals
+    #[test]
+    fn test_signals() {
+        match fork().unwrap() {
+            Fork::Child => {
+                let mut ok = set_signal(libc::SIGUSR2, Disposition::Catch).is_ok();
+                unsafe { libc::raise(libc::SIGUSR2) };
+                ok &= take_signals() == vec![libc::SIGUSR2] && take_signals().is_empty();
+
+                ok &= set_signal(libc::SIGUSR2, Disposition::Ignore).is_ok();
+                unsafe { libc::raise(libc::SIGUSR2) };
+                ok &= take_signals().is_empty() && set_signal(libc::SIGKILL, Disposition::Catch).is_err();
+                exit_child(if ok { 0 } else { 1 });
+            }
+            Fork::Parent(pid) => assert_eq!(wait(pid).unwrap(), 0),
+        }
+    }
+}
@@ -0,0 +1,412 @@
+//! vars.rs
+//!
+//! # Author
+//! Afnan Enayet
+//!
+//! # Summary
+//! The vars module holds the shell's variables and positional parameters.
+//! Every function call gets a frame with its own positional parameters and
+//! the variables it declared with `local`. Looking a variable up goes
+//! through the frames from the innermost call out, then the shell's global
+//! variables, and then the environment. Setting a variable that came from
+//! the environment updates the environment, so child processes see it.
+//!
+//! A variable is a string, an indexed array or an associative array. Used
+//! as a string, an array stands for its element 0
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsString;
+
+/// The shell's variables
+#[derive(Debug, Default)]
+pub struct Variables {
+    /// Variables that aren't exported to the environment
+    globals: HashMap<String, Value>,
+
+    /// The positional parameters the shell itself was given
+    args: Vec<OsString>,
+
+    /// One frame for each function call that is running, innermost last
+    frames: Vec<Frame>,
+}
+
+// The state of a function call
+#[derive(Debug, Default)]
+struct Frame {
+    // The arguments the function was called with
+    args: Vec<OsString>,
+
+    // The variables declared with `local`. A local that was declared
+    // without a value is unset, but still hides any outer variable
+    locals: HashMap<String, Option<Value>>,
+}
+
+/// The value of a variable
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// A plain string
+    Scalar(OsString),
+
+    /// An array indexed by numbers, which can have gaps
+    Indexed(BTreeMap<usize, OsString>),
+
+    /// An array indexed by strings, declared with `declare -A`
+    Assoc(BTreeMap<String, OsString>),
+}
+
+/// Picks out an element of an array
+#[derive(Debug, Clone, PartialEq)]
+pub enum Key {
+    /// An element of an indexed array. A negative index counts back from
+    /// the end
+    Index(i64),
+
+    /// An element of an associative array
+    Name(String),
+}
+
+impl Value {
+    /// Returns the element with the given key, if it is set. A string is
+    /// the element 0 of an indexed array
+    pub fn element(&self, key: &Key) -> Option<&OsString> {
+        match (self, key) {
+            (Value::Scalar(s), Key::Index(0)) | (Value::Scalar(s), Key::Index(-1)) => Some(s),
+            (Value::Indexed(items), &Key::Index(index)) => resolve(items, index).and_then(|i| items.get(&i)),
+            (Value::Assoc(items), Key::Name(name)) => items.get(name),
+            _ => None,
+        }
+    }
+
+    /// Returns the values of the elements, in order
+    pub fn values(&self) -> Vec<OsString> {
+        match self {
+            Value::Scalar(s) => vec![s.clone()],
+            Value::Indexed(items) => items.values().cloned().collect(),
+            Value::Assoc(items) => items.values().cloned().collect(),
+        }
+    }
+
+    /// Returns the keys of the elements, in order
+    pub fn keys(&self) -> Vec<OsString> {
+        match self {
+            Value::Scalar(_) => vec![OsString::from("0")],
+            Value::Indexed(items) => items.keys().map(|i| OsString::from(i.to_string())).collect(),
+            Value::Assoc(items) => items.keys().map(OsString::from).collect(),
+        }
+    }
+
+    // Returns the string the value stands for when it isn't used as an
+    // array
+    fn scalar(&self) -> Option<&OsString> {
+        match self {
+            Value::Assoc(_) => self.element(&Key::Name("0".to_string())),
+            _ => self.element(&Key::Index(0)),
+        }
+    }
+
+    // Sets an element, turning a string into an indexed array first if it
+    // needs to be one. Returns false if the key doesn't fit the value
+    fn set_element(&mut self, key: Key, value: OsString) -> bool {
+        if let Value::Scalar(s) = self {
+            let s = std::mem::take(s);
+            *self = Value::Indexed(BTreeMap::new());
+            self.set_element(Key::Index(0), s);
+        }
+
+        match (self, key) {
+            (Value::Indexed(items), Key::Index(index)) => match resolve(items, index) {
+                Some(i) => {
+                    items.insert(i, value);
+                    true
+                }
+                None => false,
+            },
+            (Value::Assoc(items), Key::Name(name)) => {
+                items.insert(name, value);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Turns an index into an indexed array, which might count back from the
+// end, into a position in it. Returns `None` if it is before the start
+fn resolve(items: &BTreeMap<usize, OsString>, index: i64) -> Option<usize> {
+    if index >= 0 {
+        return usize::try_from(index).ok();
+    }
+
+    let len = items.keys().next_back().map_or(0, |last| last + 1);
+    usize::try_from(len as i64 + index).ok()
+}
+
+impl Variables {
+    /// Returns the value of a variable as a string, if it is set. An array
+    /// gives its element 0
+    pub fn get(&self, name: &str) -> Option<OsString> {
+        self.value(name).and_then(|value| value.scalar().cloned())
+    }
+
+    /// Returns the whole value of a variable, if it is set
+    pub fn value(&self, name: &str) -> Option<Value> {
+        for frame in self.frames.iter().rev() {
+            if let Some(value) = frame.locals.get(name) {
+                return value.clone();
+            }
+        }
+
+        match self.globals.get(name) {
+            Some(value) => Some(value.clone()),
+            None => env::var_os(name).map(Value::Scalar),
+        }
+    }
+
+    /// Sets a variable to a string. The innermost local with the name is
+    /// set if there is one, otherwise the global variable is. Setting an
+    /// array sets its element 0
+    pub fn set(&mut self, name: &str, value: OsString) {
+        self.update(name, |old| match old {
+            Some(Value::Assoc(mut items)) => {
+                items.insert("0".to_string(), value);
+                Value::Assoc(items)
+            }
+            Some(Value::Indexed(mut items)) => {
+                items.insert(0, value);
+                Value::Indexed(items)
+            }
+            _ => Value::Scalar(value),
+        });
+    }
+
+    /// Replaces the whole value of a variable
+    pub fn set_value(&mut self, name: &str, value: Value) {
+        self.update(name, |_| value);
+    }
+
+    /// Sets an element of an array, making the variable an indexed array
+    /// if it isn't an array yet. Returns false if the key doesn't fit the
+    /// array, like a string key for an indexed array or a negative index
+    /// before its start
+    pub fn set_element(&mut self, name: &str, key: Key, value: OsString) -> bool {
+        let mut set = false;
+
+        self.update(name, |old| {
+            let mut array = old.unwrap_or_else(|| Value::Indexed(BTreeMap::new()));
+            set = array.set_element(key, value);
+            array
+        });
+        set
+    }
+
+    /// Unsets a variable. An unset local still hides any outer variable
+    /// with the name until its function returns
+    pub fn unset(&mut self, name: &str) {
+        for frame in self.frames.iter_mut().rev() {
+            if let Some(local) = frame.locals.get_mut(name) {
+                *local = None;
+                return;
+            }
+        }
+
+        self.globals.remove(name);
+        env::remove_var(name);
+    }
+
+    /// Returns whether a variable is an associative array
+    pub fn is_assoc(&self, name: &str) -> bool {
+        matches!(self.value(name), Some(Value::Assoc(_)))
+    }
+
+    // Changes a variable where it is kept: the innermost local with the
+    // name, or else the global variable. A string variable that came from
+    // the environment stays there, an array can't be exported so it moves
+    // to the globals
+    fn update<F: FnOnce(Option<Value>) -> Value>(&mut self, name: &str, f: F) {
+        for frame in self.frames.iter_mut().rev() {
+            if let Some(local) = frame.locals.get_mut(name) {
+                *local = Some(f(local.take()));
+                return;
+            }
+        }
+
+        if self.globals.contains_key(name) {
+            let value = f(self.globals.remove(name));
+            self.globals.insert(name.to_string(), value);
+            return;
+        }
+
+        match f(env::var_os(name).map(Value::Scalar)) {
+            Value::Scalar(value) if env::var_os(name).is_some() => env::set_var(name, value),
+            value => {
+                env::remove_var(name);
+                self.globals.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    /// Declares a variable local to the function that is running. Returns
+    /// false if no function is running
+    pub fn declare_local(&mut self, name: &str, value: Option<Value>) -> bool {
+        match self.frames.last_mut() {
+            Some(frame) => {
+                frame.locals.insert(name.to_string(), value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Starts a function call with the given positional parameters
+    pub fn push_frame(&mut self, args: Vec<OsString>) {
+        self.frames.push(Frame {
+            args,
+            locals: HashMap::new(),
+        });
+    }
+
+    /// Ends the innermost function call, dropping its locals
+    pub fn pop_frame(&mut self) {
+        self.frames.pop();
+    }
+
+    /// Returns whether a function is running
+    pub fn in_function(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    /// Returns how many function calls are running
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Replaces the positional parameters of the innermost function call,
+    /// or the shell's own if no function is running
+    pub fn set_args(&mut self, args: Vec<OsString>) {
+        match self.frames.last_mut() {
+            Some(frame) => frame.args = args,
+            None => self.args = args,
+        }
+    }
+
+    /// Returns the positional parameters of the innermost function call, or
+    /// the shell's own if no function is running
+    pub fn args(&self) -> &[OsString] {
+        match self.frames.last() {
+            Some(frame) => &frame.args,
+            None => &self.args,
+        }
+    }
+}
+
+/// Returns whether a string can be used as a variable name: a letter or
+/// underscore followed by letters, digits and underscores
+pub fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+/// Splits `name[subscript]` into the name and the subscript. Returns
+/// `None` if the text isn't a variable name followed by a subscript
+pub fn split_subscript(text: &str) -> Option<(&str, &str)> {
+    let open = text.find('[')?;
+    let (name, subscript) = (&text[..open], &text[open + 1..]);
+
+    if !valid_name(name) || !subscript.ends_with(']') {
+        return None;
+    }
+    Some((name, &subscript[..subscript.len() - 1]))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that locals hide globals until their function returns
+    #[test]
+    fn test_local_scope() {
+        let mut vars = Variables::default();
+        vars.set("ensh_vars_x", OsString::from("global"));
+        assert!(!vars.declare_local("ensh_vars_x", None));
+
+        vars.push_frame(vec![OsString::from("a")]);
+        assert!(vars.declare_local("ensh_vars_x", None));
+        assert_eq!(vars.get("ensh_vars_x"), None);
+        vars.set("ensh_vars_x", OsString::from("local"));
+
+        // Without a local of its own, an inner call sees the caller's
+        vars.push_frame(Vec::new());
+        assert_eq!(vars.get("ensh_vars_x"), Some(OsString::from("local")));
+        assert!(vars.args().is_empty());
+        vars.pop_frame();
+
+        assert_eq!(vars.args(), [OsString::from("a")]);
+        vars.pop_frame();
+        assert_eq!(vars.get("ensh_vars_x"), Some(OsString::from("global")));
+        assert!(!vars.in_function());
+    }
+
+    // Tests that variables from the environment are updated in place
+    #[test]
+    fn test_set_exported() {
+        env::set_var("ENSH_VARS_EXPORTED", "1");
+        let mut vars = Variables::default();
+        vars.set("ENSH_VARS_EXPORTED", OsString::from("2"));
+        assert_eq!(env::var("ENSH_VARS_EXPORTED").unwrap(), "2");
+        assert!(vars.globals.is_empty());
+    }
+
+    // Tests setting elements of arrays, and that an array used as a string
+    // is its element 0
+    #[test]
+    fn test_arrays() {
+        let mut vars = Variables::default();
+        assert!(vars.set_element("ensh_vars_a", Key::Index(2), OsString::from("c")));
+        assert!(vars.set_element("ensh_vars_a", Key::Index(-1), OsString::from("C")));
+        assert!(!vars.set_element("ensh_vars_a", Key::Index(-4), OsString::from("x")));
+        assert!(!vars.set_element("ensh_vars_a", Key::Name("k".to_string()), OsString::from("x")));
+        assert_eq!(vars.get("ensh_vars_a"), None);
+        vars.set("ensh_vars_a", OsString::from("a"));
+
+        let value = vars.value("ensh_vars_a").unwrap();
+        assert_eq!(value.values(), vec![OsString::from("a"), OsString::from("C")]);
+        assert_eq!(value.keys(), vec![OsString::from("0"), OsString::from("2")]);
+        assert_eq!(value.element(&Key::Index(-3)), Some(&OsString::from("a")));
+
+        vars.set("ensh_vars_s", OsString::from("s"));
+        assert!(vars.set_element("ensh_vars_s", Key::Index(1), OsString::from("t")));
+        assert_eq!(vars.value("ensh_vars_s").unwrap().values(), vec![OsString::from("s"), OsString::from("t")]);
+
+        vars.set_value("ensh_vars_m", Value::Assoc(BTreeMap::new()));
+        assert!(vars.is_assoc("ensh_vars_m"));
+        assert!(vars.set_element("ensh_vars_m", Key::Name("k".to_string()), OsString::from("v")));
+        assert!(!vars.set_element("ensh_vars_m", Key::Index(0), OsString::from("v")));
+    }
+
+    // Tests splitting an array element into its name and subscript
+    #[test]
+    fn test_split_subscript() {
+        assert_eq!(split_subscript("a[1]"), Some(("a", "1")));
+        assert_eq!(split_subscript("a[b[0]]"), Some(("a", "b[0]")));
+        assert_eq!(split_subscript("a"), None);
+        assert_eq!(split_subscript("1[0]"), None);
+        assert_eq!(split_subscript("a[0"), None);
+    }
+
+    // Tests which variable names are accepted
+    #[test]
+    fn test_valid_name() {
+        assert!(valid_name("_x1"));
+        assert!(valid_name("HOME"));
+        assert!(!valid_name("1x"));
+        assert!(!valid_name("a-b"));
+        assert!(!valid_name(""));
+    }
+}